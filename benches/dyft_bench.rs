@@ -12,6 +12,7 @@ use criterion::Criterion;
 use criterion::PlotConfiguration;
 use criterion::Throughput;
 use master::benchmarks::IndexBenchmarkBuildSetup;
+use master::benchmarks::IndexBenchmarkQueryInstance;
 use master::benchmarks::IndexBenchmarkQuerySetup;
 use master::benchmarks::BACKWARD_DATASET;
 use master::benchmarks::BACKWARD_PERCENTILES_GRID;
@@ -24,18 +25,10 @@ use master::benchmarks::PORTO_PERCENTILES_GRID;
 use master::benchmarks::PORTO_QUERYSET;
 use master::config::IndexConfig;
 use master::config::MartConfig;
-use master::dyft::DyFT;
 use master::dyft::DyftIndex;
-use master::dyft::VCodeArray;
-use master::dyft::VCodeTools;
+use master::index::SimilarityIndex;
 use master::lsh::Linear64;
-use master::lsh::TrajectoryLsh;
-use master::point::Distance;
-use master::trajectory::TrajectoryDataset;
-use rand::Fill;
-use std::path::Path;
 use std::time::Duration;
-use get_size::GetSize;
 
 mod build {
     use master::benchmarks::BACKWARD_DATASET_FULL;
@@ -49,22 +42,7 @@ mod build {
         M: Measurement,
     {
         b.iter(|| {
-            let mut index = DyftIndex::<Linear64>::new(
-                black_box(&instance.config),
-                black_box(instance.max_len),
-            );
-            let vcodes = index.hash_dataset(black_box(
-                &instance.dataset.trajectories()[..MAX_BENCHMARK_INDEX_SIZE.min(n)],
-            ));
-            index.build(
-                black_box(&vcodes),
-                black_box(
-                    MAX_BENCHMARK_INDEX_SIZE
-                        .min(vcodes.size())
-                        .try_into()
-                        .unwrap(),
-                ),
-            );
+            instance.build_index::<DyftIndex<Linear64>>(black_box(MAX_BENCHMARK_INDEX_SIZE.min(n)))
         });
     }
 
@@ -179,86 +157,20 @@ mod build {
 mod query {
     use super::*;
 
-    struct DyFTQueryInstance<'a, H>
-    where
-        H: TrajectoryLsh,
-        [H::Hash]: Fill,
-        [(); H::Hash::N_DIM]:,
-    {
-        index: DyftIndex<'a, H>,
-        vcodes: VCodeArray<H::Hash>,
-        #[allow(dead_code)]
-        dataset: TrajectoryDataset<'a>,
-        queryset: TrajectoryDataset<'a>,
-        #[allow(dead_code)]
-        distance: Option<Distance>,
-    }
-
-    fn prepare_dyft_query_instance<'a, H>(
-        config: IndexConfig<MartConfig>,
-        dataset: impl AsRef<Path>,
-        queryset: impl AsRef<Path>,
-    ) -> DyFTQueryInstance<'a, H>
-    where
-        H: TrajectoryLsh + GetSize,
-        H::Hash: GetSize,
-        [H::Hash]: Fill,
-        [(); H::Hash::N_DIM]:,
-    {
-        let IndexBenchmarkQuerySetup {
-            build:
-                IndexBenchmarkBuildSetup {
-                    config,
-                    dataset,
-                    max_len: max_len_data,
-                },
-            queryset,
-            max_len: max_len_query,
-        } = IndexBenchmarkQuerySetup::<MartConfig>::load_query_benchmark_inputs(
-            config, dataset, queryset,
-        );
-        let distance = config.index_params().distance;
-        let max_len = max_len_data.max(max_len_query);
-        let mut index = DyftIndex::<H>::new(&config, max_len);
-        let vcodes = index.hash_dataset(dataset.trajectories());
-        index.build(
-            &vcodes,
-            MAX_BENCHMARK_INDEX_SIZE
-                .min(vcodes.size())
-                .try_into()
-                .unwrap(),
-        );
-
-        DyFTQueryInstance {
-            index,
-            vcodes,
-            dataset,
-            queryset,
-            distance,
-        }
-    }
-
-    fn dyft_query_no_verification<'a, M, H>(
+    fn dyft_query_no_verification<'a, M, I>(
         c: &'a mut Criterion<M>,
-        instance: &DyFTQueryInstance<'a, H>,
+        instance: &IndexBenchmarkQueryInstance<'a, I>,
         id: &str,
     ) -> &'a mut Criterion<M>
     where
         M: Measurement + 'static,
-        H: TrajectoryLsh + GetSize,
-        H::Hash: GetSize,
-        [(); H::Hash::N_DIM]:,
-        [H::Hash]: Fill,
+        I: SimilarityIndex,
     {
         c.bench_function(id, |b: &mut Bencher<'_, M>| {
             b.iter(|| {
-                let qvcodes = instance
+                let _ = instance
                     .index
-                    .hash_queryset(&instance.queryset.trajectories());
-                let _ = instance.index.trie_query_collect::<Vec<(usize, usize)>>(
-                    black_box(&instance.vcodes),
-                    black_box(&qvcodes),
-                );
+                    .query(black_box(instance.queryset.trajectories()));
             })
         })
     }
@@ -267,7 +179,10 @@ mod query {
         ($fun:ident, $bench_fn:ident, $name:expr, $config:expr, $dataset:expr, $queryset:expr) => {
             pub fn $fun(c: &mut Criterion) {
                 let instance =
-                    prepare_dyft_query_instance::<Linear64>($config, $dataset, $queryset);
+                    IndexBenchmarkQuerySetup::<MartConfig>::load_query_benchmark_inputs(
+                        $config, $dataset, $queryset,
+                    )
+                    .into_query_instance::<DyftIndex<Linear64>>();
                 $bench_fn(c, &instance, $name);
             }
         };
@@ -12,26 +12,21 @@ use criterion::Criterion;
 use criterion::PlotConfiguration;
 use criterion::Throughput;
 use master::benchmarks::IndexBenchmarkBuildSetup;
+use master::benchmarks::IndexBenchmarkQueryInstance;
 use master::benchmarks::IndexBenchmarkQuerySetup;
 use master::benchmarks::BACKWARD_DATASET;
 use master::benchmarks::BACKWARD_DATASET_FULL;
 use master::benchmarks::BACKWARD_PERCENTILES_GRID;
 use master::benchmarks::BACKWARD_QUERYSET;
-use master::benchmarks::MAX_BENCHMARK_INDEX_SIZE;
 use master::benchmarks::N_SAMPLES;
 use master::benchmarks::PORTO_DATASET;
 use master::benchmarks::PORTO_PERCENTILES_GRID;
 use master::benchmarks::PORTO_QUERYSET;
 use master::config::FreshConfig;
 use master::config::IndexConfig;
-use master::dyft::VCodeTools;
 use master::fresh::Fresh;
+use master::index::SimilarityIndex;
 use master::lsh::Linear64;
-use master::lsh::TrajectoryLsh;
-use master::point::Distance;
-use master::trajectory::TrajectoryDataset;
-use rand::Fill;
-use std::path::Path;
 use std::time::Duration;
 
 mod build {
@@ -170,97 +165,34 @@ mod build {
 mod query {
     use super::*;
 
-    struct FreshQueryInstance<'a, H>
-    where
-        H: TrajectoryLsh,
-        [H::Hash]: Fill,
-        [(); H::Hash::N_DIM]:,
-    {
-        index: Fresh<H, true>,
-        dataset: TrajectoryDataset<'a>,
-        queryset: TrajectoryDataset<'a>,
-        verify_fraction_distance: Option<(Distance, Distance)>,
-    }
-
-    fn prepare_fresh_query_instance<'a, H>(
-        config: IndexConfig<FreshConfig>,
-        dataset: impl AsRef<Path>,
-        queryset: impl AsRef<Path>,
-    ) -> FreshQueryInstance<'a, H>
-    where
-        H: TrajectoryLsh,
-        [H::Hash]: Fill,
-        [(); H::Hash::N_DIM]:,
-    {
-        let IndexBenchmarkQuerySetup {
-            build:
-                IndexBenchmarkBuildSetup {
-                    config,
-                    dataset,
-                    max_len: max_len_data,
-                },
-            queryset,
-            max_len: max_len_query,
-        } = IndexBenchmarkQuerySetup::<FreshConfig>::load_query_benchmark_inputs(
-            config, dataset, queryset,
-        );
-        let distance = config.index_params().distance;
-        let max_len = max_len_data.max(max_len_query);
-        let mut index = Fresh::new(&config, max_len, dataset.len());
-        index.build(&dataset.trajectories()[..MAX_BENCHMARK_INDEX_SIZE.min(dataset.len())]);
-        let index = index.fix_table();
-        let verify_fraction_distance = match config.index_params().verify_fraction {
-            Some(verify_fraction) => match distance {
-                Some(distance) => Some((verify_fraction, distance)),
-                None => None,
-            },
-            None => None,
-        };
-        FreshQueryInstance {
-            index,
-            dataset,
-            queryset,
-            verify_fraction_distance,
-        }
-    }
-
-    fn fresh_query_with_verification<'a, M, H>(
+    fn fresh_query_with_verification<'a, M, I>(
         b: &mut Bencher<'a, M>,
-        instance: &FreshQueryInstance<'a, H>,
+        instance: &IndexBenchmarkQueryInstance<'a, I>,
     ) where
         M: Measurement,
-        H: TrajectoryLsh,
-        [(); H::Hash::N_DIM]:,
-        [H::Hash]: Fill,
+        I: SimilarityIndex,
     {
-        let (verify_fraction, distance) = instance.verify_fraction_distance.unwrap();
+        let distance = instance.distance.unwrap();
         b.iter(|| {
-            instance
-                .index
-                .query_with_verification_fraction::<Vec<(usize, usize)>>(
-                    black_box(instance.dataset.trajectories()),
-                    black_box(instance.queryset.trajectories()),
-                    black_box(distance),
-                    black_box(verify_fraction),
-                )
+            instance.index.query_with_verification(
+                black_box(instance.dataset.trajectories()),
+                black_box(instance.queryset.trajectories()),
+                black_box(distance).into(),
+            )
         });
     }
 
-    fn fresh_query_no_verification<'a, M, H>(
+    fn fresh_query_no_verification<'a, M, I>(
         b: &mut Bencher<'a, M>,
-        instance: &FreshQueryInstance<'a, H>,
+        instance: &IndexBenchmarkQueryInstance<'a, I>,
     ) where
         M: Measurement,
-        H: TrajectoryLsh,
-        [(); H::Hash::N_DIM]:,
-        [H::Hash]: Fill,
+        I: SimilarityIndex,
     {
         b.iter(|| {
             instance
                 .index
-                .query_collision_collect::<Vec<(usize, usize)>>(black_box(
-                    &instance.queryset.trajectories(),
-                ))
+                .query(black_box(instance.queryset.trajectories()))
         })
     }
 
@@ -268,7 +200,10 @@ mod query {
         ($fun:ident, $name:expr, $config:expr, $dataset:expr, $queryset:expr) => {
             pub fn $fun(c: &mut Criterion) {
                 let instance =
-                    prepare_fresh_query_instance::<Linear64>($config, $dataset, $queryset);
+                    IndexBenchmarkQuerySetup::<FreshConfig>::load_query_benchmark_inputs(
+                        $config, $dataset, $queryset,
+                    )
+                    .into_query_instance::<Fresh<Linear64, true>>();
                 let n = instance.dataset.len();
                 let plot_config =
                     PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
@@ -295,7 +230,10 @@ mod query {
         ($fun:ident, $name:expr, $config:expr, $dataset:expr, $queryset:expr) => {
             pub fn $fun(c: &mut Criterion) {
                 let instance =
-                    prepare_fresh_query_instance::<Linear64>($config, $dataset, $queryset);
+                    IndexBenchmarkQuerySetup::<FreshConfig>::load_query_benchmark_inputs(
+                        $config, $dataset, $queryset,
+                    )
+                    .into_query_instance::<Fresh<Linear64, true>>();
                 let n = instance.dataset.len();
                 let plot_config =
                     PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
@@ -63,7 +63,7 @@ fn hash<T>(
     concatentations: usize,
     resolution: f64,
     seed: u64,
-) -> Vec<Vec<T>>
+) -> (Vec<Vec<T>>, u64)
 where
     T: VCodeTools,
     [T]: Fill,
@@ -73,12 +73,12 @@ where
     match hash_type {
         HashType::LinearFactorLsh => {
             let lsh = TensoredMultiHash::<LinearFactorLsh<T>>::init(length, concatentations, resolution, max_len, rng);
-            dataset.into_iter().map(|t| lsh.multi_hash(t).collect()).collect()
+            (dataset.into_iter().map(|t| lsh.multi_hash(t).collect()).collect(), lsh.fingerprint())
         }
         HashType::ConstantFactorLsh => {
             let lsh =
                 TensoredMultiHash::<ConstantFactorLsh<T>>::init(length, concatentations, resolution, max_len, rng);
-            dataset.into_iter().map(|t| lsh.multi_hash(t).collect()).collect()
+            (dataset.into_iter().map(|t| lsh.multi_hash(t).collect()).collect(), lsh.fingerprint())
         }
     }
 }
@@ -94,12 +94,13 @@ fn main() -> Result<()> {
 		output,
     } = HashConfig::parse();
     let dataset = master::io::trajectory_dataset(&dataset)?;
-    let hashes = hash::<u64>(hash_type, dataset.trajectories(), length, concatentations, resolution, seed);
+    let (hashes, fingerprint) = hash::<u64>(hash_type, dataset.trajectories(), length, concatentations, resolution, seed);
 
     write_hashes_parquet::<UInt64Type>(
         output,
         hashes.into_iter().map(|hash| hash.into_iter()),
         dataset.ids().into_iter().map(|id| id.value()),
+        Some(fingerprint),
     )?;
 
     Ok(())
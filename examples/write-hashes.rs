@@ -1,3 +1,4 @@
+use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
 use arrow::datatypes::UInt64Type;
@@ -21,6 +22,15 @@ struct WriteHashesCommand {
     seed: Option<u64>,
     n: Option<usize>,
     max_length: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Path to a previously saved hasher, replacing the seeded hasher, delta, l and k must match it (optional)"
+    )]
+    hasher: Option<PathBuf>,
+
+    #[arg(long, help = "Path to save the hasher to (optional)")]
+    save_hasher: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
@@ -31,19 +41,45 @@ fn main() -> Result<()> {
     let data = data.take(Some(n));
     let ids = data.ids().into_iter().map(|id| id.value());
     let trajectories = data.trajectories();
-	let lsh = TensoredMultiHash::<Linear64>::init(
-        args.l,
-        args.k,
-        args.delta,
-        args.max_length.unwrap_or(data.max_trajectory_length()),
-        &mut StdRng::seed_from_u64(seed),
-    );
+    let lsh = match &args.hasher {
+        Some(path) => {
+            if args.seed.is_some() || args.max_length.is_some() {
+                bail!("seed and max_length are taken from the hasher, drop them with --hasher");
+            }
+            let lsh = TensoredMultiHash::<Linear64>::load(path)?;
+            if (lsh.resolution(), lsh.hash_length(), lsh.concatenations())
+                != (Some(args.delta), args.l, args.k)
+            {
+                bail!(
+                    "the hasher has delta {:?}, l {}, k {}, but got delta {}, l {}, k {}",
+                    lsh.resolution(),
+                    lsh.hash_length(),
+                    lsh.concatenations(),
+                    args.delta,
+                    args.l,
+                    args.k
+                );
+            }
+            lsh
+        }
+        None => TensoredMultiHash::<Linear64>::init(
+            args.l,
+            args.k,
+            args.delta,
+            args.max_length.unwrap_or(data.max_trajectory_length()),
+            &mut StdRng::seed_from_u64(seed),
+        ),
+    };
+    if let Some(path) = &args.save_hasher {
+        lsh.save(path)?;
+    }
     let start = std::time::SystemTime::now();
     let hashes = trajectories.into_iter().map(|t| lsh.multi_hash(&t));
     write_hashes_parquet::<UInt64Type>(
         args.output,
         hashes.into_iter(),
         ids,
+        Some(lsh.fingerprint()),
     )?;
     println!("Wrote hashes in {:?}", start.elapsed()?);
     Ok(())
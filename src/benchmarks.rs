@@ -1,8 +1,17 @@
 use crate::config::IndexConfig;
+use crate::config::LshConfig;
+use crate::index::SimilarityIndex;
 use crate::io::trajectory_dataset;
 use crate::io::trajectory_queryset;
+use crate::lsh::MultiTrajectoryLsh;
+use crate::lsh::TensoredMultiHash;
+use crate::lsh::TrajectoryLsh;
+use crate::point::Distance;
 use crate::trajectory::TrajectoryDataset;
 use clap::Args;
+use rand::rngs::StdRng;
+use rand::Fill;
+use rand::SeedableRng;
 use serde::Deserialize;
 use std::path::Path;
 
@@ -40,6 +49,15 @@ where
     pub max_len: usize,
 }
 
+/// An index built for a query benchmark, with the distance threshold of its
+/// verifying queries if the config enables them
+pub struct IndexBenchmarkQueryInstance<'a, I> {
+    pub index: I,
+    pub dataset: TrajectoryDataset<'a>,
+    pub queryset: TrajectoryDataset<'a>,
+    pub distance: Option<Distance>,
+}
+
 impl<'a, C> IndexBenchmarkBuildSetup<'a, C>
 where
     C: Args + for <'de> Deserialize<'de>,
@@ -57,6 +75,38 @@ where
             max_len,
         }
     }
+
+    /// Builds index `I` over the first `n` trajectories of the dataset
+    pub fn build_index<I>(&self, n: usize) -> I
+    where
+        I: SimilarityIndex<Params = C>,
+        [<I::Lsh as TrajectoryLsh>::Hash]: Fill,
+    {
+        self.build_index_with_max_len(n, self.max_len)
+    }
+
+    /// Like `build_index`, with a hasher generated from the seed in the
+    /// config for trajectories of up to `max_len` points
+    fn build_index_with_max_len<I>(&self, n: usize, max_len: usize) -> I
+    where
+        I: SimilarityIndex<Params = C>,
+        [<I::Lsh as TrajectoryLsh>::Hash]: Fill,
+    {
+        let &LshConfig {
+            k,
+            l,
+            resolution,
+            seed,
+        } = self.config.lsh_params();
+        let hasher = TensoredMultiHash::<I::Lsh>::init(
+            l,
+            k,
+            resolution,
+            max_len,
+            &mut StdRng::seed_from_u64(seed),
+        );
+        I::build(&self.config, Some(hasher), &self.dataset.trajectories()[..n])
+    }
 }
 
 
@@ -79,4 +129,22 @@ where
             max_len,
         }
     }
+
+    /// Builds index `I` over at most `MAX_BENCHMARK_INDEX_SIZE` trajectories
+    /// of the dataset, hashing trajectories as long as the longest data or
+    /// query trajectory
+    pub fn into_query_instance<I>(self) -> IndexBenchmarkQueryInstance<'a, I>
+    where
+        I: SimilarityIndex<Params = C>,
+        [<I::Lsh as TrajectoryLsh>::Hash]: Fill,
+    {
+        let n = MAX_BENCHMARK_INDEX_SIZE.min(self.build.dataset.len());
+        let max_len = self.build.max_len.max(self.max_len);
+        IndexBenchmarkQueryInstance {
+            index: self.build.build_index_with_max_len(n, max_len),
+            distance: I::verification_distance(&self.build.config),
+            dataset: self.build.dataset,
+            queryset: self.queryset,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,330 @@
+use crate::config::IndexConfig;
+use crate::index::SimilarityIndex;
+use crate::index::VerificationResult;
+use crate::io;
+use crate::lsh::TensoredMultiHash;
+use crate::params::IndexCommandWithArgs;
+use crate::point::Distance;
+use crate::point::QueryDistance;
+use crate::subtrajectory::SubtrajectoryIndex;
+use crate::subtrajectory::Windows;
+use crate::trajectory::TrajectoryDataset;
+use crate::util::map_to_trajectory_ids;
+use crate::util::MasterStats;
+use anyhow::bail;
+use anyhow::Result;
+use clap::Args;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::to_writer;
+
+/// Runs the build, query or join command of the binary of index `I`, and
+/// writes its stats as json to stdout
+pub fn run_index_command<I>(name: &str) -> Result<()>
+where
+    I: SimilarityIndex + Sync,
+    I::Params: Args + Clone + Serialize + for<'de> Deserialize<'de>,
+    TensoredMultiHash<I::Lsh>: for<'de> Deserialize<'de>,
+{
+    sysinfo::set_open_files_limit(0);
+
+    let config = IndexCommandWithArgs::<I::Params>::parse_from_args_or_file()?;
+    if let Some(threads) = config.threads() {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    let index_config = config.index_config();
+    let metric = I::verification_metric(index_config);
+    if config.frechet_distance() && !metric.is_frechet() {
+        let name = metric.to_possible_value().expect("metrics are not skipped");
+        bail!("--frechet-distance needs a fréchet metric, not {}", name.get_name());
+    }
+    let datapath = config.data_path();
+    let querypath = config.query_path();
+    let mut stats = MasterStats::<I::Stats, IndexConfig<I::Params>>::new(
+        name,
+        index_config,
+        datapath,
+        querypath,
+    );
+
+    let start = std::time::SystemTime::now();
+    let dataset = io::trajectory_dataset(datapath)?.take(config.n());
+    let data_trajectories = dataset.trajectories();
+    let data_ids = dataset.ids();
+    let queryset = querypath.map(io::trajectory_queryset).transpose()?;
+    stats.data_load_time(start.elapsed()?);
+
+    // instantiate the index, either from a previously saved one or by building it
+    let hasher = config
+        .hasher_path()
+        .map(TensoredMultiHash::<I::Lsh>::load)
+        .transpose()?;
+    if let Some(windows) = config.windows() {
+        return run_subtrajectory_command::<I>(
+            &config,
+            &windows,
+            hasher,
+            stats,
+            &dataset,
+            queryset.as_ref(),
+        );
+    }
+    let index = if let Some(loadpath) = config.load_index_path() {
+        I::load(index_config, hasher, data_trajectories, loadpath)?
+    } else {
+        let start = std::time::SystemTime::now();
+        if let Some(samples) = config.memory_samples() {
+            let index = I::build_with_memory_samples(
+                index_config,
+                hasher,
+                data_trajectories,
+                &mut stats,
+                &samples,
+            );
+            stats.index_build_time(start.elapsed()?);
+            index
+        } else if config.threads().is_some() {
+            let index = I::par_build(index_config, hasher, data_trajectories);
+            stats.index_parallel_build_time(start.elapsed()?);
+            index
+        } else {
+            let index = I::build(index_config, hasher, data_trajectories);
+            stats.index_build_time(start.elapsed()?);
+            index
+        }
+    };
+    if let Some(savepath) = config.save_index_path() {
+        index.save(savepath)?;
+    }
+    stats.index_stats(index.stats());
+    let radii = queryset.as_ref().and_then(|queryset| queryset.radii());
+    if I::verification_distance(index_config).is_some() || radii.is_some() {
+        stats.verification_metric(metric);
+    }
+
+    // query the index, or join the dataset with itself
+    let parallel = config.threads().is_some();
+    if let Some(queryset) = queryset {
+        let query_trajectories = queryset.trajectories();
+        let query_ids = queryset.ids();
+        stats.index_query_size(queryset.len());
+        let start = std::time::SystemTime::now();
+        let distance = query_distance(I::verification_distance(index_config), &queryset);
+        if config.stream() {
+            return run_streaming_query(&config, &index, stats, &dataset, &queryset, distance);
+        }
+        let (results, verification) = match distance {
+            Some(distance) => {
+                let verification = match parallel {
+                    true => index.par_query_with_verification(
+                        data_trajectories,
+                        query_trajectories,
+                        distance,
+                    ),
+                    false => index.query_with_verification(
+                        data_trajectories,
+                        query_trajectories,
+                        distance,
+                    ),
+                };
+                verification.record_stats(stats.index_stats_mut_unchecked());
+                (verification.pairs().to_vec(), Some(verification))
+            }
+            None if parallel => (index.par_query(query_trajectories), None),
+            None => (index.query(query_trajectories), None),
+        };
+        stats.index_query_time(start.elapsed()?);
+        let result_pairs: Vec<_> =
+            map_to_trajectory_ids(results.iter().copied(), data_ids, query_ids).collect();
+        stats.candidates(result_pairs.iter().cloned());
+        if let Some(outpath) = config.output_path() {
+            let records = index.query_results(query_trajectories, &results, verification.as_ref());
+            let records = records
+                .into_iter()
+                .map(|record| match config.frechet_distance() {
+                    true => {
+                        record.with_frechet_distance(data_trajectories, query_trajectories, metric)
+                    }
+                    false => record,
+                });
+            io::write_query_result_records(
+                outpath,
+                map_to_trajectory_ids(records, data_ids, query_ids),
+            )?;
+        }
+        to_writer(std::io::stdout(), &stats)?;
+    } else if config.is_join() {
+        stats.index_query_size(dataset.len());
+        let start = std::time::SystemTime::now();
+        let results = match I::verification_distance(index_config) {
+            Some(distance) => {
+                let verification = index.self_join_with_verification(data_trajectories, distance);
+                verification.record_stats(stats.index_stats_mut_unchecked());
+                verification.pairs().to_vec()
+            }
+            None => index.self_join(),
+        };
+        stats.index_query_time(start.elapsed()?);
+        let result_pairs: Vec<_> = map_to_trajectory_ids(results, data_ids, data_ids).collect();
+        stats.candidates(result_pairs.iter().cloned());
+        if let Some(outpath) = config.output_path() {
+            io::write_query_results(outpath, result_pairs)?;
+        }
+        to_writer(std::io::stdout(), &stats)?;
+    } else {
+        to_writer(std::io::stdout(), &stats)?;
+    }
+
+    Ok(())
+}
+
+/// The thresholds of the verifying queries: the radius column of the
+/// queryset if it has one, which verifies the queries even without a
+/// configured distance, and the configured distance otherwise
+fn query_distance<'a>(
+    configured: Option<Distance>,
+    queryset: &'a TrajectoryDataset<'a>,
+) -> Option<QueryDistance<'a>> {
+    match queryset.radii() {
+        Some(radii) => Some(QueryDistance::PerQuery(radii)),
+        None => configured.map(QueryDistance::Global),
+    }
+}
+
+/// Runs the query command one query at a time, writing the results of each
+/// query as soon as it finishes. Only the number of results of each query is
+/// kept in the stats.
+fn run_streaming_query<'a, I>(
+    config: &IndexCommandWithArgs<I::Params>,
+    index: &I,
+    mut stats: MasterStats<'a, I::Stats, IndexConfig<I::Params>>,
+    dataset: &'a TrajectoryDataset<'a>,
+    queryset: &'a TrajectoryDataset<'a>,
+    distance: Option<QueryDistance<'a>>,
+) -> Result<()>
+where
+    I: SimilarityIndex,
+    I::Params: Args + Clone + Serialize + for<'de> Deserialize<'de>,
+{
+    let (data_trajectories, query_trajectories) = (dataset.trajectories(), queryset.trajectories());
+    let metric = I::verification_metric(config.index_config());
+    // the query groups are lazy, except for the thresholds some indexes
+    // estimate over the whole queryset before the first query
+    let start = std::time::SystemTime::now();
+    let groups = index.query_groups(data_trajectories, query_trajectories, distance);
+    let mut query_time = start.elapsed()?;
+    let groups = groups
+        .map(|mut group| {
+            if config.frechet_distance() {
+                group.results = Vec::from_iter(group.results.into_iter().map(|record| {
+                    record.with_frechet_distance(data_trajectories, query_trajectories, metric)
+                }));
+            }
+            group
+        })
+        .filter_map(|group| group.map_to_trajectory_ids(dataset.ids(), queryset.ids()))
+        .map(|group| {
+            query_time += group.time;
+            if let Some(verification) = &group.verification {
+                verification.record_stats(stats.index_stats_mut_unchecked());
+            }
+            stats.query_result_count(group.query, group.results.len());
+            group
+        });
+    match config.output_path() {
+        Some(outpath) if outpath.extension().is_some_and(|ext| ext == "jsonl") => {
+            io::write_query_groups_jsonl(outpath, groups)?;
+        }
+        Some(outpath) => {
+            io::write_query_groups_parquet(outpath, groups)?;
+        }
+        None => groups.for_each(drop),
+    }
+    stats.index_query_time(query_time);
+    to_writer(std::io::stdout(), &stats)?;
+    Ok(())
+}
+
+/// Runs the build or query command in subtrajectory search mode, with the
+/// index built over the windows of the dataset
+fn run_subtrajectory_command<'a, I>(
+    config: &IndexCommandWithArgs<I::Params>,
+    windows: &Windows,
+    hasher: Option<TensoredMultiHash<I::Lsh>>,
+    mut stats: MasterStats<'a, I::Stats, IndexConfig<I::Params>>,
+    dataset: &'a TrajectoryDataset<'a>,
+    queryset: Option<&'a TrajectoryDataset<'a>>,
+) -> Result<()>
+where
+    I: SimilarityIndex + Sync,
+    I::Params: Args + Clone + Serialize + for<'de> Deserialize<'de>,
+{
+    if config.is_join() {
+        bail!("the join command does not support subtrajectory search");
+    }
+    if config.stream() {
+        bail!("--stream does not support subtrajectory search");
+    }
+    if config.frechet_distance() {
+        bail!("--frechet-distance does not support subtrajectory search");
+    }
+    let index_config = config.index_config();
+    let data_trajectories = dataset.trajectories();
+    let parallel = config.threads().is_some();
+
+    let index = if let Some(loadpath) = config.load_index_path() {
+        SubtrajectoryIndex::<I>::load(index_config, hasher, data_trajectories, windows, loadpath)?
+    } else {
+        let start = std::time::SystemTime::now();
+        if parallel {
+            let index = SubtrajectoryIndex::<I>::par_build(
+                index_config,
+                hasher,
+                data_trajectories,
+                windows,
+            );
+            stats.index_parallel_build_time(start.elapsed()?);
+            index
+        } else {
+            let index =
+                SubtrajectoryIndex::<I>::build(index_config, hasher, data_trajectories, windows);
+            stats.index_build_time(start.elapsed()?);
+            index
+        }
+    };
+    if let Some(savepath) = config.save_index_path() {
+        index.save(savepath)?;
+    }
+    stats.index_stats(index.index().stats());
+
+    if let Some(queryset) = queryset {
+        let query_trajectories = queryset.trajectories();
+        stats.index_query_size(queryset.len());
+        let start = std::time::SystemTime::now();
+        let distance = query_distance(I::verification_distance(index_config), queryset);
+        let matches = match distance {
+            Some(distance) if parallel => {
+                index.par_query_with_verification(data_trajectories, query_trajectories, distance)
+            }
+            Some(distance) => {
+                index.query_with_verification(data_trajectories, query_trajectories, distance)
+            }
+            None => index.query(query_trajectories),
+        };
+        stats.index_query_time(start.elapsed()?);
+        let matches: Vec<_> =
+            map_to_trajectory_ids(matches, dataset.ids(), queryset.ids()).collect();
+        stats.candidates(matches.iter().map(|m| (m.query, m.trajectory)));
+        if let Some(outpath) = config.output_path() {
+            io::write_subtrajectory_matches(outpath, matches)?;
+        }
+        to_writer(std::io::stdout(), &stats)?;
+    } else {
+        to_writer(std::io::stdout(), &stats)?;
+    }
+
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::metric::VerificationMetric;
 use crate::point::Distance;
 use anyhow::Result;
 use clap::Args;
@@ -8,6 +9,7 @@ use std::env::var;
 use std::fs::ReadDir;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub const MASTER_DATA_DIR: &str = "MASTER_DATA_DIR";
 pub const MASTER_QUERY_DIR: &str = "MASTER_QUERY_DIR";
@@ -97,6 +99,10 @@ impl Default for IndexConfig<MartConfig> {
                 radius: 8,
                 errors: 8,
                 distance: None,
+                max_candidates: None,
+                max_verification_millis: None,
+                bbox_filter: false,
+                metric: VerificationMetric::default(),
             },
         }
     }
@@ -109,6 +115,12 @@ impl Default for IndexConfig<FreshConfig> {
             index: FreshConfig {
                 distance: None,
                 verify_fraction: None,
+                compact_fraction: None,
+                max_candidates: None,
+                max_verification_millis: None,
+                bbox_filter: false,
+                probes: None,
+                metric: VerificationMetric::default(),
             },
         }
     }
@@ -171,6 +183,46 @@ impl IndexConfig<MartConfig> {
             ..self
         }
     }
+
+    pub fn with_max_candidates(self, max_candidates: usize) -> Self {
+        IndexConfig {
+            index: MartConfig {
+                max_candidates: Some(max_candidates),
+                ..self.index
+            },
+            ..self
+        }
+    }
+
+    pub fn with_max_verification_millis(self, millis: u64) -> Self {
+        IndexConfig {
+            index: MartConfig {
+                max_verification_millis: Some(millis),
+                ..self.index
+            },
+            ..self
+        }
+    }
+
+    pub fn with_bbox_filter(self) -> Self {
+        IndexConfig {
+            index: MartConfig {
+                bbox_filter: true,
+                ..self.index
+            },
+            ..self
+        }
+    }
+
+    pub fn with_metric(self, metric: VerificationMetric) -> Self {
+        IndexConfig {
+            index: MartConfig {
+                metric,
+                ..self.index
+            },
+            ..self
+        }
+    }
 }
 
 impl<T> IndexConfig<T>
@@ -247,12 +299,44 @@ pub struct MartConfig {
 
     #[arg(
         long,
-        help = "Distance threshold for discrete fréchet distance verification"
+        help = "Distance threshold for the verification metric. A radius column in the queryset replaces it, and turns on verification without it"
     )]
     pub distance: Option<Distance>,
+
+    #[arg(
+        long,
+        help = "Maximum number of candidates verified per query, the closest ones are kept (optional)"
+    )]
+    pub max_candidates: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Maximum time in milliseconds spent verifying the candidates of a query (optional)"
+    )]
+    pub max_verification_millis: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Prune the candidates of verifying queries whose bounding boxes rule out a match"
+    )]
+    #[serde(default)]
+    pub bbox_filter: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Distance used to verify the candidates of verifying queries"
+    )]
+    #[serde(default)]
+    pub metric: VerificationMetric,
 }
 
 impl MartConfig {
+    pub fn query_budget(&self) -> QueryBudget {
+        QueryBudget::new(self.max_candidates, self.max_verification_millis)
+    }
+
     pub fn parse_errors(s: &str) -> Result<u8> {
         let errors = s.parse()?;
         if errors < 17 {
@@ -268,12 +352,74 @@ impl MartConfig {
 pub struct FreshConfig {
     #[arg(
         long,
-        help = "Distance threshold for discrete fréchet distance verification"
+        help = "Distance threshold for the verification metric. A radius column in the queryset replaces it, and turns on verification without it"
     )]
     pub distance: Option<Distance>,
 
     #[arg(long, help = "The fraction of non-zero scored candidates to verify")]
     pub verify_fraction: Option<Distance>,
+
+    #[arg(
+        long,
+        help = "The fraction of deleted entries at which the tables are compacted, if not set, 0.25 is used"
+    )]
+    pub compact_fraction: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Maximum number of candidates verified per query, the closest ones are kept (optional)"
+    )]
+    pub max_candidates: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Maximum time in milliseconds spent verifying the candidates of a query (optional)"
+    )]
+    pub max_verification_millis: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Prune the candidates of verifying queries whose bounding boxes rule out a match"
+    )]
+    #[serde(default)]
+    pub bbox_filter: bool,
+
+    #[arg(
+        long,
+        help = "Number of extra buckets probed in every table for each query (optional)"
+    )]
+    #[serde(default)]
+    pub probes: Option<usize>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "Distance used to verify the candidates of verifying queries"
+    )]
+    #[serde(default)]
+    pub metric: VerificationMetric,
+}
+
+/// Per-query limits of the verifying queries. A query that reaches either
+/// limit returns the candidates it verified so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryBudget {
+    pub max_candidates: Option<usize>,
+    pub max_verification_time: Option<Duration>,
+}
+
+impl QueryBudget {
+    pub fn new(max_candidates: Option<usize>, max_verification_millis: Option<u64>) -> Self {
+        QueryBudget {
+            max_candidates,
+            max_verification_time: max_verification_millis.map(Duration::from_millis),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_candidates.is_none() && self.max_verification_time.is_none()
+    }
 }
 
 pub struct FreshPartialVerification {
@@ -282,6 +428,10 @@ pub struct FreshPartialVerification {
 }
 
 impl FreshConfig {
+    pub fn query_budget(&self) -> QueryBudget {
+        QueryBudget::new(self.max_candidates, self.max_verification_millis)
+    }
+
     pub fn partial_verification(&self) -> Option<FreshPartialVerification> {
         match (self.distance, self.verify_fraction) {
             (Some(distance), Some(verify_fraction))
@@ -307,6 +457,15 @@ impl IndexConfig<FreshConfig> {
             ..self
         }
     }
+    pub fn compact_fraction(self, compact_fraction: f64) -> Self {
+        IndexConfig {
+            index: FreshConfig {
+                compact_fraction: Some(compact_fraction),
+                ..self.index
+            },
+            ..self
+        }
+    }
     pub fn distance(self, distance: Distance) -> Self {
         IndexConfig {
             index: FreshConfig {
@@ -316,4 +475,49 @@ impl IndexConfig<FreshConfig> {
             ..self
         }
     }
+    pub fn max_candidates(self, max_candidates: usize) -> Self {
+        IndexConfig {
+            index: FreshConfig {
+                max_candidates: Some(max_candidates),
+                ..self.index
+            },
+            ..self
+        }
+    }
+    pub fn max_verification_millis(self, millis: u64) -> Self {
+        IndexConfig {
+            index: FreshConfig {
+                max_verification_millis: Some(millis),
+                ..self.index
+            },
+            ..self
+        }
+    }
+    pub fn bbox_filter(self) -> Self {
+        IndexConfig {
+            index: FreshConfig {
+                bbox_filter: true,
+                ..self.index
+            },
+            ..self
+        }
+    }
+    pub fn probes(self, probes: usize) -> Self {
+        IndexConfig {
+            index: FreshConfig {
+                probes: Some(probes),
+                ..self.index
+            },
+            ..self
+        }
+    }
+    pub fn metric(self, metric: VerificationMetric) -> Self {
+        IndexConfig {
+            index: FreshConfig {
+                metric,
+                ..self.index
+            },
+            ..self
+        }
+    }
 }
@@ -39,6 +39,30 @@ impl<T: VCodeTools> VCodeArray<T> {
         self.m_vcodes.extend(T::to_vints(code, self.m_bits));
     }
 
+    /// Appends a vcode of exactly `m_bits` hashes and returns its id
+    pub fn push<I>(&mut self, vcode: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let id = self.size();
+        self.m_vcodes.extend(vcode);
+        assert_eq!(
+            self.m_vcodes.len(),
+            (id + 1) * self.m_bits,
+            "vcode must have exactly {} hashes",
+            self.m_bits
+        );
+        id
+    }
+
+    /// Overwrites the vcode of `id` with `vcode` of exactly `m_bits` hashes
+    pub fn set(&mut self, id: usize, vcode: &[T]) {
+        assert!(id < self.size(), "id must be less than size");
+        assert_eq!(vcode.len(), self.m_bits, "vcode must have exactly {} hashes", self.m_bits);
+        let idx = id * self.m_bits;
+        self.m_vcodes[idx..idx + self.m_bits].copy_from_slice(vcode);
+    }
+
     pub fn access(&self, id: usize) -> &[T] {
         assert!(id < self.size(), "id must be less than size");
         let idx = id * self.m_bits;
@@ -8,6 +8,8 @@ use num_traits::ToPrimitive;
 use num_traits::WrappingAdd;
 use num_traits::WrappingMul;
 use num_traits::Zero;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::iter::Sum;
@@ -43,6 +45,8 @@ pub trait VCodeToolsBase:
     + Hash
     + Send
     + Sync
+    + Serialize
+    + DeserializeOwned
 {
     fn size_of() -> usize {
         std::mem::size_of::<Self>() * std::mem::size_of::<*const Self>()
@@ -8,6 +8,12 @@ pub trait DyFT<T: VCodeTools> {
 
     fn append(&mut self, vcode: &[T], database: &VCodeArray<T>);
 
+    /// Removes `id` from the index, where `database` holds the vcode it was
+    /// appended with. Returns false if `id` is not in the index. A leaf
+    /// emptied by the removal is unlinked from the trie, but its postings
+    /// group is never reused and stays allocated until the index is rebuilt.
+    fn remove(&mut self, id: u32, database: &VCodeArray<T>) -> bool;
+
     fn trie_search(&self, vcode: &[T]) -> impl Iterator<Item = u32>;
 }
 
@@ -35,6 +41,8 @@ pub struct DyFTStats {
     pub(crate) partial_verification_count: usize,
     pub(crate) full_verification_count: usize,
     pub(crate) filtered_verification_count: usize,
+    pub(crate) truncated_queries: usize,
+    pub(crate) pruned_pairs: usize,
 }
 
 impl DyFTStats {
@@ -71,4 +79,5 @@ pub struct InNodeStats {
     pub(crate) k: usize,
     pub(crate) num: usize,
     pub(crate) empty: usize,
+    pub(crate) shrunk: usize,
 }
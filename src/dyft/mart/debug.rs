@@ -15,15 +15,19 @@ impl<'a> DyFTStatistics for MartIndex<'a> {
             partial_verification_count: 0,
             full_verification_count: 0,
             filtered_verification_count: 0,
+            truncated_queries: 0,
+            pruned_pairs: 0,
         }
     }
 
     fn size(&self) -> usize {
-        self.m_ids.try_into().unwrap()
+        (self.m_ids - self.m_removed).try_into().unwrap()
     }
 
     fn leaves(&self) -> usize {
-        self.m_postings_list.size().try_into().unwrap()
+        (self.m_postings_list.size() - self.m_empty_leaves)
+            .try_into()
+            .unwrap()
     }
 
     fn depth(&self) -> usize {
@@ -46,5 +50,7 @@ impl<'a> DyFTStatistics for MartIndex<'a> {
             self.m_array_256.innode_stats(),
         ]
         .into_iter()
+        .zip(self.m_shrink_counts)
+        .map(|(stats, shrunk)| InNodeStats { shrunk, ..stats })
     }
 }
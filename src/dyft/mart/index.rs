@@ -20,7 +20,73 @@ where
     fn append(&mut self, vcode: &[T], database: &VCodeArray<T>) {
         // create a new id
         let new_id = self.inc_id();
+        self.append_id(new_id, vcode, database);
+    }
+
+    fn remove(&mut self, id: u32, database: &VCodeArray<T>) -> bool {
+        if id >= self.m_ids {
+            return false;
+        }
+        let vcode = database.access(id as usize);
+
+        // follow the vcode down to its leaf, recording the edges on the way
+        let mut path = Vec::new();
+        let mut mc = MartCursor::from_next(&self.m_rootptr);
+        for label in self.byte_pack_iter(vcode) {
+            path.push((mc.clone(), label));
+            if !self.perform_find_ptr(&mut mc, label) {
+                return false;
+            }
+            if mc.is_leaf() {
+                break;
+            }
+        }
+        let leaf_pos = mc.nptr().nid();
+        if !mc.is_leaf() || !self.m_postings_list.remove(leaf_pos, id) {
+            return false;
+        }
+        self.m_removed += 1;
+
+        // unlink an emptied leaf, and free or shrink the nodes left sparse by it
+        if self.m_postings_list.group_size(leaf_pos) == 0 {
+            self.m_empty_leaves += 1;
+            while let Some((mut mc, label)) = path.pop() {
+                let is_root = mc.pptr().is_null_ptr();
+                match self.perform_remove_ptr(&mc, label) {
+                    MartRemoveFlags::MartNeededToFree if !is_root => self.perform_free_node(&mc),
+                    MartRemoveFlags::MartNeededToShrink if !is_root => {
+                        self.perform_shrink(&mut mc);
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        true
+    }
+
+    fn trie_search(&self, vcode: &[T]) -> impl Iterator<Item = u32> {
+        self.trie_search_with_errors(vcode, self.m_errors, &mut 0)
+            .into_iter()
+    }
+}
+
+impl<'a> MartIndex<'a> {
+    /// Appends `id` again after it was removed, under its vcode in
+    /// `database`, which may have changed since it was removed
+    pub fn reappend<T>(&mut self, id: u32, database: &VCodeArray<T>)
+    where
+        T: VCodeTools,
+    {
+        assert!(id < self.m_ids, "MartIndex::reappend: {id} was never appended");
+        self.append_id(id, database.access(id as usize), database);
+        self.m_removed -= 1;
+    }
 
+    fn append_id<T>(&mut self, new_id: u32, vcode: &[T], database: &VCodeArray<T>)
+    where
+        T: VCodeTools,
+    {
         // create a mart cursor from the root pointer
         let mut mc = MartCursor::from_next(&self.m_rootptr);
 
@@ -50,7 +116,36 @@ where
         }
     }
 
-    fn trie_search(&self, vcode: &[T]) -> impl Iterator<Item = u32> {
+    /// Whether `id` is stored in the leaf reached by `vcode`, which is false
+    /// once the id has been removed
+    pub fn contains<T>(&self, id: u32, vcode: &[T]) -> bool
+    where
+        T: VCodeTools,
+    {
+        let mut mc = MartCursor::from_next(&self.m_rootptr);
+        for label in self.byte_pack_iter(vcode) {
+            if !self.perform_find_ptr(&mut mc, label) {
+                return false;
+            }
+            if mc.is_leaf() {
+                break;
+            }
+        }
+        mc.is_leaf()
+            && self.m_postings_list.access(mc.nptr().nid()).is_some_and(|ids| ids.contains(&id))
+    }
+
+    /// Searches the trie allowing `errors` instead of the configured number
+    /// of errors, and adds the number of visited states to `num_states`
+    pub fn trie_search_with_errors<T>(
+        &self,
+        vcode: &[T],
+        errors: u8,
+        num_states: &mut usize,
+    ) -> VecDeque<u32>
+    where
+        T: VCodeTools,
+    {
         // initialize a return iterator
         let mut ret = VecDeque::<u32>::with_capacity(1024);
 
@@ -67,23 +162,24 @@ where
 
         // perform bfs search on the trie
         while let Some(mut state) = m_states.pop() {
+            *num_states += 1;
             assert!(
-                self.m_errors >= state.dist(),
+                errors >= state.dist(),
                 "Error: radius must be less than state.dist, state.dist={} radius={}",
                 state.dist(),
-                self.m_radius
+                errors
             );
             let label = labels[state.pos()];
             let hamming_distance = HamTables::hamming_distance(self.m_bits, label);
 
             // if the hamming distance is less than the errors, perform trie search
-            // with number of allowed error=state.dist() - self.m_errors, otherwise
+            // with number of allowed error=errors - state.dist(), otherwise
             // perform exact search
-            if state.dist() < self.m_errors {
+            if state.dist() < errors {
                 for MartEdge {
                     label,
                     ptr: MartPointer { nid, ntype },
-                } in self.perform_scan(&state, *hamming_distance, label, ints_per_chunk)
+                } in self.perform_scan(&state, *hamming_distance, label, ints_per_chunk, errors)
                 {
                     match ntype {
                         MartNodeTypes::MartLeafNode => {
@@ -105,11 +201,9 @@ where
                     .map(|ids| ret.extend(ids));
             }
         }
-        ret.into_iter()
+        ret
     }
-}
 
-impl<'a> MartIndex<'a> {
     pub fn root(&self) -> &MartPointer {
         &self.m_rootptr
     }
@@ -136,6 +230,17 @@ impl<'a> MartIndex<'a> {
         BytePack::<T>::ints_per_chunk(self.m_bits)
     }
 
+    /// The number of errors at which a trie search visits every leaf
+    pub(crate) fn max_errors<T>(&self) -> u8
+    where
+        T: VCodeTools,
+    {
+        ((self.m_end - self.m_begin) * self.ints_per_chunk::<T>())
+            .min(u8::MAX.into())
+            .try_into()
+            .unwrap()
+    }
+
     fn byte_pack_iter<'b, T>(&self, vcode: &'b [T]) -> impl Iterator<Item = MartByteLabel> + 'b
     where
         T: VCodeTools,
@@ -204,7 +309,7 @@ impl<'a> MartIndex<'a> {
                 buckets[key].push(id);
             }
             Some(
-                (0u8..)
+                (0..=u8::MAX)
                     .zip(buckets.into_iter())
                     .filter(|(_, idxs)| !idxs.is_empty()),
             )
@@ -420,8 +525,9 @@ impl<'a> MartIndex<'a> {
         hamming_distance: HamTableEntry,
         label: MartByteLabel,
         ints_per_chunk: MartByteLabel,
+        errors: u8,
     ) -> impl Iterator<Item = MartEdge> + 's {
-        let radius = u8::min(self.m_errors - state.dist(), ints_per_chunk);
+        let radius = u8::min(errors - state.dist(), ints_per_chunk);
         self.perform_inner_scan(state, &hamming_distance, radius, label)
             .into_iter()
             .filter(move |edge| hamming_distance[edge.label_idx()] <= radius)
@@ -446,6 +552,64 @@ impl<'a> MartIndex<'a> {
         None
     }
 
+    fn perform_find_ptr(&self, mc: &mut MartCursor, label: u8) -> bool {
+        match mc.ntype() {
+            MartNodeTypes::Mart2Node => self.m_array_2.find_ptr(mc, label),
+            MartNodeTypes::Mart4Node => self.m_array_4.find_ptr(mc, label),
+            MartNodeTypes::Mart8Node => self.m_array_8.find_ptr(mc, label),
+            MartNodeTypes::Mart16Node => self.m_array_16.find_ptr(mc, label),
+            MartNodeTypes::Mart32Node => self.m_array_32.find_ptr(mc, label),
+            MartNodeTypes::Mart64Node => self.m_array_64.find_ptr(mc, label),
+            MartNodeTypes::Mart128Node => self.m_array_128.find_ptr(mc, label),
+            MartNodeTypes::Mart256Node => self.m_array_256.find_ptr(mc, label),
+            _ => panic!("Error: Invalid node type: {:#?}", mc.ntype()),
+        }
+    }
+
+    fn perform_remove_ptr(&mut self, mc: &MartCursor, label: u8) -> MartRemoveFlags {
+        match mc.ntype() {
+            MartNodeTypes::Mart2Node => self.m_array_2.remove_ptr(mc, label),
+            MartNodeTypes::Mart4Node => self.m_array_4.remove_ptr(mc, label),
+            MartNodeTypes::Mart8Node => self.m_array_8.remove_ptr(mc, label),
+            MartNodeTypes::Mart16Node => self.m_array_16.remove_ptr(mc, label),
+            MartNodeTypes::Mart32Node => self.m_array_32.remove_ptr(mc, label),
+            MartNodeTypes::Mart64Node => self.m_array_64.remove_ptr(mc, label),
+            MartNodeTypes::Mart128Node => self.m_array_128.remove_ptr(mc, label),
+            MartNodeTypes::Mart256Node => self.m_array_256.remove_ptr(mc, label),
+            _ => panic!("Error: Invalid node type: {:#?}", mc.ntype()),
+        }
+    }
+
+    // returns a node without children to the empty stack of its array
+    fn perform_free_node(&mut self, mc: &MartCursor) {
+        let m_edges = match mc.ntype() {
+            MartNodeTypes::Mart2Node => self.m_array_2.extract_edges(mc),
+            MartNodeTypes::Mart4Node => self.m_array_4.extract_edges(mc),
+            MartNodeTypes::Mart8Node => self.m_array_8.extract_edges(mc),
+            MartNodeTypes::Mart16Node => self.m_array_16.extract_edges(mc),
+            MartNodeTypes::Mart32Node => self.m_array_32.extract_edges(mc),
+            MartNodeTypes::Mart64Node => self.m_array_64.extract_edges(mc),
+            MartNodeTypes::Mart128Node => self.m_array_128.extract_edges(mc),
+            MartNodeTypes::Mart256Node => self.m_array_256.extract_edges(mc),
+            _ => panic!("Error: Invalid node type: {:#?}", mc.ntype()),
+        };
+        assert!(m_edges.is_empty(), "Error: freed node has children");
+    }
+
+    fn perform_shrink(&mut self, mc: &mut MartCursor) {
+        self.m_shrink_counts[usize::from(u8::from(mc.ntype())) - 1] += 1;
+        match mc.ntype() {
+            MartNodeTypes::Mart4Node => self.shrink_4(mc),
+            MartNodeTypes::Mart8Node => self.shrink_8(mc),
+            MartNodeTypes::Mart16Node => self.shrink_16(mc),
+            MartNodeTypes::Mart32Node => self.shrink_32(mc),
+            MartNodeTypes::Mart64Node => self.shrink_64(mc),
+            MartNodeTypes::Mart128Node => self.shrink_128(mc),
+            MartNodeTypes::Mart256Node => self.shrink_256(mc),
+            _ => panic!("Error: Invalid node type: {:#?}", mc.ntype()),
+        }
+    }
+
     fn perform_find_child(&self, ptr: MartPointer, label: u8) -> Option<MartPointer> {
         match ptr.ntype() {
             MartNodeTypes::Mart2Node => self.m_array_2.find_child(ptr, label),
@@ -506,3 +670,191 @@ define_expand_fn!(expand_16, m_array_16, m_array_32, append_ptr);
 define_expand_fn!(expand_32, m_array_32, m_array_64, append_ptr);
 define_expand_fn!(expand_64, m_array_64, m_array_128, append_ptr);
 define_expand_fn!(expand_128, m_array_128, m_array_256, insert_ptr);
+
+macro_rules! define_shrink_fn {
+    ($name:ident, $from:ident, $to:ident) => {
+        impl<'a> MartIndex<'a> {
+            fn $name(&mut self, mc: &mut MartCursor) {
+                *mc.nptr_mut() = {
+                    let m_edges = self.$from.extract_edges(mc);
+                    self.$to.make_node_with_edges(&m_edges)
+                };
+                self.perform_update_srcptr(mc);
+            }
+        }
+    };
+}
+
+define_shrink_fn!(shrink_4, m_array_4, m_array_2);
+define_shrink_fn!(shrink_8, m_array_8, m_array_4);
+define_shrink_fn!(shrink_16, m_array_16, m_array_8);
+define_shrink_fn!(shrink_32, m_array_32, m_array_16);
+define_shrink_fn!(shrink_64, m_array_64, m_array_32);
+define_shrink_fn!(shrink_128, m_array_128, m_array_64);
+define_shrink_fn!(shrink_256, m_array_256, m_array_128);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MartConfig;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    // with radius == errors the trie search misses no vcode within the radius,
+    // so the query results do not depend on the shape of the trie
+    fn config() -> MartConfig {
+        MartConfig {
+            bits: 4,
+            splitthreshold: Some(4),
+            in_weight: Some(1.0),
+            radius: 2,
+            errors: 2,
+            distance: None,
+            max_candidates: None,
+            max_verification_millis: None,
+            bbox_filter: false,
+            metric: Default::default(),
+        }
+    }
+
+    fn query<'b>(
+        index: &'b MartIndex<'b>,
+        vcodes: &'b VCodeArray<u64>,
+        qvcodes: &'b VCodeArray<u64>,
+        ids: &[u32],
+    ) -> Vec<(usize, usize)> {
+        index
+            .trie_query(vcodes, qvcodes)
+            .map(|(q, candidate)| (q, ids[candidate] as usize))
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn remove_matches_rebuild() {
+        let config = config();
+        let mut rng = StdRng::seed_from_u64(42);
+        let vcodes = VCodeArray::<u64>::from_hashes(
+            (0..300 * config.bits).map(|_| rng.gen()),
+            config.bits,
+        );
+        let qvcodes = VCodeArray::<u64>::from_hashes(
+            (0..100).flat_map(|q| {
+                let mut vcode = vcodes.access(q * 3).to_vec();
+                vcode[q % 4] ^= 1 << (q % 64);
+                vcode
+            }),
+            config.bits,
+        );
+
+        // interleave appends and removals
+        let mut index = MartIndex::new(&config, 0, u64::N_DIM);
+        let mut removed = vec![];
+        index.build(&vcodes, 200);
+        for id in 0..200 {
+            if rng.gen_bool(0.4) {
+                assert!(index.remove(id, &vcodes));
+                removed.push(id);
+            }
+        }
+        index.build(&vcodes, 300);
+        for id in 150..300 {
+            if !removed.contains(&id) && rng.gen_bool(0.4) {
+                assert!(index.remove(id, &vcodes));
+                removed.push(id);
+            }
+        }
+        assert!(!index.remove(removed[0], &vcodes));
+        assert!(!index.remove(300, &vcodes));
+
+        // rebuild from the remaining vcodes only
+        let kept: Vec<u32> = (0..300).filter(|id| !removed.contains(id)).collect();
+        let kept_vcodes = VCodeArray::<u64>::from_hashes(
+            kept.iter()
+                .flat_map(|&id| vcodes.access(id as usize).to_vec()),
+            config.bits,
+        );
+        let mut rebuilt = MartIndex::new(&config, 0, u64::N_DIM);
+        rebuilt.build(&kept_vcodes, kept.len());
+
+        assert_eq!(index.size(), kept.len());
+        assert_eq!(index.size(), rebuilt.size());
+        let all: Vec<u32> = (0..300).collect();
+        let expected = query(&rebuilt, &kept_vcodes, &qvcodes, &kept);
+        assert!(!expected.is_empty());
+        assert_eq!(query(&index, &vcodes, &qvcodes, &all), expected);
+
+        // removing every id shrinks nodes on the way, and frees all nodes below the root
+        for &id in kept.iter() {
+            assert!(index.remove(id, &vcodes));
+        }
+        assert_eq!(index.size(), 0);
+        assert_eq!(index.leaves(), 0);
+        assert_eq!(index.trie_query(&vcodes, &qvcodes).count(), 0);
+        assert!(index.innode_stats().any(|stats| stats.shrunk > 0));
+        for stats in index.innode_stats() {
+            let root = usize::from(stats.k == MartNode256::BYTES);
+            assert_eq!(stats.num, stats.empty + root);
+        }
+    }
+
+    #[test]
+    fn remove_shrinks_wide_nodes() {
+        let config = config();
+        let mut rng = StdRng::seed_from_u64(7);
+        // clear the first label, such that all vcodes end up below a single wide node
+        let vcodes = VCodeArray::<u64>::from_hashes(
+            (0..400 * config.bits).map(|_| rng.gen::<u64>() & !0b11),
+            config.bits,
+        );
+        let mut index = MartIndex::new(&config, 0, u64::N_DIM);
+        index.build(&vcodes, vcodes.size());
+        let wide = |index: &MartIndex| {
+            index
+                .innode_stats()
+                .filter(|stats| stats.k >= 64)
+                .map(|stats| stats.num - stats.empty)
+                .sum::<usize>()
+        };
+        assert!(wide(&index) > 1);
+
+        // the remaining ids stay reachable while the nodes above them shrink
+        let mut ids: Vec<u32> = (0..400).collect();
+        ids.shuffle(&mut rng);
+        let (&last, ids) = ids.split_last().unwrap();
+        for (i, &id) in ids.iter().enumerate() {
+            assert!(index.remove(id, &vcodes));
+            assert_eq!(index.size(), 399 - i);
+            assert!(index
+                .trie_search(vcodes.access(last as usize))
+                .any(|candidate| candidate == last));
+        }
+        assert!(index.remove(last, &vcodes));
+        assert_eq!(wide(&index), 1);
+        for k in [64, 128, 256] {
+            assert!(index
+                .innode_stats()
+                .any(|stats| stats.k == k && stats.shrunk > 0));
+        }
+    }
+
+    #[test]
+    fn split_labels_all_buckets() {
+        // a split counts its labels across all 256 buckets, up to u8::MAX. The
+        // first label is cleared, such that all vcodes land in a single leaf.
+        let config = config();
+        let mut rng = StdRng::seed_from_u64(5);
+        let vcodes = VCodeArray::<u64>::from_hashes(
+            (0..100 * config.bits).map(|_| rng.gen::<u64>() & !0b11),
+            config.bits,
+        );
+        let mut index = MartIndex::new(&config, 0, u64::N_DIM);
+        index.build(&vcodes, vcodes.size());
+        assert!(index.split_count() > 0);
+        for id in 0..vcodes.size() {
+            assert!(index.trie_search(vcodes.access(id)).any(|found| found as usize == id));
+        }
+    }
+}
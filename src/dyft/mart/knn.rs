@@ -0,0 +1,62 @@
+use super::structure::MartIndex;
+use crate::dyft::*;
+use crate::point::Distance;
+use crate::trajectory::Trajectory;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// The k nearest neighbours of a query, nearest first, together with the
+/// cost of finding them
+#[derive(Debug, Clone, Serialize)]
+pub struct DyFTKnnResult {
+    pub neighbours: Vec<(usize, Distance)>, // (trajectory id, fréchet distance)
+    pub errors: u8,                         // errors of the widest trie search
+    pub states: usize,                      // trie states visited over all searches
+    pub verifications: usize,               // fréchet distances computed
+}
+
+impl<'a> MartIndex<'a> {
+    /// Finds the k nearest neighbours of `query` by fréchet distance. The
+    /// trie is searched with 0, 1, 2, ... errors until at least k candidates
+    /// are found, and the candidates are ranked by their exact distance to
+    /// the trajectories in `dataset`.
+    pub fn knn_query<T>(
+        &self,
+        qvcode: &[T],
+        dataset: &[Trajectory],
+        query: &Trajectory,
+        k: usize,
+    ) -> DyFTKnnResult
+    where
+        T: VCodeTools,
+    {
+        let mut seen = HashSet::new();
+        let mut neighbours = Vec::new();
+        let mut states = 0;
+        let mut errors = 0;
+        loop {
+            for id in self.trie_search_with_errors(qvcode, errors, &mut states) {
+                let id = id as usize;
+                if seen.insert(id) {
+                    neighbours.push((id, query.frechet_distance(&dataset[id])));
+                }
+            }
+            if neighbours.len() >= k
+                || seen.len() >= self.size()
+                || errors >= self.max_errors::<T>()
+            {
+                break;
+            }
+            errors += 1;
+        }
+        let verifications = neighbours.len();
+        neighbours.sort_by(|(a_id, a), (b_id, b)| a.total_cmp(b).then(a_id.cmp(b_id)));
+        neighbours.truncate(k);
+        DyFTKnnResult {
+            neighbours,
+            errors,
+            states,
+            verifications,
+        }
+    }
+}
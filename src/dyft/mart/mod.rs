@@ -3,6 +3,10 @@ mod index;
 mod structure;
 mod debug;
 mod export;
+mod persist;
+mod knn;
 
 pub use export::*;
+pub use persist::*;
+pub use knn::*;
 pub use structure::*;
\ No newline at end of file
@@ -0,0 +1,327 @@
+use super::structure::MartIndex;
+use crate::config::map_master_path;
+use crate::config::master_result_dir;
+use crate::config::MartConfig;
+use crate::dyft::*;
+use crate::lsh::format_fingerprint;
+use anyhow::anyhow;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+/// Magic bytes at the start of every persisted MartIndex file
+pub const MART_INDEX_MAGIC: &[u8; 8] = b"DYFTMART";
+
+/// Version of the on-disk layout, bumped whenever the layout changes
+pub const MART_INDEX_VERSION: u32 = 4;
+
+/// The header of a persisted MartIndex. It records the parameters the trie
+/// structure depends on and the fingerprint of the hasher that produced the
+/// indexed vcodes, such that a file is only loaded into an index configured
+/// the same way it was built.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MartIndexHeader {
+    pub version: u32,
+    pub hash_bits: usize,
+    pub bits: usize,
+    pub errors: u8,
+    pub splitthreshold: Option<u32>,
+    pub in_weight: f32,
+    pub begin: usize,
+    pub end: usize,
+    pub hasher_fingerprint: u64,
+}
+
+impl MartIndexHeader {
+    fn expected<T>(config: &MartConfig, begin: usize, end: usize, hasher_fingerprint: u64) -> Self
+    where
+        T: VCodeTools,
+    {
+        MartIndexHeader {
+            version: MART_INDEX_VERSION,
+            hash_bits: T::N_DIM,
+            bits: config.bits,
+            errors: config.errors,
+            splitthreshold: config.splitthreshold,
+            in_weight: config.in_weight.unwrap_or(1.0),
+            begin,
+            end,
+            hasher_fingerprint,
+        }
+    }
+
+    fn check(&self, expected: &Self) -> Result<()> {
+        if self.version != expected.version {
+            return Err(anyhow!(
+                "MartIndex file has version {}, expected {}",
+                self.version,
+                expected.version
+            ));
+        }
+        if self.hash_bits != expected.hash_bits {
+            return Err(anyhow!(
+                "MartIndex file was built with {}-bit hashes, expected {}-bit hashes",
+                self.hash_bits,
+                expected.hash_bits
+            ));
+        }
+        if self.hasher_fingerprint != expected.hasher_fingerprint {
+            return Err(anyhow!(
+                "MartIndex file was built from hashes of hasher {}, expected hasher {}",
+                format_fingerprint(self.hasher_fingerprint),
+                format_fingerprint(expected.hasher_fingerprint)
+            ));
+        }
+        if self != expected {
+            return Err(anyhow!(
+                "MartIndex file was built with an incompatible configuration: {:?}, expected {:?}",
+                self,
+                expected
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The owned counterpart of the serialized MartIndex fields
+#[derive(Deserialize)]
+struct MartIndexBody {
+    m_array_2: MartSparse<MartNode2>,
+    m_array_4: MartSparse<MartNode4>,
+    m_array_8: MartSparse<MartNode8>,
+    m_array_16: MartSparse<MartNode16>,
+    m_array_32: MartSparse<MartNode32>,
+    m_array_64: MartDense<MartNode64>,
+    m_array_128: MartDense<MartNode128>,
+    m_array_256: MartFull<MartNode256>,
+    m_rootptr: MartPointer,
+    m_postings_list: SparseTable,
+    m_split_count: usize,
+    m_shrink_counts: [usize; 8],
+    m_max_depth: usize,
+    m_ids: MartNodeId,
+    m_removed: MartNodeId,
+    m_empty_leaves: u32,
+}
+
+impl<'a> MartIndex<'a> {
+    pub fn header<T>(&self, hasher_fingerprint: u64) -> MartIndexHeader
+    where
+        T: VCodeTools,
+    {
+        MartIndexHeader {
+            version: MART_INDEX_VERSION,
+            hash_bits: T::N_DIM,
+            bits: self.m_bits,
+            errors: self.m_errors,
+            splitthreshold: match self.m_splitthreshold {
+                SplitThresholds::Threshold(t) => Some(t),
+                SplitThresholds::Thresholds(_) => None,
+            },
+            in_weight: self.m_in_weight,
+            begin: self.m_begin,
+            end: self.m_end,
+            hasher_fingerprint,
+        }
+    }
+
+    /// Writes the index to `writer`, prefixed by the magic bytes and the header
+    pub fn write_to<T, W>(&self, writer: &mut W, hasher_fingerprint: u64) -> Result<()>
+    where
+        T: VCodeTools,
+        W: Write,
+    {
+        writer.write_all(MART_INDEX_MAGIC)?;
+        rmp_serde::encode::write_named(writer, &self.header::<T>(hasher_fingerprint))?;
+        rmp_serde::encode::write_named(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads an index written by `write_to`, rejecting it if it was built with
+    /// a different configuration, hash width or hasher than the one given
+    pub fn read_from<T, R>(
+        reader: &mut R,
+        config: &MartConfig,
+        bit_pos_begin: usize,
+        bit_pos_end: usize,
+        hasher_fingerprint: u64,
+    ) -> Result<Self>
+    where
+        T: VCodeTools,
+        R: Read,
+    {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MART_INDEX_MAGIC {
+            return Err(anyhow!("not a MartIndex file"));
+        }
+        let mut de = rmp_serde::Deserializer::new(reader);
+        let header = MartIndexHeader::deserialize(&mut de)?;
+        header.check(&MartIndexHeader::expected::<T>(
+            config,
+            bit_pos_begin,
+            bit_pos_end,
+            hasher_fingerprint,
+        ))?;
+        let body = MartIndexBody::deserialize(&mut de)?;
+
+        let mut index = MartIndex::new(config, bit_pos_begin, bit_pos_end);
+        index.m_array_2 = body.m_array_2;
+        index.m_array_4 = body.m_array_4;
+        index.m_array_8 = body.m_array_8;
+        index.m_array_16 = body.m_array_16;
+        index.m_array_32 = body.m_array_32;
+        index.m_array_64 = body.m_array_64;
+        index.m_array_128 = body.m_array_128;
+        index.m_array_256 = body.m_array_256;
+        index.m_rootptr = body.m_rootptr;
+        index.m_postings_list = body.m_postings_list;
+        index.m_split_count = body.m_split_count;
+        index.m_shrink_counts = body.m_shrink_counts;
+        index.m_max_depth = body.m_max_depth;
+        index.m_ids = body.m_ids;
+        index.m_removed = body.m_removed;
+        index.m_empty_leaves = body.m_empty_leaves;
+        Ok(index)
+    }
+
+    /// Saves the index to `path`, relative to $MASTER_RESULT_DIR if set
+    pub fn save<T>(&self, path: impl AsRef<Path>, hasher_fingerprint: u64) -> Result<()>
+    where
+        T: VCodeTools,
+    {
+        let file = File::create(map_master_path(path, master_result_dir()))?;
+        let mut writer = BufWriter::new(file);
+        self.write_to::<T, _>(&mut writer, hasher_fingerprint)?;
+        writer.flush().map_err(|e| anyhow!(e))
+    }
+
+    /// Loads an index saved with `save` from `path`, relative to $MASTER_RESULT_DIR if set
+    pub fn load<T>(
+        path: impl AsRef<Path>,
+        config: &MartConfig,
+        bit_pos_begin: usize,
+        bit_pos_end: usize,
+        hasher_fingerprint: u64,
+    ) -> Result<Self>
+    where
+        T: VCodeTools,
+    {
+        let file = File::open(map_master_path(path, master_result_dir()))?;
+        Self::read_from::<T, _>(
+            &mut BufReader::new(file),
+            config,
+            bit_pos_begin,
+            bit_pos_end,
+            hasher_fingerprint,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    const FINGERPRINT: u64 = 0x5eed;
+
+    fn config() -> MartConfig {
+        MartConfig {
+            bits: 4,
+            splitthreshold: Some(4),
+            in_weight: Some(1.0),
+            radius: 4,
+            errors: 2,
+            distance: None,
+            max_candidates: None,
+            max_verification_millis: None,
+            bbox_filter: false,
+            metric: Default::default(),
+        }
+    }
+
+    fn random_vcodes(n: usize, bits: usize, rng: &mut StdRng) -> VCodeArray<u64> {
+        VCodeArray::from_hashes((0..n * bits).map(|_| rng.gen()), bits)
+    }
+
+    fn perturbed_vcodes(vcodes: &VCodeArray<u64>, n: usize, rng: &mut StdRng) -> VCodeArray<u64> {
+        VCodeArray::from_hashes(
+            (0..n).flat_map(|_| {
+                let vcode = vcodes.access(rng.gen_range(0..vcodes.size())).to_vec();
+                let flip = rng.gen_range(0..vcode.len());
+                let bit = rng.gen_range(0..u64::N_DIM);
+                vcode
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, v)| if i == flip { v ^ (1 << bit) } else { v })
+            }),
+            vcodes.bits(),
+        )
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let config = config();
+        let mut rng = StdRng::seed_from_u64(42);
+        let vcodes = random_vcodes(200, config.bits, &mut rng);
+        let qvcodes = perturbed_vcodes(&vcodes, 100, &mut rng);
+
+        let mut index = MartIndex::new(&config, 0, u64::N_DIM);
+        index.build(&vcodes, vcodes.size());
+
+        let mut bytes = Vec::new();
+        index.write_to::<u64, _>(&mut bytes, FINGERPRINT).unwrap();
+        let loaded = MartIndex::read_from::<u64, _>(
+            &mut bytes.as_slice(),
+            &config,
+            0,
+            u64::N_DIM,
+            FINGERPRINT,
+        )
+        .unwrap();
+
+        assert_eq!(index.size(), loaded.size());
+        assert!(index.split_count() > 0);
+        assert_eq!(index.split_count(), loaded.split_count());
+        let expected: Vec<_> = index.trie_query(&vcodes, &qvcodes).collect();
+        let actual: Vec<_> = loaded.trie_query(&vcodes, &qvcodes).collect();
+        assert!(!expected.is_empty());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn load_rejects_incompatible_config() {
+        let config = config();
+        let index = MartIndex::new(&config, 0, u64::N_DIM);
+        let mut bytes = Vec::new();
+        index.write_to::<u64, _>(&mut bytes, FINGERPRINT).unwrap();
+
+        let other = MartConfig {
+            bits: 8,
+            ..config.clone()
+        };
+        let read = |bytes: &[u8], config: &MartConfig, fingerprint: u64| {
+            MartIndex::read_from::<u64, _>(&mut &bytes[..], config, 0, u64::N_DIM, fingerprint)
+        };
+        assert!(read(&bytes, &config, FINGERPRINT).is_ok());
+        assert!(read(&bytes, &other, FINGERPRINT).is_err());
+        assert!(read(&bytes, &config, FINGERPRINT + 1).is_err());
+        assert!(read(&bytes[1..], &config, FINGERPRINT).is_err());
+        assert!(MartIndex::read_from::<u32, _>(
+            &mut bytes.as_slice(),
+            &config,
+            0,
+            u32::N_DIM,
+            FINGERPRINT
+        )
+        .is_err());
+    }
+}
@@ -1,8 +1,17 @@
 use crate::config::MartConfig;
+use crate::config::QueryBudget;
 use crate::dyft::*;
+use crate::index::VerificationResult;
+use crate::metric::VerificationMetric;
 use crate::point::Distance;
+use crate::point::QueryDistance;
+use crate::prefilter::BoundingBoxFilter;
+use crate::result::QueryResult;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::time::Instant;
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize)]
 pub struct MartIndex<'a> {
     pub(crate) m_array_2: MartSparse<MartNode2>,
     pub(crate) m_array_4: MartSparse<MartNode4>,
@@ -12,13 +21,17 @@ pub struct MartIndex<'a> {
     pub(crate) m_array_64: MartDense<MartNode64>,
     pub(crate) m_array_128: MartDense<MartNode128>,
     pub(crate) m_array_256: MartFull<MartNode256>,
+    #[serde(skip)]
     pub(crate) m_edges: Vec<MartEdge>,
     pub(crate) m_rootptr: MartPointer,
     pub(crate) m_postings_list: SparseTable,
+    #[serde(skip)]
     pub(crate) m_splitthreshold: SplitThresholds<'a>,
     pub(crate) m_split_count: usize,
+    pub(crate) m_shrink_counts: [usize; 8], // number of shrunk nodes per node type
     pub(crate) m_radius: u32, // radius for hamming distance
     pub(crate) m_errors: u8,  // number of errors in trie search
+    #[serde(skip)]
     pub(crate) m_bit_positions: &'a BitPositionsEntry,
     pub(crate) m_in_weight: f32,
     pub(crate) m_max_depth: usize,
@@ -26,6 +39,17 @@ pub struct MartIndex<'a> {
     pub(crate) m_end: usize,
     pub(crate) m_begin: usize,
     pub(crate) m_ids: MartNodeId, // number of ids
+    pub(crate) m_removed: MartNodeId, // number of removed ids
+    pub(crate) m_empty_leaves: u32,   // number of leaves emptied by removals, never reclaimed
+    #[serde(skip)]
+    #[get_size(ignore)]
+    pub(crate) m_budget: QueryBudget, // per-query limits of the verifying queries
+    #[serde(skip)]
+    #[get_size(ignore)]
+    pub(crate) m_bbox_filter: Option<BoundingBoxFilter>, // filled by DyftIndex
+    #[serde(skip)]
+    #[get_size(ignore)]
+    pub(crate) m_metric: VerificationMetric, // decides the fully verified candidates
 }
 
 impl<'a> MartIndex<'a> {
@@ -37,6 +61,10 @@ impl<'a> MartIndex<'a> {
             in_weight,
             splitthreshold,
             distance: _,
+            max_candidates,
+            max_verification_millis,
+            bbox_filter,
+            metric,
         }: &MartConfig,
         bit_pos_begin: usize,
         bit_pos_end: usize,
@@ -66,9 +94,12 @@ impl<'a> MartIndex<'a> {
             m_array_256,
             m_edges,
             m_split_count: 0,
+            m_shrink_counts: [0; 8],
             m_rootptr,
             m_postings_list,
             m_ids: 0,
+            m_removed: 0,
+            m_empty_leaves: 0,
             m_radius: radius,
             m_errors: errors,
             m_bit_positions,
@@ -77,6 +108,9 @@ impl<'a> MartIndex<'a> {
             m_bits: bits,
             m_begin: bit_pos_begin,
             m_end: bit_pos_end,
+            m_budget: QueryBudget::new(max_candidates, max_verification_millis),
+            m_bbox_filter: bbox_filter.then(BoundingBoxFilter::default),
+            m_metric: metric,
         }
     }
 
@@ -100,40 +134,255 @@ impl<'a> MartIndex<'a> {
             })
     }
 
+    /// Like `trie_query`, with the queries split across the threads of the
+    /// current rayon pool. The pairs are ordered by query id, as in
+    /// `trie_query`.
+    pub fn par_trie_query<T>(
+        &self,
+        vcodes: &VCodeArray<T>,
+        qvcodes: &VCodeArray<T>,
+    ) -> Vec<(usize, usize)>
+    where
+        T: VCodeTools + Sync,
+    {
+        (0..qvcodes.size())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let query = qvcodes.access(i);
+                self.trie_search(query)
+                    .map(move |candidate| (i, candidate as usize))
+                    .filter(move |&(_, candidate)| {
+                        vcodes.verify_candidate_predicate(candidate, query, self.m_radius)
+                    })
+            })
+            .collect()
+    }
+
     pub fn trie_query_partial_verification<T, V>(
         &'a self,
         vcodes: &'a VCodeArray<T>,
         qvcodes: &'a VCodeArray<T>,
         dataset: &'a [Trajectory],
         queryset: &'a [Trajectory],
-        distance: Distance,
+        distance: QueryDistance<'a>,
     ) -> DyFTPartialVerificationResult<V>
     where
         T: VCodeTools,
         V: FromIterator<(usize, usize)>,
     {
-        let partial_verification_iter = qvcodes.iter().enumerate().flat_map(|(i, q)| {
-            self.trie_search(q)
-                .map(move |candidate| (i, candidate as usize))
+        DyFTPartialVerificationResult::merge((0..qvcodes.size()).map(|i| {
+            self.query_partial_verification(i, vcodes, qvcodes, dataset, queryset, distance)
+        }))
+    }
+
+    /// Like `trie_query_partial_verification`, with the queries split across
+    /// the threads of the current rayon pool
+    pub fn par_trie_query_partial_verification<T, V>(
+        &self,
+        vcodes: &VCodeArray<T>,
+        qvcodes: &VCodeArray<T>,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> DyFTPartialVerificationResult<V>
+    where
+        T: VCodeTools + Sync,
+        V: FromIterator<(usize, usize)>,
+    {
+        let results: Vec<DyFTPartialVerificationResult<Vec<(usize, usize)>>> = (0..qvcodes
+            .size())
+            .into_par_iter()
+            .map(|i| {
+                self.query_partial_verification(i, vcodes, qvcodes, dataset, queryset, distance)
+            })
+            .collect();
+        DyFTPartialVerificationResult::merge(results)
+    }
+
+    /// Turns the pairs returned by a trie query into records carrying the
+    /// exact hamming distance of each pair, which the queries stop counting
+    /// past the radius. Every pair is taken on its own, so the pairs can come
+    /// in any order. If the pairs come from a verifying query, those beyond
+    /// the partial verification radius are marked as verified by the decider
+    /// of the verification metric.
+    pub fn query_results<'b, T>(
+        &'b self,
+        vcodes: &'b VCodeArray<T>,
+        qvcodes: &'b VCodeArray<T>,
+        pairs: impl IntoIterator<Item = (usize, usize)> + 'b,
+        verified: bool,
+    ) -> impl Iterator<Item = QueryResult> + 'b
+    where
+        T: VCodeTools,
+    {
+        pairs.into_iter().map(move |(query, candidate)| {
+            let hamming = vcodes.hamdist_radius(candidate, qvcodes.access(query), u32::MAX);
+            QueryResult {
+                hamming_distance: Some(hamming),
+                verified: verified && hamming > self.partial_verification_radius(),
+                ..QueryResult::new(query, candidate)
+            }
+        })
+    }
+
+    /// Verifies the candidates of query `i`. Candidates ruled out by the
+    /// bounding box filter are pruned first. With a query budget, the
+    /// candidates are verified in increasing hamming distance order until
+    /// either limit of the budget is reached.
+    fn query_partial_verification<T>(
+        &self,
+        i: usize,
+        vcodes: &VCodeArray<T>,
+        qvcodes: &VCodeArray<T>,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> DyFTPartialVerificationResult<Vec<(usize, usize)>>
+    where
+        T: VCodeTools,
+    {
+        let query = qvcodes.access(i);
+        let bboxes = self
+            .m_bbox_filter
+            .as_ref()
+            .map(|filter| filter.candidates(&queryset[i], distance.of(i)));
+        let mut pruned = 0;
+        let candidates = self.trie_search(query).filter(|&candidate| {
+            let keep = bboxes
+                .as_ref()
+                .map_or(true, |bboxes| bboxes.may_match(candidate as usize));
+            pruned += usize::from(!keep);
+            keep
         });
+        if self.m_budget.is_unlimited() {
+            let mut result = DyFTPartialVerificationResult::from_candidates(
+                candidates.map(move |candidate| (i, candidate as usize)),
+                vcodes,
+                qvcodes,
+                dataset,
+                queryset,
+                self.partial_verification_radius(),
+                self.m_radius,
+                distance,
+                self.m_metric,
+            );
+            result.m_pruned_pairs = pruned;
+            return result;
+        }
 
-        DyFTPartialVerificationResult::<V>::from_candidates(
-            partial_verification_iter,
+        let mut candidates = Vec::from_iter(candidates.map(|candidate| {
+            let candidate = candidate as usize;
+            (vcodes.hamdist_radius(candidate, query, self.m_radius), candidate)
+        }));
+        candidates.sort_unstable();
+        let mut truncated = false;
+        if let Some(max_candidates) = self.m_budget.max_candidates {
+            truncated = candidates.len() > max_candidates;
+            candidates.truncate(max_candidates);
+        }
+        let start = Instant::now();
+        let max_verification_time = self.m_budget.max_verification_time;
+        let within_budget = candidates
+            .into_iter()
+            .map(|(_, candidate)| (i, candidate))
+            .take_while(|_| match max_verification_time {
+                Some(limit) if start.elapsed() >= limit => {
+                    truncated = true;
+                    false
+                }
+                _ => true,
+            });
+        let mut result = DyFTPartialVerificationResult::from_candidates(
+            within_budget,
             vcodes,
             qvcodes,
             dataset,
             queryset,
-            self.m_radius - (self.m_radius as f32).sqrt().floor() as u32,
+            self.partial_verification_radius(),
             self.m_radius,
             distance,
+            self.m_metric,
+        );
+        result.m_truncated_queries = usize::from(truncated);
+        result.m_pruned_pairs = pruned;
+        result
+    }
+
+    /// Candidates within this hamming distance are accepted without
+    /// deciding their distance
+    fn partial_verification_radius(&self) -> u32 {
+        self.m_radius - (self.m_radius as f32).sqrt().floor() as u32
+    }
+
+    /// Pairs every vcode of `vcodes` with the indexed vcodes within the
+    /// hamming radius, searching the trie with the dataset vcodes themselves
+    /// instead of hashing a queryset. Each unordered pair is returned once as
+    /// `(smaller id, larger id)` and no trajectory is paired with itself.
+    pub fn self_join<T>(
+        &'a self,
+        vcodes: &'a VCodeArray<T>,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a
+    where
+        T: VCodeTools,
+    {
+        self.self_join_candidates(vcodes).filter(move |&(a, b)| {
+            vcodes.verify_candidate_predicate(b, vcodes.access(a), self.m_radius)
+        })
+    }
+
+    pub fn self_join_partial_verification<T, V>(
+        &'a self,
+        vcodes: &'a VCodeArray<T>,
+        dataset: &'a [Trajectory],
+        distance: Distance,
+    ) -> DyFTPartialVerificationResult<V>
+    where
+        T: VCodeTools,
+        V: FromIterator<(usize, usize)>,
+    {
+        DyFTPartialVerificationResult::<V>::from_candidates(
+            self.self_join_candidates(vcodes),
+            vcodes,
+            vcodes,
+            dataset,
+            dataset,
+            self.partial_verification_radius(),
+            self.m_radius,
+            QueryDistance::Global(distance),
+            self.m_metric,
         )
     }
+
+    /// Searches the trie with every vcode whose id is still indexed. A pair
+    /// within the radius is found from both of its sides, so it is only
+    /// emitted from the smaller id, which avoids buffering the pairs to
+    /// deduplicate them
+    fn self_join_candidates<T>(
+        &'a self,
+        vcodes: &'a VCodeArray<T>,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a
+    where
+        T: VCodeTools,
+    {
+        vcodes
+            .iter()
+            .enumerate()
+            .filter(|&(i, q)| self.contains(i as u32, q))
+            .flat_map(|(i, q)| {
+                self.trie_search(q)
+                    .map(|candidate| candidate as usize)
+                    .filter(move |&candidate| candidate > i)
+                    .map(move |candidate| (i, candidate))
+            })
+    }
 }
 
 pub struct DyFTPartialVerificationResult<I> {
     m_full_verification_count: usize,
     m_partial_verification_count: usize,
     m_filtered_verification_count: usize,
+    m_truncated_queries: usize, // queries cut off by the query budget
+    m_pruned_pairs: usize,      // pairs pruned by the bounding box filter
     m_candidates: I,
 }
 
@@ -149,7 +398,8 @@ where
         queryset: &[Trajectory],
         hamming_distance: u32,
         upper_hamming_distance: u32,
-        distance: Distance,
+        distance: QueryDistance<'_>,
+        metric: VerificationMetric,
     ) -> Self
     where
         T: VCodeTools,
@@ -163,6 +413,7 @@ where
             vcodes,
             qvcodes,
             distance,
+            metric,
             hamming_distance,
             upper_hamming_distance,
             &mut m_full_verification_count,
@@ -178,6 +429,40 @@ where
             m_full_verification_count,
             m_partial_verification_count,
             m_filtered_verification_count,
+            m_truncated_queries: 0,
+            m_pruned_pairs: 0,
+            m_candidates,
+        }
+    }
+
+    /// Combines the results of disjoint sets of queries in the given order,
+    /// summing their verification counters
+    pub fn merge(
+        results: impl IntoIterator<Item = DyFTPartialVerificationResult<Vec<(usize, usize)>>>,
+    ) -> Self {
+        let mut m_full_verification_count = 0;
+        let mut m_partial_verification_count = 0;
+        let mut m_filtered_verification_count = 0;
+        let mut m_truncated_queries = 0;
+        let mut m_pruned_pairs = 0;
+        let m_candidates = results
+            .into_iter()
+            .flat_map(|result| {
+                m_full_verification_count += result.m_full_verification_count;
+                m_partial_verification_count += result.m_partial_verification_count;
+                m_filtered_verification_count += result.m_filtered_verification_count;
+                m_truncated_queries += result.m_truncated_queries;
+                m_pruned_pairs += result.m_pruned_pairs;
+                result.m_candidates
+            })
+            .collect::<V>();
+
+        DyFTPartialVerificationResult {
+            m_full_verification_count,
+            m_partial_verification_count,
+            m_filtered_verification_count,
+            m_truncated_queries,
+            m_pruned_pairs,
             m_candidates,
         }
     }
@@ -194,6 +479,17 @@ where
         self.m_filtered_verification_count
     }
 
+    /// The number of queries that reached the query budget before all their
+    /// candidates were verified
+    pub fn truncated_query_count(&self) -> usize {
+        self.m_truncated_queries
+    }
+
+    /// The number of candidates pruned by the bounding box filter
+    pub fn pruned_pair_count(&self) -> usize {
+        self.m_pruned_pairs
+    }
+
     pub fn results(self) -> V {
         self.m_candidates
     }
@@ -203,7 +499,8 @@ where
         queryset: &'a [Trajectory],
         vcodes: &'a VCodeArray<T>,
         qvcodes: &'a VCodeArray<T>,
-        distance: Distance,
+        distance: QueryDistance<'a>,
+        metric: VerificationMetric,
         hamming_distance: u32,
         upper_hamming_distance: u32,
         full_verification_count: &'a mut usize,
@@ -224,7 +521,7 @@ where
             }
             ham if ham <= upper_hamming_distance => {
                 *full_verification_count += 1;
-                queryset[query].frechet_decider(&dataset[candidate], distance)
+                metric.decide(&queryset[query], &dataset[candidate], distance.of(query))
             }
             _ => {
                 *filtered_verification_count += 1;
@@ -233,3 +530,19 @@ where
         }
     }
 }
+
+impl VerificationResult for DyFTPartialVerificationResult<Vec<(usize, usize)>> {
+    type Stats = DyFTStats;
+
+    fn pairs(&self) -> &[(usize, usize)] {
+        &self.m_candidates
+    }
+
+    fn record_stats(&self, stats: &mut DyFTStats) {
+        stats.partial_verification_count += self.m_partial_verification_count;
+        stats.full_verification_count += self.m_full_verification_count;
+        stats.filtered_verification_count += self.m_filtered_verification_count;
+        stats.truncated_queries += self.m_truncated_queries;
+        stats.pruned_pairs += self.m_pruned_pairs;
+    }
+}
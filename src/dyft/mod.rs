@@ -10,10 +10,15 @@ mod sparse;
 use crate::config::IndexConfig;
 use crate::config::LshConfig;
 use crate::config::MartConfig;
+use crate::index::SimilarityIndex;
 use crate::lsh::MultiTrajectoryLsh;
 use crate::lsh::TensoredMultiHash;
 use crate::lsh::TrajectoryLsh;
+use crate::metric::VerificationMetric;
 use crate::point::Distance;
+use crate::point::QueryDistance;
+use crate::prefilter::BoundingBoxFilter;
+use crate::result::QueryResult;
 use crate::trajectory::Trajectory;
 use crate::util::GetIndexSize;
 use crate::util::IndexSize;
@@ -30,6 +35,7 @@ pub use node::*;
 use rand::rngs::StdRng;
 use rand::Fill;
 use rand::SeedableRng;
+use rayon::prelude::*;
 pub use sparse::SparseGroup;
 pub use sparse::SparseTable;
 
@@ -44,6 +50,7 @@ where
 {
     m_hasher: TensoredMultiHash<H>,
     m_index: MartIndex<'a>,
+    m_vcodes: VCodeArray<H::Hash>, // vcodes of trajectories inserted with insert_trajectory
 }
 
 impl<'a, H> DyFT<H::Hash> for DyftIndex<'a, H>
@@ -60,6 +67,10 @@ where
         self.m_index.append(vcode, database)
     }
 
+    fn remove(&mut self, id: u32, database: &VCodeArray<H::Hash>) -> bool {
+        self.m_index.remove(id, database)
+    }
+
     fn trie_search(&self, vcode: &[H::Hash]) -> impl Iterator<Item = u32> {
         self.m_index.trie_search(vcode)
     }
@@ -120,9 +131,78 @@ where
                 &mut StdRng::seed_from_u64(seed),
             ),
             m_index: MartIndex::new(&mart_config, 0, H::Hash::N_DIM.min(u64::N_DIM)),
+            m_vcodes: VCodeArray::empty(mart_config.bits),
+        }
+    }
+
+    /// Creates an index that hashes with a previously saved hasher, instead
+    /// of one generated from the seed in `config`
+    pub fn with_hasher(
+        config: &IndexConfig<MartConfig>,
+        hasher: TensoredMultiHash<H>,
+    ) -> DyftIndex<'a, H> {
+        DyftIndex {
+            m_hasher: hasher,
+            m_index: MartIndex::new(config.index_params(), 0, H::Hash::N_DIM.min(u64::N_DIM)),
+            m_vcodes: VCodeArray::empty(config.index_params().bits),
         }
     }
 
+    /// Loads a trie saved with `save` that was built over `dataset`, whose
+    /// vcodes are hashed again. The hasher is derived from the seed in
+    /// `config`, and the trie is rejected unless it was built from vcodes of
+    /// that same hasher.
+    pub fn load(
+        config: &IndexConfig<MartConfig>,
+        dataset: &[Trajectory],
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<DyftIndex<'a, H>> {
+        Self::new(config, max_trajectory_length(dataset)).load_trie(config, dataset, path)
+    }
+
+    /// Like `load`, for a trie built from vcodes of `hasher`
+    pub fn load_with_hasher(
+        config: &IndexConfig<MartConfig>,
+        hasher: TensoredMultiHash<H>,
+        dataset: &[Trajectory],
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<DyftIndex<'a, H>> {
+        Self::with_hasher(config, hasher).load_trie(config, dataset, path)
+    }
+
+    fn load_trie(
+        mut self,
+        config: &IndexConfig<MartConfig>,
+        dataset: &[Trajectory],
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<DyftIndex<'a, H>> {
+        self.m_index = MartIndex::load::<H::Hash>(
+            path,
+            config.index_params(),
+            0,
+            H::Hash::N_DIM.min(u64::N_DIM),
+            self.m_hasher.fingerprint(),
+        )?;
+        if self.m_index.m_ids as usize != dataset.len() {
+            return Err(anyhow::anyhow!(
+                "the trie holds {} trajectories, but the dataset has {}",
+                self.m_index.m_ids,
+                dataset.len()
+            ));
+        }
+        self.m_vcodes = self.hash_dataset(dataset);
+        self.index_bounding_boxes(dataset);
+        Ok(self)
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        self.m_index.save::<H::Hash>(path, self.m_hasher.fingerprint())
+    }
+
+    pub fn hasher(&self) -> &TensoredMultiHash<H> {
+        &self.m_hasher
+    }
+
     pub fn build_with_memory_samples(
         &mut self,
         dataset: &VCodeArray<H::Hash>,
@@ -145,13 +225,13 @@ where
         qvcodes: &'b VCodeArray<H::Hash>,
         dataset: &'b [Trajectory],
         queryset: &'b [Trajectory],
-        distance: Distance,
+        distance: impl Into<QueryDistance<'b>>,
     ) -> DyFTPartialVerificationResult<V>
     where
         V: FromIterator<(usize, usize)> + IntoIterator<Item = (usize, usize)>,
     {
         self.m_index.trie_query_partial_verification::<H::Hash, V>(
-            &vcodes, &qvcodes, &dataset, &queryset, distance,
+            &vcodes, &qvcodes, &dataset, &queryset, distance.into(),
         )
     }
 
@@ -165,6 +245,81 @@ where
     {
         V::from_iter(self.m_index.trie_query(&vcodes, &qvcodes))
     }
+
+    /// Like `trie_query_collect_with_verification`, with the queries split
+    /// across the threads of the current rayon pool
+    pub fn par_trie_query_collect_with_verification<'b, V>(
+        &self,
+        vcodes: &VCodeArray<H::Hash>,
+        qvcodes: &VCodeArray<H::Hash>,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: impl Into<QueryDistance<'b>>,
+    ) -> DyFTPartialVerificationResult<V>
+    where
+        V: FromIterator<(usize, usize)>,
+        H::Hash: Sync,
+    {
+        self.m_index.par_trie_query_partial_verification::<H::Hash, V>(
+            vcodes, qvcodes, dataset, queryset, distance.into(),
+        )
+    }
+
+    /// Like `trie_query_collect`, with the queries split across the threads
+    /// of the current rayon pool
+    pub fn par_trie_query_collect<V>(
+        &self,
+        vcodes: &VCodeArray<H::Hash>,
+        qvcodes: &VCodeArray<H::Hash>,
+    ) -> V
+    where
+        V: FromIterator<(usize, usize)>,
+        H::Hash: Sync,
+    {
+        V::from_iter(self.m_index.par_trie_query(vcodes, qvcodes))
+    }
+
+    /// Turns the pairs returned by one of the trie queries into records with
+    /// their hamming distances, see `MartIndex::query_results`. Set
+    /// `verified` if the pairs come from a verifying query.
+    pub fn query_results<V>(
+        &self,
+        vcodes: &VCodeArray<H::Hash>,
+        qvcodes: &VCodeArray<H::Hash>,
+        pairs: impl IntoIterator<Item = (usize, usize)>,
+        verified: bool,
+    ) -> V
+    where
+        V: FromIterator<QueryResult>,
+    {
+        V::from_iter(self.m_index.query_results(vcodes, qvcodes, pairs, verified))
+    }
+
+    /// Finds every pair of dataset trajectories whose vcodes are within the
+    /// hamming radius, reusing the vcodes the index was built from. Each
+    /// unordered pair is returned once as `(smaller id, larger id)`.
+    pub fn self_join_collect<'b, V>(&'b self, vcodes: &'b VCodeArray<H::Hash>) -> V
+    where
+        V: FromIterator<(usize, usize)>,
+    {
+        V::from_iter(self.m_index.self_join(vcodes))
+    }
+
+    /// Like `self_join_collect`, but pairs that are not close enough in
+    /// hamming distance to be accepted outright are verified with the
+    /// decider of the verification metric
+    pub fn self_join_collect_with_verification<'b, V>(
+        &'b self,
+        vcodes: &'b VCodeArray<H::Hash>,
+        dataset: &'b [Trajectory],
+        distance: Distance,
+    ) -> DyFTPartialVerificationResult<V>
+    where
+        V: FromIterator<(usize, usize)>,
+    {
+        self.m_index
+            .self_join_partial_verification::<H::Hash, V>(vcodes, dataset, distance)
+    }
 }
 
 impl<'a, H> DyftIndex<'a, H>
@@ -173,7 +328,7 @@ where
     [H::Hash]: Fill,
     [(); H::Hash::N_DIM]:,
 {
-    pub fn hash_dataset(&'a self, dataset: &'a [Trajectory]) -> VCodeArray<H::Hash> {
+    pub fn hash_dataset(&self, dataset: &[Trajectory]) -> VCodeArray<H::Hash> {
         VCodeArray::from_hashes(
             dataset
                 .iter()
@@ -182,7 +337,21 @@ where
         )
     }
 
-    pub fn hash_queryset(&'a self, dataset: &'a [Trajectory]) -> VCodeArray<H::Hash> {
+    /// Like `hash_dataset`, hashing the trajectories on the threads of the
+    /// current rayon pool
+    pub fn par_hash_dataset(&self, dataset: &[Trajectory]) -> VCodeArray<H::Hash>
+    where
+        H: Sync,
+        H::Hash: Send,
+    {
+        let hashes: Vec<H::Hash> = dataset
+            .par_iter()
+            .flat_map_iter(|trajectory| self.m_hasher.multi_hash(trajectory))
+            .collect();
+        VCodeArray::from_hashes(hashes.into_iter(), self.m_index.m_bits)
+    }
+
+    pub fn hash_queryset(&self, dataset: &[Trajectory]) -> VCodeArray<H::Hash> {
         VCodeArray::from_hashes(
             dataset
                 .iter()
@@ -190,6 +359,289 @@ where
             self.m_index.m_bits,
         )
     }
+
+    /// Finds the k trajectories of `dataset` nearest to `query` by fréchet
+    /// distance, see `MartIndex::knn_query`
+    pub fn knn_query(
+        &self,
+        dataset: &[Trajectory],
+        query: &Trajectory,
+        k: usize,
+    ) -> DyFTKnnResult {
+        let qvcode: Vec<H::Hash> = self.m_hasher.multi_hash_query(query).collect();
+        self.m_index.knn_query(&qvcode, dataset, query, k)
+    }
+
+    pub fn knn_query_collect<V>(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        k: usize,
+    ) -> V
+    where
+        V: FromIterator<DyFTKnnResult>,
+    {
+        queryset
+            .iter()
+            .map(|query| self.knn_query(dataset, query, k))
+            .collect()
+    }
+
+    /// Hashes `trajectory`, stores its vcode in the index and appends it to
+    /// the trie, returning the id of the trajectory. Splits are resolved
+    /// against the stored vcodes, so an index filled this way must not also
+    /// be built or appended to from an external vcode database.
+    pub fn insert_trajectory(&mut self, trajectory: &Trajectory) -> u32 {
+        let vcode: Vec<H::Hash> = self.m_hasher.multi_hash(trajectory).collect();
+        let id = self.insert_vcode(&vcode);
+        if let Some(filter) = &mut self.m_index.m_bbox_filter {
+            filter.insert(id as usize, trajectory);
+        }
+        id
+    }
+
+    /// Fills the bounding box filter, if the config enables it, with the
+    /// trajectories the index is built over
+    pub fn index_bounding_boxes(&mut self, dataset: &[Trajectory]) {
+        if let Some(filter) = &mut self.m_index.m_bbox_filter {
+            *filter = BoundingBoxFilter::new(dataset);
+        }
+    }
+
+    /// Stores `vcode` in the index and appends it to the trie, like
+    /// `insert_trajectory` for a trajectory that is already hashed
+    pub fn insert_vcode(&mut self, vcode: &[H::Hash]) -> u32 {
+        assert_eq!(
+            self.m_vcodes.size(),
+            self.m_index.m_ids as usize,
+            "DyftIndex::insert_vcode: index was built from an external vcode database"
+        );
+        let id = self.m_vcodes.push(vcode.iter().copied());
+        self.m_index.append(self.m_vcodes.access(id), &self.m_vcodes);
+        id.try_into()
+            .expect("DyftIndex::insert_vcode: id does not fit in u32")
+    }
+
+    /// Moves trajectory `id` to its new `vcode`, e.g., after a point was
+    /// appended to a `StreamingTrajectory`. The trajectory keeps its id, it is
+    /// removed from the trie and appended again under the new vcode. Its
+    /// bounding box is refreshed with `update_bounding_box`.
+    pub fn update_trajectory(&mut self, id: u32, vcode: &[H::Hash]) {
+        assert!(
+            self.remove_trajectory(id),
+            "DyftIndex::update_trajectory: {id} is not in the index"
+        );
+        self.m_vcodes.set(id as usize, vcode);
+        self.m_index.reappend(id, &self.m_vcodes);
+    }
+
+    /// Refreshes the bounding box of trajectory `id` in the bounding box
+    /// filter, if enabled. A streamed trajectory grows with every appended
+    /// point, even when its vcode stays the same.
+    pub fn update_bounding_box(&mut self, id: u32, trajectory: &Trajectory) {
+        if let Some(filter) = &mut self.m_index.m_bbox_filter {
+            filter.insert(id as usize, trajectory);
+        }
+    }
+
+    /// Removes a trajectory inserted with `insert_trajectory`. Returns false
+    /// if `id` is not in the index.
+    pub fn remove_trajectory(&mut self, id: u32) -> bool {
+        (id as usize) < self.m_vcodes.size() && self.m_index.remove(id, &self.m_vcodes)
+    }
+
+    /// Returns the ids of the inserted trajectories whose vcodes are within
+    /// the hamming radius of the vcode of `trajectory`
+    pub fn query_trajectory(&self, trajectory: &Trajectory) -> Vec<u32> {
+        let query: Vec<H::Hash> = self.m_hasher.multi_hash_query(trajectory).collect();
+        self.m_index
+            .trie_search(&query)
+            .filter(|&candidate| {
+                self.m_vcodes.verify_candidate_predicate(
+                    candidate as usize,
+                    &query,
+                    self.m_index.m_radius,
+                )
+            })
+            .collect()
+    }
+}
+
+/// The trie is built over vcodes stored in the index, like the ones of
+/// `insert_trajectory`, so that inserts can follow the build
+impl<'a, H> SimilarityIndex for DyftIndex<'a, H>
+where
+    H: TrajectoryLsh + GetSize + Sync,
+    H::Hash: GetSize + Send + Sync,
+    [H::Hash]: Fill,
+    [(); H::Hash::N_DIM]:,
+{
+    type Params = MartConfig;
+    type Lsh = H;
+    type Stats = DyFTStats;
+    type Verification = DyFTPartialVerificationResult<Vec<(usize, usize)>>;
+
+    fn build(
+        config: &IndexConfig<MartConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        let mut index = Self::with_optional_hasher(config, hasher, dataset);
+        index.m_vcodes = index.hash_dataset(dataset);
+        index.m_index.build(&index.m_vcodes, index.m_vcodes.size());
+        index.index_bounding_boxes(dataset);
+        index
+    }
+
+    fn par_build(
+        config: &IndexConfig<MartConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        // the trie is built serially, only the hashing runs in parallel
+        let mut index = Self::with_optional_hasher(config, hasher, dataset);
+        index.m_vcodes = index.par_hash_dataset(dataset);
+        index.m_index.build(&index.m_vcodes, index.m_vcodes.size());
+        index.index_bounding_boxes(dataset);
+        index
+    }
+
+    fn build_with_memory_samples(
+        config: &IndexConfig<MartConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+        stats: &mut MasterStats<DyFTStats, IndexConfig<MartConfig>>,
+        samples: &[usize],
+    ) -> Self {
+        let mut index = Self::with_optional_hasher(config, hasher, dataset);
+        let vcodes = index.hash_dataset(dataset);
+        index.build_with_memory_samples(&vcodes, stats, samples);
+        index.m_vcodes = vcodes;
+        index.index_bounding_boxes(dataset);
+        index
+    }
+
+    fn load(
+        config: &IndexConfig<MartConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+        path: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        match hasher {
+            Some(hasher) => Self::load_with_hasher(config, hasher, dataset, path),
+            None => Self::load(config, dataset, path),
+        }
+    }
+
+    fn save(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        self.m_index.save::<H::Hash>(path, self.m_hasher.fingerprint())
+    }
+
+    fn insert(&mut self, trajectory: &Trajectory) -> usize {
+        self.insert_trajectory(trajectory) as usize
+    }
+
+    fn query(&self, queryset: &[Trajectory]) -> Vec<(usize, usize)> {
+        self.trie_query_collect(&self.m_vcodes, &self.hash_queryset(queryset))
+    }
+
+    fn par_query(&self, queryset: &[Trajectory]) -> Vec<(usize, usize)> {
+        self.par_trie_query_collect(&self.m_vcodes, &self.hash_queryset(queryset))
+    }
+
+    fn verification_distance(config: &IndexConfig<MartConfig>) -> Option<Distance> {
+        config.index_params().distance
+    }
+
+    fn verification_metric(config: &IndexConfig<MartConfig>) -> VerificationMetric {
+        config.index_params().metric
+    }
+
+    fn query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Self::Verification {
+        let qvcodes = self.hash_queryset(queryset);
+        self.trie_query_collect_with_verification(
+            &self.m_vcodes,
+            &qvcodes,
+            dataset,
+            queryset,
+            distance,
+        )
+    }
+
+    fn par_query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Self::Verification {
+        let qvcodes = self.hash_queryset(queryset);
+        self.par_trie_query_collect_with_verification(
+            &self.m_vcodes,
+            &qvcodes,
+            dataset,
+            queryset,
+            distance,
+        )
+    }
+
+    fn self_join(&self) -> Vec<(usize, usize)> {
+        self.self_join_collect(&self.m_vcodes)
+    }
+
+    fn self_join_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        distance: Distance,
+    ) -> Self::Verification {
+        self.self_join_collect_with_verification(&self.m_vcodes, dataset, distance)
+    }
+
+    fn query_results(
+        &self,
+        queryset: &[Trajectory],
+        pairs: &[(usize, usize)],
+        verification: Option<&Self::Verification>,
+    ) -> Vec<QueryResult> {
+        let qvcodes = self.hash_queryset(queryset);
+        self.query_results(
+            &self.m_vcodes,
+            &qvcodes,
+            pairs.iter().copied(),
+            verification.is_some(),
+        )
+    }
+
+    fn stats(&self) -> DyFTStats {
+        self.m_index.stats()
+    }
+}
+
+impl<'a, H> DyftIndex<'a, H>
+where
+    H: TrajectoryLsh + GetSize,
+    H::Hash: GetSize,
+    [H::Hash]: Fill,
+    [(); H::Hash::N_DIM]:,
+{
+    fn with_optional_hasher(
+        config: &IndexConfig<MartConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        match hasher {
+            Some(hasher) => Self::with_hasher(config, hasher),
+            None => Self::new(config, max_trajectory_length(dataset)),
+        }
+    }
+}
+
+fn max_trajectory_length(dataset: &[Trajectory]) -> usize {
+    dataset.iter().map(|t| t.len()).max().unwrap_or(0)
 }
 
 impl<'a, H> MartExporter for DyftIndex<'a, H>
@@ -210,7 +662,9 @@ where
     [(); H::Hash::N_DIM]:,
 {
     fn get_heap_size(&self) -> usize {
-        self.m_hasher.get_heap_size() + self.m_index.get_heap_size()
+        self.m_hasher.get_heap_size()
+            + self.m_index.get_heap_size()
+            + std::mem::size_of_val(self.m_vcodes.vcodes())
     }
 }
 
@@ -226,4 +680,321 @@ where
             heap_size: self.get_heap_size(),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_trajectories;
+    use crate::lsh::Linear64;
+
+    #[test]
+    fn insert_trajectory_matches_linear_search() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let queryset = random_trajectories(30, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(2)
+            .with_errors(2);
+
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        for (id, trajectory) in dataset.iter().enumerate() {
+            assert_eq!(index.insert_trajectory(trajectory), id as u32);
+        }
+        assert!(index.split_count() > 0);
+
+        let hasher = index.hasher();
+        let vcodes = VCodeArray::from_hashes(
+            dataset.iter().flat_map(|t| hasher.multi_hash(t)),
+            config.index.bits,
+        );
+        let qvcodes = VCodeArray::from_hashes(
+            queryset.iter().flat_map(|t| hasher.multi_hash_query(t)),
+            config.index.bits,
+        );
+        let expected = vcodes.linear_search(&qvcodes, 2).sorted().collect_vec();
+        let actual = queryset
+            .iter()
+            .enumerate()
+            .flat_map(|(q, query)| {
+                index
+                    .query_trajectory(query)
+                    .into_iter()
+                    .map(move |id| (q, id as usize))
+            })
+            .sorted()
+            .collect_vec();
+        assert!(!expected.is_empty());
+        assert_eq!(expected, actual);
+
+        let (_, id) = expected[0];
+        assert!(index.remove_trajectory(id as u32));
+        assert!(!index.remove_trajectory(id as u32));
+        assert!(!index.query_trajectory(&queryset[0]).contains(&(id as u32)));
+    }
+
+    #[test]
+    fn load_hashes_the_dataset_again() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(2)
+            .with_errors(2);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        dataset.iter().for_each(|trajectory| {
+            index.insert_trajectory(trajectory);
+        });
+
+        let path = std::env::temp_dir().join(format!("dyft-load-{}.idx", std::process::id()));
+        index.save(&path).unwrap();
+        assert!(DyftIndex::<Linear64>::load(&config, &dataset[1..], &path).is_err());
+        let mut loaded = DyftIndex::<Linear64>::load(&config, &dataset, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(index.query_trajectory(&dataset[7]), loaded.query_trajectory(&dataset[7]));
+        assert!(loaded.remove_trajectory(7));
+        assert!(!loaded.query_trajectory(&dataset[7]).contains(&7));
+        assert_eq!(loaded.insert_trajectory(&dataset[7]), dataset.len() as u32);
+        assert!(loaded.query_trajectory(&dataset[7]).contains(&(dataset.len() as u32)));
+    }
+
+    #[test]
+    fn self_join_returns_each_pair_once() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(2)
+            .with_errors(2);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        let vcodes = index.hash_dataset(&dataset);
+        index.build(&vcodes, vcodes.size());
+
+        let expected = vcodes
+            .linear_search(&vcodes, 2)
+            .filter(|&(a, b)| a < b)
+            .sorted()
+            .collect_vec();
+        let actual = index.self_join_collect::<Vec<_>>(&vcodes);
+        assert!(!expected.is_empty());
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+
+        let verified = index
+            .self_join_collect_with_verification::<Vec<_>>(&vcodes, &dataset, 0.5)
+            .results();
+        assert!(verified.iter().all(|pair| expected.contains(pair)));
+    }
+
+    #[test]
+    fn moved_trajectories_keep_their_ids() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let replacements = random_trajectories(50, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(0)
+            .with_errors(0);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        dataset.iter().for_each(|trajectory| {
+            index.insert_trajectory(trajectory);
+        });
+
+        for (id, trajectory) in replacements.iter().enumerate() {
+            let vcode = index.hasher().multi_hash(trajectory).collect_vec();
+            index.update_trajectory(id as u32, &vcode);
+        }
+        assert_eq!(index.size(), dataset.len());
+        assert_eq!(index.m_vcodes.size(), dataset.len());
+        for (id, trajectory) in replacements.iter().enumerate() {
+            assert!(index.query_trajectory(trajectory).contains(&(id as u32)));
+            assert!(!index.query_trajectory(&dataset[id]).contains(&(id as u32)));
+        }
+    }
+
+    #[test]
+    fn self_join_skips_removed_trajectories() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(2)
+            .with_errors(2);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        dataset.iter().for_each(|trajectory| {
+            index.insert_trajectory(trajectory);
+        });
+
+        let (a, b) = SimilarityIndex::self_join(&index)[0];
+        assert!(index.remove_trajectory(a as u32));
+        assert!(index.remove_trajectory(b as u32));
+        let pairs = SimilarityIndex::self_join(&index);
+        assert!(!pairs.is_empty());
+        assert!(pairs.iter().all(|&(x, y)| ![x, y].contains(&a) && ![x, y].contains(&b)));
+    }
+
+    #[test]
+    fn parallel_queries_match_sequential_queries() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(4)
+            .with_errors(4);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        let vcodes = index.hash_dataset(&dataset);
+        assert_eq!(vcodes.vcodes(), index.par_hash_dataset(&dataset).vcodes());
+        index.build(&vcodes, vcodes.size());
+        let qvcodes = index.hash_queryset(&queryset);
+
+        let expected = index.trie_query_collect::<Vec<_>>(&vcodes, &qvcodes);
+        assert!(!expected.is_empty());
+        assert_eq!(expected, index.par_trie_query_collect::<Vec<_>>(&vcodes, &qvcodes));
+
+        let expected = index.trie_query_collect_with_verification::<Vec<_>>(
+            &vcodes, &qvcodes, &dataset, &queryset, 0.5,
+        );
+        let actual = index.par_trie_query_collect_with_verification::<Vec<_>>(
+            &vcodes, &qvcodes, &dataset, &queryset, 0.5,
+        );
+        assert_eq!(
+            expected.full_verification_count(),
+            actual.full_verification_count()
+        );
+        assert_eq!(
+            expected.partial_verification_count(),
+            actual.partial_verification_count()
+        );
+        assert_eq!(
+            expected.filtered_verification_count(),
+            actual.filtered_verification_count()
+        );
+        assert_eq!(expected.results(), actual.results());
+    }
+
+    #[test]
+    fn per_query_distances_verify_each_query_with_its_own_threshold() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(8)
+            .with_errors(8);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        let vcodes = index.hash_dataset(&dataset);
+        index.build(&vcodes, vcodes.size());
+        let qvcodes = index.hash_queryset(&queryset);
+
+        let radii = Vec::from_iter((0..queryset.len()).map(|q| [0.1, 2.0][q % 2]));
+        let verify = |distance: f64| {
+            index
+                .trie_query_collect_with_verification::<Vec<_>>(
+                    &vcodes, &qvcodes, &dataset, &queryset, distance,
+                )
+                .results()
+        };
+        let (strict, loose) = (verify(0.1), verify(2.0));
+        let expected = Vec::from_iter(
+            loose
+                .iter()
+                .filter(|&&(q, _)| q % 2 == 1)
+                .chain(strict.iter().filter(|&&(q, _)| q % 2 == 0))
+                .copied()
+                .sorted(),
+        );
+        let actual = index
+            .par_trie_query_collect_with_verification::<Vec<_>>(
+                &vcodes,
+                &qvcodes,
+                &dataset,
+                &queryset,
+                radii.as_slice(),
+            )
+            .results();
+        assert_ne!(strict.len(), loose.len());
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+    }
+
+    #[test]
+    fn query_results_carry_hamming_distances() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(8)
+            .with_errors(8);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        let vcodes = index.hash_dataset(&dataset);
+        index.build(&vcodes, vcodes.size());
+        let qvcodes = index.hash_queryset(&queryset);
+
+        let pairs = index.trie_query_collect::<Vec<_>>(&vcodes, &qvcodes);
+        let results: Vec<QueryResult> = index.query_results(&vcodes, &qvcodes, pairs, false);
+        assert!(!results.is_empty());
+        for result in results {
+            let hamming = result.hamming_distance.unwrap();
+            assert!(hamming <= 8 && !result.verified);
+            assert!(vcodes.verify_candidate_predicate(
+                result.candidate,
+                qvcodes.access(result.query),
+                hamming
+            ));
+        }
+
+        let pairs = index
+            .trie_query_collect_with_verification::<Vec<_>>(
+                &vcodes, &qvcodes, &dataset, &queryset, 5.0,
+            )
+            .results();
+        let results: Vec<QueryResult> = index.query_results(&vcodes, &qvcodes, pairs, true);
+        assert!(results.iter().any(|result| result.verified));
+        for result in results.into_iter().filter(|result| result.verified) {
+            let result =
+                result.with_frechet_distance(&dataset, &queryset, VerificationMetric::default());
+            assert!(result.hamming_distance.unwrap() > 8 - 2);
+            assert!(queryset[result.query].frechet_decider(&dataset[result.candidate], 5.0));
+            assert!(result.frechet_distance.is_some());
+        }
+    }
+
+    #[test]
+    fn knn_query_ranks_by_frechet_distance() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let dataset = random_trajectories(60, 8, &mut rng);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4);
+        let mut index = DyftIndex::<Linear64>::new(&config, 8);
+        dataset.iter().for_each(|trajectory| {
+            index.insert_trajectory(trajectory);
+        });
+
+        let result = index.knn_query(&dataset, &dataset[3], 5);
+        assert_eq!(result.neighbours.len(), 5);
+        assert_eq!(result.neighbours[0], (3, 0.0));
+        assert!(result.neighbours.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(result.verifications >= 5 && result.states > 0);
+
+        // asking for every trajectory widens the search until all are verified
+        let result = index.knn_query(&dataset, &dataset[3], dataset.len());
+        let expected = dataset
+            .iter()
+            .map(|trajectory| dataset[3].frechet_distance(trajectory))
+            .enumerate()
+            .sorted_by(|(a_id, a), (b_id, b)| a.total_cmp(b).then(a_id.cmp(b_id)))
+            .collect_vec();
+        assert_eq!(result.verifications, dataset.len());
+        assert_eq!(result.neighbours, expected);
+    }
+}
@@ -26,6 +26,29 @@ pub enum MartInsertFlags {
     MartNeededToExpand,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MartRemoveFlags {
+    MartNotFound,
+    MartRemoved,
+    MartNeededToShrink,
+    MartNeededToFree,
+}
+
+impl MartRemoveFlags {
+    /// The flag for a node with room for `k` children that is left with `num`
+    /// children. A node is shrunk once at most a quarter of it is occupied, such
+    /// that it fills half of the next smaller node.
+    pub fn removed(num: usize, k: usize) -> Self {
+        if num == 0 {
+            MartRemoveFlags::MartNeededToFree
+        } else if num <= k / 4 {
+            MartRemoveFlags::MartNeededToShrink
+        } else {
+            MartRemoveFlags::MartRemoved
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, GetSize)]
 pub enum MartNodeTypes {
     MartLeafNode,
@@ -196,7 +219,7 @@ impl MartPointer {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MartCursor {
     pub offset: MartPointerOffset,
     pub pptr: MartPointer, // src pointer
@@ -4,6 +4,7 @@ use super::common::MartCursor;
 use super::common::MartEdge;
 use super::common::MartInsertFlags;
 use super::common::MartPointer;
+use super::common::MartRemoveFlags;
 use super::mart_vec::MartVec;
 use super::node_types::IntoMartNode;
 use super::offsets::MartDenseOffsets;
@@ -29,9 +30,12 @@ use crate::dyft::PopulationStatistics;
 use crate::dyft::PopulationStats;
 use get_size::GetSize;
 use num_traits::Zero;
+use serde::Deserialize;
+use serde::Serialize;
 use std::convert::AsRef;
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub struct MartDense<T> {
     inner: EmptyNodes<ByteLabels<MartVec<MartDenseOffsets<T>>>>,
 }
@@ -131,6 +135,49 @@ impl<T: IntoMartNode> MartArray<T> for MartDense<T> {
     fn update_srcptr(&mut self, mc: &MartCursor) {
         self.inner.update_src(mc);
     }
+
+    fn find_ptr(&self, mc: &mut MartCursor, label: u8) -> bool {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let node_id = mc.nptr.nid();
+        match self
+            .inner
+            .ptr_offset(node_id, label)
+            .and_then(|offset| self.inner.ptr(node_id, offset).map(|ptr| (offset, ptr)))
+        {
+            Some((offset, ptr)) => {
+                mc.update(offset, &ptr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_ptr(&mut self, mc: &MartCursor, label: u8) -> MartRemoveFlags {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let node_id = mc.nptr.nid();
+        if let Some(offset) = self.inner.ptr_offset(node_id, label) {
+            // move the last pointer into the removed pointer's slot
+            let last = usize::from(self.inner.header(node_id)) - 1;
+            if offset != last {
+                let last_label = (0..=u8::MAX)
+                    .find(|&l| self.inner.ptr_offset(node_id, l) == Some(last))
+                    .expect("MartArrayDense::remove_ptr: no label for the last ptr");
+                let last_ptr = self
+                    .inner
+                    .ptr(node_id, last)
+                    .expect("MartArrayDense::remove_ptr: last ptr is None");
+                self.inner
+                    .set_label(node_id, last_label.into(), offset as u8);
+                self.inner.set_ptr(node_id, offset, &last_ptr);
+            }
+            self.inner.set_label(node_id, label.into(), Self::NIL_IDX);
+            self.inner.clear_ptr(node_id, last);
+            self.inner.header_dec(node_id);
+            MartRemoveFlags::removed(last, T::BYTES)
+        } else {
+            MartRemoveFlags::MartNotFound
+        }
+    }
 }
 
 impl<T: IntoMartNode> MartArrayExtractable<T> for MartDense<T> {
@@ -236,3 +283,21 @@ impl<T: IntoMartNode> InNodeStatistics for MartDense<T> {
         self.inner.innode_stats()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dyft::MartNode64;
+
+    #[test]
+    fn children_include_the_last_label() {
+        let mut dense = MartDense::<MartNode64>::default();
+        let edges = Vec::from_iter([0, 200, 255].map(|label| MartEdge {
+            label,
+            ptr: MartPointer::leaf(label.into()),
+        }));
+        let ptr = dense.make_node_with_edges(edges);
+        let labels = Vec::from_iter(dense.children(&ptr).map(|edge| edge.label));
+        assert_eq!(labels, [0, 200, 255]);
+    }
+}
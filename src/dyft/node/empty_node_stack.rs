@@ -1,9 +1,9 @@
 use get_size::GetSize;
+use serde::Deserialize;
+use serde::Serialize;
 
-use super::mart_vec::MartVec;
 use super::traits::MartEmptyNodeStack;
 use super::traits::MartOffsets;
-use super::ByteLabels;
 use super::MartCursor;
 use super::MartPointer;
 use super::MartTrie;
@@ -16,7 +16,6 @@ use crate::dyft::PopulationStatistics;
 use crate::dyft::PopulationStats;
 use std::array::TryFromSliceError;
 use std::collections::HashSet;
-use std::convert::AsRef;
 use std::ops::Range;
 
 /// Wraps a MartByteVec and provides it with a stack of empty nodes.
@@ -25,7 +24,7 @@ use std::ops::Range;
 /// when extracting, and pop from the empty stack when inserting. The
 /// stack's data is kept as a doubly linked list in the underlying MartByteVec.
 ///
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct EmptyNodes<T> {
     // head node id
     m_head_nid: Option<u32>,
@@ -35,19 +34,21 @@ pub struct EmptyNodes<T> {
     pub(crate) inner: T,
 }
 
-impl<T> EmptyNodes<ByteLabels<MartVec<T>>>
+impl<T> EmptyNodes<T>
 where
-    T: MartOffsets, // AsRef<MartVec<T>> + AsMut<MartVec<T>>,
+    T: MartTrie,
 {
     pub fn iter_empty(&self) -> impl Iterator<Item = MartNodeId> {
         let mut v = HashSet::new();
         if let Some(head) = self.head() {
             v.insert(head);
-            while let Some(next) = self.next_ref(head) {
+            let mut node_id = head;
+            while let Some(next) = self.next_ref(node_id) {
                 if v.contains(&next) {
                     break;
                 }
                 v.insert(next);
+                node_id = next;
             }
         }
         v.into_iter()
@@ -76,9 +77,9 @@ impl<T: MartOffsets> MartOffsets for EmptyNodes<T> {
     const BYTES: usize = T::BYTES;
 }
 
-impl<T> EmptyNodes<ByteLabels<MartVec<T>>>
+impl<T> EmptyNodes<T>
 where
-    T: MartOffsets,
+    T: MartTrie,
 {
     fn map_node_ref(
         &self,
@@ -96,13 +97,13 @@ where
         node_id: MartNodeId,
         idx: Range<usize>,
     ) -> Result<MartNodeId, TryFromSliceError> {
-        self.inner.as_ref().node(node_id)[idx]
+        self.inner.node(node_id)[idx]
             .try_into()
             .map(|bytes| u32::from_le_bytes(bytes))
     }
 
     fn set_node_ref(&mut self, node_id: MartNodeId, idx: Range<usize>, value: MartNodeId) {
-        let node = self.inner.as_mut().node_mut(node_id);
+        let node = self.inner.node_mut(node_id);
         node[idx].copy_from_slice(&value.to_le_bytes());
     }
 
@@ -120,7 +121,7 @@ where
     }
     #[allow(dead_code)]
     fn reset_node(&mut self, node_id: u32) {
-        self.inner.as_mut().node_mut(node_id).fill(u8::MAX);
+        self.inner.node_mut(node_id).fill(u8::MAX);
     }
 
     pub fn empty_nodes_iter(&self) -> impl Iterator<Item = MartNodeId> {
@@ -141,9 +142,9 @@ where
     }
 }
 
-impl<T> MartEmptyNodeStack<u32> for EmptyNodes<ByteLabels<MartVec<T>>>
+impl<T> MartEmptyNodeStack<u32> for EmptyNodes<T>
 where
-    T: MartOffsets,
+    T: MartTrie,
 {
     fn empty_node_push(&mut self, value: u32) {
         if let Some(head) = self.m_head_nid {
@@ -172,8 +173,8 @@ where
                 self.set_next_ref(prev, next);
             } else {
                 self.m_head_nid = None;
-                self.m_num_empty = 0;
             }
+            self.m_num_empty -= 1;
             // reset rest of node
             // TODO: find out if we need to reset the node
             self.reset_node(head);
@@ -197,6 +198,12 @@ impl<T: MartTrie> MartTrie for EmptyNodes<T> {
     fn next_node_id(&self) -> u32 {
         self.inner.next_node_id()
     }
+    fn node(&self, node_id: MartNodeId) -> &[u8] {
+        self.inner.node(node_id)
+    }
+    fn node_mut(&mut self, node_id: MartNodeId) -> &mut [u8] {
+        self.inner.node_mut(node_id)
+    }
     fn header(&self, node_id: MartNodeId) -> u8 {
         self.inner.header(node_id)
     }
@@ -219,25 +226,24 @@ impl<T: MartTrie> MartTrie for EmptyNodes<T> {
     }
 }
 
-impl<T> TrieCounts for EmptyNodes<ByteLabels<MartVec<T>>>
+impl<T> TrieCounts for EmptyNodes<T>
 where
-    T: MartOffsets,
+    T: MartTrie + TrieCounts,
 {
     fn num_nodes(&self) -> usize {
-        self.inner.as_ref().num_nodes()
+        self.inner.num_nodes()
     }
 
     fn num_edges(&self) -> usize {
         let m_num: u32 = self
             .inner
-            .as_ref()
             .num_nodes()
             .try_into()
             .expect("overflow in MartByteVec::num_edges_total");
         let empty = self.iter_empty().collect::<HashSet<_>>();
         let mut count = 0;
         for node_id in (0..m_num).into_iter().filter(|i| !empty.contains(i)) {
-            count += <u8 as Into<usize>>::into(self.inner.as_ref().header(node_id));
+            count += <u8 as Into<usize>>::into(self.inner.header(node_id));
         }
         count
     }
@@ -247,7 +253,7 @@ where
     }
 }
 
-impl<T: MartOffsets> PopulationStatistics for EmptyNodes<ByteLabels<MartVec<T>>> {
+impl<T: MartTrie + TrieCounts> PopulationStatistics for EmptyNodes<T> {
     fn population_stats(&self) -> PopulationStats {
         let empty_nodes: HashSet<MartNodeId> = self.empty_nodes_iter().collect();
         let mut nodes = vec![0; T::BYTES + 1];
@@ -271,12 +277,13 @@ impl<T: MartOffsets> PopulationStatistics for EmptyNodes<ByteLabels<MartVec<T>>>
     }
 }
 
-impl<T: MartOffsets> InNodeStatistics for EmptyNodes<ByteLabels<MartVec<T>>> {
+impl<T: MartTrie + TrieCounts> InNodeStatistics for EmptyNodes<T> {
     fn innode_stats(&self) -> InNodeStats {
         InNodeStats {
             k: T::K,
             num: self.num_nodes(),
             empty: self.num_empty(),
+            shrunk: 0,
         }
     }
 }
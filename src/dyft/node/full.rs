@@ -4,11 +4,15 @@ use super::common::MartCursor;
 use super::common::MartEdge;
 use super::common::MartInsertFlags;
 use super::common::MartPointer;
+use super::common::MartRemoveFlags;
 use super::mart_vec::MartVec;
 use super::node_types::IntoMartNode;
 use super::offsets::MartFullOffsets;
+use super::traits::MartEmptyNodeStack;
+use super::EmptyNodes;
 use super::InnerMartBruteForce;
 use super::MartArray;
+use super::MartArrayExtractable;
 use super::MartBruteForce;
 use super::MartChildren;
 use super::MartFind;
@@ -20,30 +24,34 @@ use crate::dyft::InNodeStatistics;
 use crate::dyft::InNodeStats;
 use crate::dyft::MartNodeId;
 use get_size::GetSize;
+use num_traits::Zero;
+use serde::Deserialize;
+use serde::Serialize;
 
 /// Node Full is a data structure for very large k and consists of pointer array Ptr of
 /// length 256 such that Ptr[b] stores the child pointer with edge label b. The data structure
 /// is identical to the array form.
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub struct MartFull<T> {
-    inner: MartVec<MartFullOffsets<T>>,
+    inner: EmptyNodes<MartVec<MartFullOffsets<T>>>,
 }
 
 impl<'a, T: IntoMartNode> Default for MartFull<T> {
     fn default() -> Self {
         Self {
-            inner: MartVec::new(T::BYTES),
+            inner: EmptyNodes::new(MartVec::new(T::BYTES)),
         }
     }
 }
 
 impl<'a, T: IntoMartNode> MartFull<T> {
     pub fn internals(&self) -> &MartVec<MartFullOffsets<T>> {
-        &self.inner
+        &self.inner.inner
     }
 
     pub fn len(&self) -> usize {
-        self.inner.len()
+        self.inner.inner.len()
     }
     pub fn header(&self, node_id: MartNodeId) -> u8 {
         self.inner.header(node_id)
@@ -78,9 +86,13 @@ impl<'a, T: IntoMartNode> MartArray<T> for MartFull<T> {
     }
 
     fn make_node(&mut self) -> MartPointer {
-        let node_id = self.inner.next_node_id();
-        self.inner.create_node();
-        MartPointer::new(node_id, T::TYPE_ID)
+        // reuse empty node
+        if let Some(head) = self.inner.empty_node_pop() {
+            self.inner.header_mut(head).set_zero();
+            MartPointer::new(head, T::TYPE_ID)
+        } else {
+            MartPointer::new(self.inner.create_node(), T::TYPE_ID)
+        }
     }
 
     fn make_node_with_edges<E>(&mut self, edges: E) -> MartPointer
@@ -107,6 +119,49 @@ impl<'a, T: IntoMartNode> MartArray<T> for MartFull<T> {
     fn update_srcptr(&mut self, mc: &MartCursor) {
         self.inner.update_src(mc);
     }
+
+    fn find_ptr(&self, mc: &mut MartCursor, label: u8) -> bool {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let offset: usize = label.into();
+        match self.inner.ptr(mc.nptr.nid(), offset) {
+            Some(ptr) => {
+                mc.update(offset, &ptr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_ptr(&mut self, mc: &MartCursor, label: u8) -> MartRemoveFlags {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let node_id = mc.nptr.nid();
+        let offset: usize = label.into();
+        if self.inner.ptr(node_id, offset).is_some() {
+            self.inner.clear_ptr(node_id, offset);
+            self.inner.header_dec(node_id);
+            MartRemoveFlags::removed(self.inner.header(node_id).into(), T::BYTES)
+        } else {
+            MartRemoveFlags::MartNotFound
+        }
+    }
+}
+
+impl<T: IntoMartNode> MartArrayExtractable<T> for MartFull<T> {
+    fn append_ptr(
+        &mut self,
+        mc: &mut MartCursor,
+        label: u8,
+        nptr: &MartPointer,
+    ) -> MartInsertFlags {
+        self.insert_ptr(mc, label, nptr)
+    }
+
+    fn extract_edges(&mut self, mc: &MartCursor) -> Vec<MartEdge> {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let extract = self.children(mc.nptr()).collect();
+        self.inner.empty_node_push(mc.nptr.nid());
+        extract
+    }
 }
 
 impl<T: IntoMartNode> MartBruteForce for MartFull<T> {
@@ -136,13 +191,12 @@ impl<T: IntoMartNode> MartFind for &MartFull<T> {
 impl<T: IntoMartNode> MartChildren for MartFull<T> {
     fn children(&self, ptr: &MartPointer) -> impl Iterator<Item = MartEdge> {
         assert_eq!(ptr.ntype(), T::TYPE_ID);
-        self.inner
-            .ptr_iter(ptr.nid(), 0..256)
-            .enumerate()
-            .map(|(i, nptr)| MartEdge {
-                label: i.try_into().expect("overflow in MartFull::children"),
-                ptr: nptr,
-            })
+        let node_id = ptr.nid();
+        (0..=u8::MAX).filter_map(move |label| {
+            self.inner
+                .ptr(node_id, label.into())
+                .map(|nptr| MartEdge { label, ptr: nptr })
+        })
     }
 }
 
@@ -165,3 +219,24 @@ impl<T: IntoMartNode> InNodeStatistics for MartFull<T> {
         self.inner.innode_stats()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dyft::MartNode256;
+
+    #[test]
+    fn children_keep_their_labels() {
+        let mut full = MartFull::<MartNode256>::default();
+        let edges = Vec::from_iter([1, 200, 255].map(|label| MartEdge {
+            label,
+            ptr: MartPointer::leaf(label.into()),
+        }));
+        let ptr = full.make_node_with_edges(edges);
+        let children = Vec::from_iter(full.children(&ptr).map(|edge| (edge.label, edge.ptr)));
+        assert_eq!(
+            children,
+            [1, 200, 255].map(|label| (label, MartPointer::leaf(label.into())))
+        );
+    }
+}
@@ -12,11 +12,13 @@ use super::MartTrie;
 use super::RawMartPointer;
 use super::TrieCounts;
 use crate::dyft::*;
+use serde::Deserialize;
+use serde::Serialize;
 use std::convert::AsMut;
 use std::convert::AsRef;
 
 /// Wraps a MartByteVec and provides it with a MartByteLabel implementation
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct ByteLabels<T> {
     pub(crate) inner: T,
 }
@@ -101,7 +103,7 @@ impl<T: IntoMartNode> MartByteLabels for ByteLabels<MartVec<MartDenseOffsets<T>>
 
     fn label_iter(&self, node_id: MartNodeId) -> impl Iterator<Item = MartByteLabel> {
         let node = self.inner.node_without_header(node_id);
-        (0..MartDense::<T>::NIL_IDX).filter(|&i| node[MartPointerOffset::from(i)] != MartDense::<T>::NIL_IDX)
+        (0..=u8::MAX).filter(|&i| node[MartPointerOffset::from(i)] != MartDense::<T>::NIL_IDX)
     }
 
     fn label(&self, node_id: u32, idx: MartPointerOffset) -> Option<MartByteLabel> {
@@ -151,6 +153,14 @@ impl<T: MartTrie> MartTrie for ByteLabels<T> {
         self.inner.next_node_id()
     }
 
+    fn node(&self, node_id: MartNodeId) -> &[u8] {
+        self.inner.node(node_id)
+    }
+
+    fn node_mut(&mut self, node_id: MartNodeId) -> &mut [u8] {
+        self.inner.node_mut(node_id)
+    }
+
     fn header(&self, node_id: MartNodeId) -> u8 {
         self.inner.header(node_id)
     }
@@ -13,6 +13,8 @@ use crate::dyft::InNodeStats;
 use crate::dyft::MartNodeId;
 use get_size::GetSize;
 use num_traits::AsPrimitive;
+use serde::Deserialize;
+use serde::Serialize;
 use std::array::TryFromSliceError;
 use std::convert::AsMut;
 use std::convert::AsRef;
@@ -21,7 +23,7 @@ use std::marker::PhantomData;
 const RAW_MART_NIL_PTR: RawMartPointer = [255; MART_PTR_SIZE];
 
 /// A byte vector for storing nodes in a tree
-#[derive(Debug, GetSize)]
+#[derive(Debug, GetSize, Serialize, Deserialize)]
 pub struct MartVec<T> {
     pub m_nodes: Vec<u8>,
     t_phantom: PhantomData<T>,
@@ -45,21 +47,11 @@ impl<T: MartOffsets> MartVec<T> {
         self.m_nodes.len() / T::BYTES
     }
 
-    pub fn node(&self, node_id: MartNodeId) -> &[u8] {
-        let start = self.header_index(node_id);
-        &self.m_nodes[start..start + T::BYTES]
-    }
-
     pub fn node_without_header(&self, node_id: MartNodeId) -> &[u8] {
         let start = self.header_index(node_id) + 1;
         &self.m_nodes[start..start + T::BYTES - 1]
     }
 
-    pub fn node_mut(&mut self, node_id: MartNodeId) -> &mut [u8] {
-        let start = self.header_index(node_id);
-        &mut self.m_nodes[start..start + T::BYTES]
-    }
-
     pub fn bytes(&self) -> &[u8] {
         &self.m_nodes
     }
@@ -87,6 +79,16 @@ impl<T: MartOffsets> MartTrie for MartVec<T> {
         (self.m_nodes.len() / T::BYTES).as_()
     }
 
+    fn node(&self, node_id: MartNodeId) -> &[u8] {
+        let start = self.header_index(node_id);
+        &self.m_nodes[start..start + T::BYTES]
+    }
+
+    fn node_mut(&mut self, node_id: MartNodeId) -> &mut [u8] {
+        let start = self.header_index(node_id);
+        &mut self.m_nodes[start..start + T::BYTES]
+    }
+
     fn header(&self, node_id: MartNodeId) -> u8 {
         self.m_nodes[self.header_index(node_id)]
     }
@@ -168,6 +170,7 @@ impl<T: MartOffsets> InNodeStatistics for MartVec<T> {
             k: T::K,
             num: self.num_nodes(),
             empty: self.num_empty(),
+            shrunk: 0,
         }
     }
 }
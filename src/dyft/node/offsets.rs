@@ -1,5 +1,7 @@
 use std::marker::PhantomData;
 use get_size::GetSize;
+use serde::Deserialize;
+use serde::Serialize;
 
 use super::IntoMartNode;
 use super::MartOffsets;
@@ -9,13 +11,13 @@ use super::MART_PTR_SIZE;
 /// in the trie. The offsets are used to calculate the position of the pointers
 /// and labels in the node.  
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct MartSparseOffsets<T>(PhantomData<T>);
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct MartDenseOffsets<T>(PhantomData<T>);
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct MartFullOffsets<T>(PhantomData<T>);
 
 impl<T: IntoMartNode> MartOffsets for MartSparseOffsets<T> {
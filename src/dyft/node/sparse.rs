@@ -4,6 +4,8 @@ use super::common::MartCursor;
 use super::common::MartEdge;
 use super::common::MartInsertFlags;
 use super::common::MartPointer;
+use super::common::MartRemoveFlags;
+use super::common::MART_NIL_LABEL;
 use super::mart_vec::MartVec;
 use super::node_types::IntoMartNode;
 use super::offsets::MartSparseOffsets;
@@ -25,12 +27,15 @@ use crate::dyft::PopulationStatistics;
 use crate::dyft::PopulationStats;
 use core::simd::Simd;
 use get_size::GetSize;
-use nalgebra::SimdBool;
 use num_traits::Zero;
+use serde::Deserialize;
+use serde::Serialize;
+use std::simd::cmp::SimdPartialEq;
 use std::simd::LaneCount;
 use std::simd::SupportedLaneCount;
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub struct MartSparse<T>
 where
     T: IntoMartNode,
@@ -71,8 +76,9 @@ where
         let simd =
             Simd::<u8, { T::BYTES }>::from_array(self.inner.inner.inner.byte_labels_slice(node_id));
 
-        let mask = (simd == Simd::<u8, { T::BYTES }>::splat(label))
-            .bitmask()
+        let mask = simd
+            .simd_eq(Simd::<u8, { T::BYTES }>::splat(label))
+            .to_bitmask()
             .trailing_zeros()
             .try_into()
             .expect("MartSparse::adaptive_search: mask is too large");
@@ -158,6 +164,49 @@ where
     fn update_srcptr(&mut self, mc: &MartCursor) {
         self.inner.update_src(mc);
     }
+
+    fn find_ptr(&self, mc: &mut MartCursor, label: u8) -> bool {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let node_id = mc.nptr.nid();
+        match self
+            .inner
+            .ptr_offset(node_id, label)
+            .and_then(|offset| self.inner.ptr(node_id, offset).map(|ptr| (offset, ptr)))
+        {
+            Some((offset, ptr)) => {
+                mc.update(offset, &ptr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_ptr(&mut self, mc: &MartCursor, label: u8) -> MartRemoveFlags {
+        assert_eq!(mc.ntype(), T::TYPE_ID);
+        let node_id = mc.nptr.nid();
+        if let Some(offset) = self.inner.ptr_offset(node_id, label) {
+            // move the last edge into the removed edge's slot
+            let last = usize::from(self.inner.header(node_id)) - 1;
+            if offset != last {
+                let last_label = self
+                    .inner
+                    .label(node_id, last)
+                    .expect("MartArraySparse::remove_ptr: last label is None");
+                let last_ptr = self
+                    .inner
+                    .ptr(node_id, last)
+                    .expect("MartArraySparse::remove_ptr: last ptr is None");
+                self.inner.set_label(node_id, offset, last_label);
+                self.inner.set_ptr(node_id, offset, &last_ptr);
+            }
+            self.inner.set_label(node_id, last, MART_NIL_LABEL);
+            self.inner.clear_ptr(node_id, last);
+            self.inner.header_dec(node_id);
+            MartRemoveFlags::removed(last, T::BYTES)
+        } else {
+            MartRemoveFlags::MartNotFound
+        }
+    }
 }
 
 impl<T: IntoMartNode> MartArrayExtractable<T> for MartSparse<T>
@@ -242,3 +291,40 @@ impl<T: IntoMartNode> InNodeStatistics for MartSparse<T> {
         self.inner.innode_stats()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dyft::MartNode4;
+
+    fn leaf_edges(labels: &[u8]) -> Vec<MartEdge> {
+        Vec::from_iter(labels.iter().map(|&label| MartEdge {
+            label,
+            ptr: MartPointer::leaf(label.into()),
+        }))
+    }
+
+    #[test]
+    fn find_child_at_every_offset() {
+        let mut sparse = MartSparse::<MartNode4>::default();
+        let ptr = sparse.make_node_with_edges(leaf_edges(&[3, 7, 9]));
+        for label in [3, 7, 9] {
+            assert_eq!(sparse.find_child(ptr, label), Some(MartPointer::leaf(label.into())));
+        }
+        assert_eq!(sparse.find_child(ptr, 5), None);
+    }
+
+    #[test]
+    fn empty_node_stack_tracks_pushes_and_pops() {
+        let mut sparse = MartSparse::<MartNode4>::default();
+        let ptrs = Vec::from_iter((0..3).map(|_| sparse.make_node_with_edges(leaf_edges(&[1]))));
+        for ptr in ptrs.iter() {
+            sparse.extract_edges(&MartCursor::from_next(ptr));
+        }
+        assert_eq!(sparse.inner.iter_empty().count(), 3);
+        assert_eq!(sparse.inner.num_empty(), 3);
+        sparse.make_node();
+        assert_eq!(sparse.inner.iter_empty().count(), 2);
+        assert_eq!(sparse.inner.num_empty(), 2);
+    }
+}
@@ -4,7 +4,9 @@ use super::MartEdge;
 use super::MartInsertFlags;
 use super::MartNodeTypes;
 use super::MartPointer;
+use super::MartRemoveFlags;
 use super::RawMartPointer;
+use super::MART_NIL_LABEL;
 use super::MART_PTR_SIZE;
 use crate::dyft::BitPositionsEntry;
 use crate::dyft::HamTableEntry;
@@ -44,6 +46,11 @@ pub trait MartTrie: MartOffsets {
 
     fn update_src(&mut self, mc: &MartCursor);
 
+    // get the bytes of a node, including its header
+    fn node(&self, node_id: MartNodeId) -> &[u8];
+
+    fn node_mut(&mut self, node_id: MartNodeId) -> &mut [u8];
+
     // get the header of a node
     fn header(&self, node_id: MartNodeId) -> MartByteLabel;
 
@@ -52,6 +59,11 @@ pub trait MartTrie: MartOffsets {
         *header += 1;
     }
 
+    fn header_dec(&mut self, node_id: MartNodeId) {
+        let header = self.header_mut(node_id);
+        *header -= 1;
+    }
+
     // get a mutable reference to the header of a node
     fn header_mut(&mut self, node_id: MartNodeId) -> &mut u8;
 
@@ -63,6 +75,13 @@ pub trait MartTrie: MartOffsets {
             raw.copy_from_slice(&value[..])
         }
     }
+    // resets the pointer at idx, such that ptr returns None
+    fn clear_ptr(&mut self, node_id: MartNodeId, idx: usize) {
+        if let Some(raw) = self.ptr_slice_mut(node_id, idx) {
+            raw.fill(MART_NIL_LABEL)
+        }
+    }
+
     fn ptr_iter<'a, I>(
         &'a self,
         node_id: MartNodeId,
@@ -88,6 +107,12 @@ pub trait MartArray<T: IntoMartNode> {
     fn update_srcptr(&mut self, mc: &MartCursor);
 
     fn find_child(&self, ptr: MartPointer, label: u8) -> Option<MartPointer>;
+
+    // moves the cursor to the child with the given label, if there is one
+    fn find_ptr(&self, mc: &mut MartCursor, label: u8) -> bool;
+
+    // removes the edge with the given label from the node at the cursor
+    fn remove_ptr(&mut self, mc: &MartCursor, label: u8) -> MartRemoveFlags;
 }
 
 pub trait MartArrayExtractable<T: IntoMartNode>: MartArray<T> {
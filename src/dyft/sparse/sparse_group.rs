@@ -1,7 +1,9 @@
 use get_size::GetSize;
+use serde::Deserialize;
+use serde::Serialize;
 
 // https://github.com/kampersanda/dyft/blob/master/include/sparse_group.hpp
-#[derive(Debug, GetSize)]
+#[derive(Debug, GetSize, Serialize, Deserialize)]
 pub struct SparseGroup {
     m_bitmap: u64,
     m_group: Vec<u32>,
@@ -121,6 +123,31 @@ impl SparseGroup {
         }
     }
 
+    /// Removes `data` from the group at `idx`, unsetting `idx` if it was the
+    /// last one. Returns false if `data` is not in the group.
+    pub fn remove(&mut self, idx: u32, data: u32) -> bool {
+        self.check_idx(idx);
+        if self.m_bitmap & (1 << idx) == 0 {
+            return false;
+        }
+        let bitmask = Self::bitmask(idx);
+        let howmany = (self.m_bitmap & bitmask).count_ones() as usize;
+        let totones = (self.m_bitmap).count_ones() as usize;
+        let start = totones + 1 + self.m_group[howmany] as usize;
+        let end = totones + 1 + self.m_group[howmany + 1] as usize;
+        match self.m_group[start..end].iter().position(|&id| id == data) {
+            None => false,
+            Some(_) if end - start == 1 => self.extract(idx).is_some(),
+            Some(pos) => {
+                self.m_group.remove(start + pos);
+                for i in howmany + 1..totones + 1 {
+                    self.m_group[i] -= 1;
+                }
+                true
+            }
+        }
+    }
+
     pub fn size(&self, idx: u32) -> u32 {
         self.check_idx(idx);
         if self.m_bitmap & (1 << idx) == 0 {
@@ -222,4 +249,25 @@ mod test {
         assert_eq!(truth.len(), len);
         assert_eq!(truth, &sparse_group.m_group.as_ref());
     }
+
+    #[test]
+    fn test_sparse_group_remove() {
+        let mut sparse_group = SparseGroup::default();
+        sparse_group.extend(3, [7, 8, 9]);
+        sparse_group.extend(1, [4]);
+        sparse_group.extend(5, [10, 11]);
+        assert!(sparse_group.remove(3, 8));
+        assert!(!sparse_group.remove(3, 8));
+        assert!(sparse_group.remove(1, 4));
+        assert_eq!(sparse_group.access(1), None);
+        assert_eq!(sparse_group.access(3), Some(&[7, 9][..]));
+        assert_eq!(sparse_group.access(5), Some(&[10, 11][..]));
+        assert!(sparse_group.remove(5, 10));
+        assert!(sparse_group.remove(5, 11));
+        assert!(sparse_group.remove(3, 7));
+        assert!(sparse_group.remove(3, 9));
+        assert_eq!(sparse_group.size(3), 0);
+        sparse_group.insert(2, 1);
+        assert_eq!(sparse_group.access(2), Some(&[1][..]));
+    }
 }
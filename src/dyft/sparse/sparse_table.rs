@@ -2,11 +2,13 @@
 #![allow(dead_code)]
 use std::fmt::Debug;
 use get_size::GetSize;
+use serde::Deserialize;
+use serde::Serialize;
 
 #[doc = "../../../doc/sparse_table.md"]
 use super::sparse_group::SparseGroup;
 
-#[derive(Debug, GetSize)]
+#[derive(Debug, GetSize, Serialize, Deserialize)]
 pub struct SparseTable {
     m_groups: Vec<SparseGroup>,
     m_size: u32,
@@ -88,6 +90,15 @@ impl SparseTable {
         );
         self.m_groups[Self::group_pos(idx)].extract(Self::group_mod(idx))
     }
+    pub fn remove(&mut self, idx: u32, data: u32) -> bool {
+        assert!(
+            idx < self.m_size,
+            "SparseTable::remove: idx out of bounds: {} >= {}",
+            idx,
+            self.m_size
+        );
+        self.m_groups[Self::group_pos(idx)].remove(Self::group_mod(idx), data)
+    }
     pub fn size(&self) -> u32 {
         self.m_size
     }
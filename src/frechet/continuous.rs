@@ -0,0 +1,422 @@
+use crate::frechet::discrete_frechet_distance;
+use crate::point::Distance;
+use crate::point::PointMatrix;
+use crate::trajectory::Trajectory;
+
+/// A filter decides cheaply whether the continuous fréchet distance of two
+/// curves is at most a bound, or returns None if it cannot tell
+type FrechetFilter = fn(&[PointMatrix], &[PointMatrix], Distance) -> Option<bool>;
+
+/// The filters tried before the free space is explored, cheapest first
+const FILTERS: [FrechetFilter; 4] = [
+    endpoint_filter,
+    bounding_box_filter,
+    greedy_walk_filter,
+    equal_time_filter,
+];
+
+/// Decides whether the continuous fréchet distance of `t1` and `t2` is at
+/// most `bound`, following Bringmann et al. - 2019 - Walking the Dog Fast in
+/// Practice. A cascade of filters settles most pairs, the rest are decided
+/// by the reachability of the end in the free space diagram. Empty curves
+/// are never within the bound.
+pub fn continuous_frechet_decider(t1: &Trajectory, t2: &Trajectory, bound: Distance) -> bool {
+    let (p, q) = (t1.as_ref(), t2.as_ref());
+    if p.is_empty() || q.is_empty() {
+        return false;
+    }
+    FILTERS
+        .iter()
+        .find_map(|filter| filter(p, q, bound))
+        .unwrap_or_else(|| free_space_decider(p, q, bound))
+}
+
+/// The continuous fréchet distance of `t1` and `t2`, which is infinite if
+/// either of them is empty
+pub fn continuous_frechet_distance(t1: &Trajectory, t2: &Trajectory) -> Distance {
+    continuous_frechet_distance_bounded(t1, t2, Distance::INFINITY).unwrap_or(Distance::INFINITY)
+}
+
+/// The continuous fréchet distance of `t1` and `t2`, or None if it exceeds
+/// `bound`. The distance is the smallest bound the decider accepts, found by
+/// bisecting between the distance of the endpoints and the discrete fréchet
+/// distance until the two ends are adjacent floats.
+pub fn continuous_frechet_distance_bounded(
+    t1: &Trajectory,
+    t2: &Trajectory,
+    bound: Distance,
+) -> Option<Distance> {
+    if !continuous_frechet_decider(t1, t2, bound) {
+        return None;
+    }
+    let (p, q) = (t1.as_ref(), t2.as_ref());
+    let mut lo = distance(&p[0], &q[0]).max(distance(&p[p.len() - 1], &q[q.len() - 1]));
+    if continuous_frechet_decider(t1, t2, lo) {
+        return Some(lo);
+    }
+    let mut hi = discrete_frechet_distance(t1, t2).min(bound);
+    loop {
+        let mid = lo + (hi - lo) / 2.0;
+        if mid <= lo || mid >= hi {
+            return Some(hi);
+        }
+        match continuous_frechet_decider(t1, t2, mid) {
+            true => hi = mid,
+            false => lo = mid,
+        }
+    }
+}
+
+fn distance(a: &PointMatrix, b: &PointMatrix) -> Distance {
+    (b - a).norm()
+}
+
+/// Rejects curves whose start or end points are too far apart, and decides
+/// exactly if either curve is a single point, which is then matched to every
+/// vertex of the other curve
+fn endpoint_filter(p: &[PointMatrix], q: &[PointMatrix], bound: Distance) -> Option<bool> {
+    if distance(&p[0], &q[0]) > bound || distance(&p[p.len() - 1], &q[q.len() - 1]) > bound {
+        return Some(false);
+    }
+    match (p, q) {
+        ([point], curve) | (curve, [point]) => {
+            Some(curve.iter().all(|vertex| distance(point, vertex) <= bound))
+        }
+        _ => None,
+    }
+}
+
+/// Rejects curves of which one leaves the bounding box of the other expanded
+/// by `bound`, as every point of a curve is matched to a point of the other
+fn bounding_box_filter(p: &[PointMatrix], q: &[PointMatrix], bound: Distance) -> Option<bool> {
+    let bounds = |curve: &[PointMatrix]| {
+        curve.iter().fold(
+            ([Distance::INFINITY; 2], [Distance::NEG_INFINITY; 2]),
+            |([x0, y0], [x1, y1]), point| {
+                (
+                    [x0.min(point.x), y0.min(point.y)],
+                    [x1.max(point.x), y1.max(point.y)],
+                )
+            },
+        )
+    };
+    let ([px0, py0], [px1, py1]) = bounds(p);
+    let ([qx0, qy0], [qx1, qy1]) = bounds(q);
+    let apart = (px0 - qx0).abs() > bound
+        || (py0 - qy0).abs() > bound
+        || (px1 - qx1).abs() > bound
+        || (py1 - qy1).abs() > bound;
+    apart.then_some(false)
+}
+
+/// Accepts curves that a greedy walk over their vertices couples within
+/// `bound`. Such a coupling bounds the discrete fréchet distance, which is at
+/// least the continuous one.
+fn greedy_walk_filter(p: &[PointMatrix], q: &[PointMatrix], bound: Distance) -> Option<bool> {
+    let (mut i, mut j) = (0, 0);
+    while i + 1 < p.len() || j + 1 < q.len() {
+        let steps = [(i + 1, j + 1), (i + 1, j), (i, j + 1)];
+        let (next_i, next_j, d) = steps
+            .into_iter()
+            .filter(|&(a, b)| a < p.len() && b < q.len())
+            .map(|(a, b)| (a, b, distance(&p[a], &q[b])))
+            .min_by(|a, b| a.2.total_cmp(&b.2))?;
+        if d > bound {
+            return None;
+        }
+        (i, j) = (next_i, next_j);
+    }
+    Some(true)
+}
+
+/// Accepts curves that an adaptive equal-time traversal couples within
+/// `bound`. The traversal moves from vertex pair to vertex pair, and covers
+/// the pieces of the curves in between at equal time. From the current pair
+/// it first tries to reach the ends of both curves, and on every failure
+/// tries half as much of what remains of each, down to a single segment.
+/// After every piece it covers it tries twice as much again.
+fn equal_time_filter(p: &[PointMatrix], q: &[PointMatrix], bound: Distance) -> Option<bool> {
+    let (p_length, q_length) = (arc_lengths(p), arc_lengths(q));
+    let (p_end, q_end) = (p.len() - 1, q.len() - 1);
+    let (mut i, mut j) = (0, 0);
+    let mut fraction: f64 = 1.0;
+    while i < p_end || j < q_end {
+        let next_i = i + ((p_end - i) as f64 * fraction).ceil() as usize;
+        let next_j = j + ((q_end - j) as f64 * fraction).ceil() as usize;
+        let covered = equal_time_piece(
+            (&p[i..=next_i], &p_length[i..=next_i]),
+            (&q[j..=next_j], &q_length[j..=next_j]),
+            bound,
+        );
+        if covered {
+            (i, j) = (next_i, next_j);
+            fraction = (fraction * 2.0).min(1.0);
+        } else if next_i - i <= 1 && next_j - j <= 1 {
+            return None;
+        } else {
+            fraction /= 2.0;
+        }
+    }
+    Some(true)
+}
+
+/// Whether two pieces of curves stay within `bound` while both are traversed
+/// at constant speed, given with the arc lengths of their vertices. Between
+/// the vertices of either piece both points move linearly, so their distance
+/// is largest at the vertices.
+fn equal_time_piece(
+    (p, p_length): (&[PointMatrix], &[Distance]),
+    (q, q_length): (&[PointMatrix], &[Distance]),
+    bound: Distance,
+) -> bool {
+    let (p_start, q_start) = (p_length[0], q_length[0]);
+    let (p_total, q_total) = (p_length[p.len() - 1] - p_start, q_length[q.len() - 1] - q_start);
+    // a piece without length stays at its first vertex
+    if p_total == 0.0 {
+        return q.iter().all(|vertex| distance(&p[0], vertex) <= bound);
+    }
+    if q_total == 0.0 {
+        return p.iter().all(|vertex| distance(vertex, &q[0]) <= bound);
+    }
+    let (mut i, mut j) = (0, 0);
+    loop {
+        // the next breakpoint is the nearest vertex of either piece
+        let p_time = (p_length[i] - p_start) / p_total;
+        let q_time = (q_length[j] - q_start) / q_total;
+        let time = p_time.min(q_time);
+        let position = |curve: &[PointMatrix], length: &[Distance], total: Distance, k: usize| {
+            if k == 0 || length[k] == length[k - 1] {
+                return curve[k];
+            }
+            let t = (time * total + length[0] - length[k - 1]) / (length[k] - length[k - 1]);
+            curve[k - 1] + (curve[k] - curve[k - 1]) * t.clamp(0.0, 1.0)
+        };
+        let d = distance(
+            &position(p, p_length, p_total, i),
+            &position(q, q_length, q_total, j),
+        );
+        if d > bound {
+            return false;
+        }
+        if i + 1 == p.len() && j + 1 == q.len() {
+            return true;
+        }
+        if p_time <= time && i + 1 < p.len() {
+            i += 1;
+        }
+        if q_time <= time && j + 1 < q.len() {
+            j += 1;
+        }
+    }
+}
+
+/// The length of the curve up to each of its vertices
+fn arc_lengths(curve: &[PointMatrix]) -> Vec<Distance> {
+    let mut length = 0.0;
+    Vec::from_iter(curve.iter().enumerate().map(|(k, point)| {
+        if k > 0 {
+            length += distance(&curve[k - 1], point);
+        }
+        length
+    }))
+}
+
+/// The parameters `t` in [0, 1] of the points `a + t (b - a)` within `bound`
+/// of `c`, which form an interval as discs are convex. The ends of the
+/// segment are compared like the vertices elsewhere, so that rounding never
+/// cuts off a vertex within the bound.
+fn free_interval(
+    a: &PointMatrix,
+    b: &PointMatrix,
+    c: &PointMatrix,
+    bound: Distance,
+) -> Option<(Distance, Distance)> {
+    let (a_free, b_free) = (distance(a, c) <= bound, distance(b, c) <= bound);
+    if a_free && b_free {
+        return Some((0.0, 1.0));
+    }
+    let (d, f) = (b - a, a - c);
+    let (qa, qb, qc) = (
+        d.norm_squared(),
+        2.0 * f.dot(&d),
+        f.norm_squared() - bound * bound,
+    );
+    let discriminant = qb * qb - 4.0 * qa * qc;
+    if qa == 0.0 || (discriminant < 0.0 && !a_free && !b_free) {
+        return None;
+    }
+    let root = discriminant.max(0.0).sqrt();
+    let (mut lo, mut hi) = ((-qb - root) / (2.0 * qa), (-qb + root) / (2.0 * qa));
+    if a_free {
+        (lo, hi) = (0.0, hi.max(0.0));
+    }
+    if b_free {
+        (lo, hi) = (lo.min(1.0), 1.0);
+    }
+    let (lo, hi) = (lo.max(0.0), hi.min(1.0));
+    (lo <= hi).then_some((lo, hi))
+}
+
+/// Decides the continuous fréchet distance by propagating the reachable
+/// parts of the cell boundaries of the free space diagram (Alt and Godau),
+/// one row of cells at a time. Row `j` holds the cells of segment `j` of `q`
+/// against the segments of `p`. Both curves have at least two points and
+/// the start and end points are within `bound`.
+fn free_space_decider(p: &[PointMatrix], q: &[PointMatrix], bound: Distance) -> bool {
+    let (n, m) = (p.len(), q.len());
+    // the reachable parts of the bottom boundaries of the current row, which
+    // are the segments of p against vertex j of q
+    let mut bottom = Vec::with_capacity(n - 1);
+    let mut reachable = true;
+    for i in 0..n - 1 {
+        let interval = reachable
+            .then(|| free_interval(&p[i], &p[i + 1], &q[0], bound))
+            .flatten()
+            .filter(|&(lo, _)| lo == 0.0);
+        reachable = interval.is_some_and(|(_, hi)| hi == 1.0);
+        bottom.push(interval);
+    }
+    // the reachable part of the left boundary of the first cell of the row
+    let mut left_reachable = true;
+    for j in 0..m - 1 {
+        let mut left = left_reachable
+            .then(|| free_interval(&q[j], &q[j + 1], &p[0], bound))
+            .flatten()
+            .filter(|&(lo, _)| lo == 0.0);
+        left_reachable = left.is_some_and(|(_, hi)| hi == 1.0);
+        let mut any = left.is_some();
+        for i in 0..n - 1 {
+            let top = free_interval(&p[i], &p[i + 1], &q[j + 1], bound);
+            let right = free_interval(&q[j], &q[j + 1], &p[i + 1], bound);
+            let (new_top, new_right) = match (bottom[i], left) {
+                (None, None) => (None, None),
+                (Some((b, _)), None) => (
+                    top.filter(|&(_, hi)| hi >= b)
+                        .map(|(lo, hi)| (lo.max(b), hi)),
+                    right,
+                ),
+                (None, Some((l, _))) => (
+                    top,
+                    right
+                        .filter(|&(_, hi)| hi >= l)
+                        .map(|(lo, hi)| (lo.max(l), hi)),
+                ),
+                (Some(_), Some(_)) => (top, right),
+            };
+            bottom[i] = new_top;
+            left = new_right;
+            any |= new_top.is_some() || new_right.is_some();
+        }
+        if !any {
+            return false;
+        }
+        if j + 2 == m {
+            return left.is_some_and(|(_, hi)| hi == 1.0)
+                || bottom[n - 2].is_some_and(|(_, hi)| hi == 1.0);
+        }
+    }
+    unreachable!("curves with at least two points have a row of cells")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_curve;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    /// The curve with every segment split into `parts` segments
+    fn resample(curve: &Trajectory, parts: usize) -> Trajectory {
+        let points = curve.as_ref();
+        let inner = points.windows(2).flat_map(|segment| {
+            (0..parts)
+                .map(move |k| segment[0] + (segment[1] - segment[0]) * (k as f64 / parts as f64))
+        });
+        inner.chain(points.last().copied()).collect()
+    }
+
+    #[test]
+    fn decider_matches_the_discrete_distance_of_resampled_curves() {
+        // resampling with segments of length at most s brings the discrete
+        // distance within s of the continuous one, which it bounds from above
+        let mut rng = StdRng::seed_from_u64(3);
+        let parts = 16;
+        let slack = 4.0 * 2f64.sqrt() / parts as f64;
+        for _ in 0..300 {
+            let (t1, t2) = (random_curve(7, 4.0, &mut rng), random_curve(7, 4.0, &mut rng));
+            let upper = discrete_frechet_distance(&resample(&t1, parts), &resample(&t2, parts));
+            assert!(continuous_frechet_decider(
+                &t1,
+                &t2,
+                discrete_frechet_distance(&t1, &t2)
+            ));
+            assert!(continuous_frechet_decider(&t1, &t2, upper));
+            assert!(!continuous_frechet_decider(&t1, &t2, upper - slack));
+            assert_eq!(
+                continuous_frechet_decider(&t1, &t2, upper),
+                continuous_frechet_decider(&t2, &t1, upper)
+            );
+
+            let distance = continuous_frechet_distance(&t1, &t2);
+            assert!(distance <= upper && distance >= upper - slack);
+            assert!(continuous_frechet_decider(&t1, &t2, distance));
+            assert!(!continuous_frechet_decider(&t1, &t2, distance * (1.0 - 1e-9)));
+            assert_eq!(continuous_frechet_distance_bounded(&t1, &t2, distance), Some(distance));
+            assert_eq!(continuous_frechet_distance_bounded(&t1, &t2, distance * 0.99), None);
+        }
+    }
+
+    #[test]
+    fn filter_cascade_agrees_with_the_free_space() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut decided = [0; FILTERS.len()];
+        for _ in 0..1000 {
+            let (t1, t2) = (random_curve(7, 4.0, &mut rng), random_curve(7, 4.0, &mut rng));
+            let (p, q) = (t1.as_ref(), t2.as_ref());
+            if p.len() < 2 || q.len() < 2 {
+                continue;
+            }
+            let bound = rng.gen_range(0.5..4.0);
+            let expected =
+                endpoint_filter(p, q, bound) != Some(false) && free_space_decider(p, q, bound);
+            // each filter is only asked about the pairs the ones before it
+            // left undecided
+            let decision = FILTERS
+                .iter()
+                .enumerate()
+                .find_map(|(k, filter)| filter(p, q, bound).map(|decision| (k, decision)));
+            if let Some((k, decision)) = decision {
+                assert_eq!(decision, expected);
+                decided[k] += 1;
+            }
+        }
+        assert!(decided.iter().all(|&count| count > 0));
+    }
+
+    #[test]
+    fn equal_time_filter_adapts_to_the_vertices() {
+        // the corners are 0.38 apart when both curves are traversed at equal
+        // time from start to end, but only 0.3 apart piece by piece
+        let p = [[0.0, 0.0], [4.0, 0.0], [4.0, 1.0]].map(PointMatrix::from);
+        let q = [[0.0, 0.3], [4.0, 0.3], [4.0, 1.0]].map(PointMatrix::from);
+        let (p_length, q_length) = (arc_lengths(&p), arc_lengths(&q));
+        assert!(!equal_time_piece((&p, &p_length), (&q, &q_length), 0.35));
+        assert_eq!(equal_time_filter(&p, &q, 0.35), Some(true));
+        assert_eq!(equal_time_filter(&p, &q, 0.25), None);
+    }
+
+    #[test]
+    fn continuous_distance_is_below_the_discrete_one() {
+        let t1 = Trajectory::from([[0.0, 0.0], [2.0, 0.0]]);
+        let t2 = Trajectory::from([[0.0, 1.0], [1.0, 1.0], [2.0, 1.0]]);
+        assert!(continuous_frechet_decider(&t1, &t2, 1.0));
+        assert!(!continuous_frechet_decider(&t1, &t2, 0.99));
+        assert_eq!(discrete_frechet_distance(&t1, &t2), 2f64.sqrt());
+
+        // a curve doubling back is far from a straight one
+        let t3 = Trajectory::from([[0.0, 0.0], [2.0, 0.0], [1.0, 0.0], [2.0, 0.0]]);
+        assert!(continuous_frechet_decider(&t1, &t3, 0.5));
+        assert!(!continuous_frechet_decider(&t1, &t3, 0.49));
+    }
+}
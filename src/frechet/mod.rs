@@ -1,220 +1,105 @@
+use crate::metric::VerificationMetric;
 use crate::point::PointMatrix;
+use crate::point::QueryDistance;
 use crate::trajectory::Trajectory;
-use geo::FrechetDistance;
 
-fn euclidean(p1: &PointMatrix, p2: &PointMatrix) -> f64 {
-    (p2 - p1).norm_squared()
-}
-
-// fn c(ca: &mut Vec<Vec<f64>>, a: &Trajectory, b: &Trajectory, i: usize, j: usize) -> f64 {
-//     if ca[i][j] > -1.0 {
-//         return ca[i][j];
-//     }
-//     if i == 0 && j == 0 {
-//         ca[i][j] = euclidean(&a[0], &b[0]);
-//     } else if i > 0 && j == 0 {
-//         let rec = c(ca, a, b, i - 1, 0);
-//         ca[i][j] = euclidean(&a[i], &b[0]).max(rec);
-//     } else if i == 0 && j > 0 {
-//         let rec = c(ca, a, b, 0, j - 1);
-//         ca[i][j] = euclidean(&a[0], &b[j]).max(rec);
-//     } else {
-//         let rec_a = c(ca, a, b, i - 1, j - 1);
-//         let rec_b = c(ca, a, b, i - 1, j);
-//         let rec_c = c(ca, a, b, i, j - 1);
-//         ca[i][j] = rec_a.min(rec_b).min(rec_c).max(euclidean(&a[i], &b[j]));
-//     }
-//     ca[i][j]
-// }
-
-// // https://github.com/Cecca/FRESH/blob/master/core/frechet.cpp#L53
-// pub fn discrete_frechet_distance_r(t1: &Trajectory, t2: &Trajectory) -> f64 {
-//     c(
-//         &mut vec![vec![-1.0; t2.len()]; t1.len()],
-//         t1,
-//         t2,
-//         t1.len() - 1,
-//         t2.len() - 1,
-//     )
-// }
-
-// // https://github.com/Cecca/FRESH/blob/master/core/frechet.h#L80C8-L80C33
-// pub fn discrete_frechet_distance(t1: &Trajectory, t2: &Trajectory) -> f64 {
-//     let t1_len = t1.len();
-//     let mut prev_row = vec![0.; t1_len];
-//     let mut current_row = vec![0.; t1_len];
-//     current_row[0] = euclidean(&t1[0], &t2[0]);
-
-//     for i in 1..t1.len() {
-//         current_row[i] = euclidean(&t1[i], &t2[0]).max(current_row[i - 1]);
-//     }
-
-//     for j in 1..t2.len() {
-//         current_row.swap_with_slice(&mut prev_row[..]);
-//         current_row[0] = euclidean(&t1[0], &t2[j]).max(prev_row[0]);
+mod continuous;
 
-//         for i in 1..t1_len {
-//             current_row[i] = euclidean(&t1[i], &t2[j])
-//                 .max(current_row[i - 1])
-//                 .min(prev_row[i])
-//                 .min(prev_row[i - 1]);
-//         }
-//     }
+pub use continuous::continuous_frechet_decider;
+pub use continuous::continuous_frechet_distance;
+pub use continuous::continuous_frechet_distance_bounded;
 
-//     current_row[t1.len() - 1].sqrt()
-// }
-
-// const fn pair_to_idx(i: usize, j: usize, n: usize) -> usize {
-//     i * n + j
-// }
-
-// const fn idx_to_pair(idx: usize, n: usize) -> (usize, usize) {
-//     (idx / n, idx % n)
-// }
-
-fn discrete_frechet_distance_predicate_r(p1: &PointMatrix, p2: &PointMatrix, bound: f64) -> bool {
-    euclidean(p1, p2) > bound
+fn euclidean(p1: &PointMatrix, p2: &PointMatrix) -> f64 {
+    (p2 - p1).norm()
 }
 
 fn start_end_heuristic(t1: &Trajectory, t2: &Trajectory, bound: f64) -> bool {
-    discrete_frechet_distance_predicate_r(&t1[0], &t2[0], bound)
-        || discrete_frechet_distance_predicate_r(&t1[t1.len() - 1], &t2[t2.len() - 1], bound)
-}
-
-// fn reverse_start_end_heiristic(t1: &Trajectory, t2: &Trajectory, bound: f64) -> bool {
-//     discrete_frechet_distance_predicate_r(&t1[0], &t2[t2.len() - 1], bound)
-//         || discrete_frechet_distance_predicate_r(&t1[t1.len() - 1], &t2[0], bound)
-// }
-
-fn iter_indices_heuristic(
-    mut indices: impl Iterator<Item = (usize, usize)>,
-    t1: &Trajectory,
-    t2: &Trajectory,
-    bound: f64,
-) -> bool {
-    indices.any(|(i, j)| discrete_frechet_distance_predicate_r(&t1[i], &t2[j], bound))
+    euclidean(&t1[0], &t2[0]) > bound || euclidean(&t1[t1.len() - 1], &t2[t2.len() - 1]) > bound
 }
 
+/// Whether the discrete fréchet distance of `t1` and `t2` is at most `bound`
 pub fn discrete_frechet_distance_predicate(t1: &Trajectory, t2: &Trajectory, bound: f64) -> bool {
-    if start_end_heuristic(t1, t2, bound) {
-        return false;
-    }
+    discrete_frechet_decider(t1, t2, bound)
+}
 
-    if iter_indices_heuristic(
-        (1..t1.len() - 1)
-            .map(|i| (i, 0))
-            .chain((1..t2.len() - 1).map(|j| (t1.len() - 1, j))),
-        t1,
-        t2,
-        bound,
-    ) {
+/// Decides whether the discrete fréchet distance of `t1` and `t2` is at most
+/// `bound`, with a dynamic program over the couplings of the two curves that
+/// keeps two rows of reachable cells. The decision is made as soon as no
+/// cell of a row is reachable. Empty curves are never within the bound.
+pub fn discrete_frechet_decider(t1: &Trajectory, t2: &Trajectory, bound: f64) -> bool {
+    let (n, m) = (t1.len(), t2.len());
+    if n == 0 || m == 0 || start_end_heuristic(t1, t2, bound) {
         return false;
     }
-    if iter_indices_heuristic(
-        (1..t2.len() - 1)
-            .map(|i| (0, i))
-            .chain((1..t1.len() - 1).map(|j| (j, t2.len() - 1))),
-        t1,
-        t2,
-        bound,
-    ) {
-        return false;
+    let mut rows = vec![false; 2 * m];
+    let (mut prev_row, mut current_row) = rows.split_at_mut(m);
+    for i in 0..n {
+        let mut reachable = false;
+        for j in 0..m {
+            current_row[j] = euclidean(&t1[i], &t2[j]) <= bound
+                && match (i, j) {
+                    (0, 0) => true,
+                    (0, _) => current_row[j - 1],
+                    (_, 0) => prev_row[0],
+                    _ => prev_row[j] || prev_row[j - 1] || current_row[j - 1],
+                };
+            reachable |= current_row[j];
+        }
+        if !reachable {
+            return false;
+        }
+        std::mem::swap(&mut prev_row, &mut current_row);
     }
-
-    t1.line_string().frechet_distance(&t2.line_string()) <= bound
-
-    // let k = pair_to_idx(t1_len - 1, t2_len - 1, t2_len);
-    // visited[k] = true;
-    // stack.push(k);
-
-    // while let Some(k) = stack.pop() {
-    //     let (i, j) = idx_to_pair(k, t2_len);
-
-    //     if i == 0 && j == 0 {
-    //         return true;
-    //         // return t1.line_string().frechet_distance(&t2.line_string()) <= bound;
-    //     }
-
-    //     if i > 0 && j > 0 {
-    //         let k = pair_to_idx(i - 1, j - 1, t2_len);
-    //         if !visited[k] && !discrete_frechet_distance_predicate_r(&t1[i - 1], &t2[j - 1], bound)
-    //         {
-    //             // let d = ;
-    //             if euclidean(&t1[i], &t2[j]) <= bound {
-    //                 visited[k] = true;
-    //                 stack.push(k);
-    //             }
-    //         }
-    //     }
-
-    //     if i > 0 {
-    //         let k = pair_to_idx(i - 1, j, t2_len);
-    //         if !visited[k] && !discrete_frechet_distance_predicate_r(&t1[i - 1], &t2[j], bound) {
-    //             // let d = ;
-    //             if euclidean(&t1[i], &t2[j]) <= bound {
-    //                 visited[k] = true;
-    //                 stack.push(k);
-    //             }
-    //         }
-    //     }
-
-    //     if j > 0 {
-    //         let k = pair_to_idx(i, j - 1, t2_len);
-    //         if !visited[k] && !discrete_frechet_distance_predicate_r(&t1[i], &t2[j - 1], bound) {
-    //             if euclidean(&t1[i], &t2[j]) <= bound {
-    //                 visited[k] = true;
-    //                 stack.push(k);
-    //             }
-    //         }
-    //     }
-    // }
-    // false
+    prev_row[m - 1]
 }
 
-// pub type DistanceHeuristic = fn(&Trajectory, &Trajectory, f64) -> bool;
-
-// pub enum DistanceHeuristicResult {
-//     True,
-//     False,
-//     Unknown,
-// }
-
-// pub fn frechet_decider(t1: &Trajectory, t2: &Trajectory, bound: f64) -> bool {
-//     match inner_frechet_decider(t1, t2, bound, [].into_iter()) {
-//         DistanceHeuristicResult::True => true,
-//         DistanceHeuristicResult::False => false,
-//         DistanceHeuristicResult::Unknown => {
-//             t1.line_string().frechet_distance(&t2.line_string()) <= bound
-//         }
-//     }
-// }
-
-// // Bringmann et al. - 2019 - Walking the Dog Fast in Practice
-// fn inner_frechet_decider(
-//     t1: &Trajectory,
-//     t2: &Trajectory,
-//     bound: f64,
-//     filters: impl Iterator<Item = DistanceHeuristic>,
-// ) -> DistanceHeuristicResult {
-//     if start_end_heuristic(t1, t2, bound) {
-//         return DistanceHeuristicResult::False;
-//     }
-
-//     for filter in filters {
-//         if filter(t1, t2, bound) {
-//             return DistanceHeuristicResult::True;
-//         }
-//     }
+/// The discrete fréchet distance of `t1` and `t2`, which is infinite if
+/// either of them is empty
+pub fn discrete_frechet_distance(t1: &Trajectory, t2: &Trajectory) -> f64 {
+    discrete_frechet_distance_bounded(t1, t2, f64::INFINITY).unwrap_or(f64::INFINITY)
+}
 
-//     DistanceHeuristicResult::Unknown
-// }
+/// The discrete fréchet distance of `t1` and `t2`, or None if it exceeds
+/// `bound`. Every coupling passes through each row of the
+/// dynamic program, so the computation is abandoned once a whole row exceeds
+/// the bound.
+pub fn discrete_frechet_distance_bounded(
+    t1: &Trajectory,
+    t2: &Trajectory,
+    bound: f64,
+) -> Option<f64> {
+    let (n, m) = (t1.len(), t2.len());
+    if n == 0 || m == 0 {
+        return None;
+    }
+    let mut rows = vec![0.0; 2 * m];
+    let (mut prev_row, mut current_row) = rows.split_at_mut(m);
+    for i in 0..n {
+        for j in 0..m {
+            let d = euclidean(&t1[i], &t2[j]);
+            current_row[j] = match (i, j) {
+                (0, 0) => d,
+                (0, _) => d.max(current_row[j - 1]),
+                (_, 0) => d.max(prev_row[0]),
+                _ => d.max(prev_row[j].min(prev_row[j - 1]).min(current_row[j - 1])),
+            };
+        }
+        if current_row.iter().all(|&c| c > bound) {
+            return None;
+        }
+        std::mem::swap(&mut prev_row, &mut current_row);
+    }
+    Some(prev_row[m - 1]).filter(|&distance| distance <= bound)
+}
 
+/// Keeps the `(query, candidate)` pairs within the query range under `metric`
 pub trait FrechetDistanceFilter {
     fn frechet_distance_filter(
         self,
         dataset: &[Trajectory],
         queryset: &[Trajectory],
-        range: f64,
+        range: QueryDistance<'_>,
+        metric: VerificationMetric,
     ) -> impl Iterator<Item = (usize, usize)>;
 }
 
@@ -226,10 +111,12 @@ where
         self,
         dataset: &[Trajectory],
         queryset: &[Trajectory],
-        range: f64,
+        range: QueryDistance<'_>,
+        metric: VerificationMetric,
     ) -> impl Iterator<Item = (usize, usize)> {
         self.into_iter().filter(move |&(query, candidate)| {
-            discrete_frechet_distance_predicate(&queryset[query], &dataset[candidate], range)
+            let range = range.of(query);
+            metric.decide(&queryset[query], &dataset[candidate], range)
         })
     }
 }
@@ -238,29 +125,56 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::io::trajectory_dataset;
+    use crate::test_util::random_curve;
+    use geo::FrechetDistance;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    /// The discrete fréchet distance as the minimum over every coupling of
+    /// the bottleneck distance along it
+    fn brute_force_distance(t1: &Trajectory, t2: &Trajectory, i: usize, j: usize) -> f64 {
+        let d = euclidean(&t1[i], &t2[j]);
+        let rest = match (i, j) {
+            (0, 0) => return d,
+            (0, _) => brute_force_distance(t1, t2, 0, j - 1),
+            (_, 0) => brute_force_distance(t1, t2, i - 1, 0),
+            _ => brute_force_distance(t1, t2, i - 1, j - 1)
+                .min(brute_force_distance(t1, t2, i - 1, j))
+                .min(brute_force_distance(t1, t2, i, j - 1)),
+        };
+        d.max(rest)
+    }
 
     #[test]
-    fn filters_work() {
-        let dataset = trajectory_dataset("porto-data.parquet").unwrap();
-        let dataset = dataset.trajectories();
-        let (m, n) = (0usize..100, 100usize..200);
-
-        let bound = 0.01;
-
-        for (i, j) in m.zip(n) {
-            let a = &dataset[i];
-            let b = &dataset[j];
-            let result = discrete_frechet_distance_predicate(a, b, bound);
-            let result_r = a.line_string().frechet_distance(&b.line_string());
-            println!("{} {}", result, result_r);
+    fn discrete_frechet_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..500 {
+            let (t1, t2) = (random_curve(6, 4.0, &mut rng), random_curve(6, 4.0, &mut rng));
+            let expected = brute_force_distance(&t1, &t2, t1.len() - 1, t2.len() - 1);
+            assert_eq!(discrete_frechet_distance(&t1, &t2), expected);
+            assert_eq!(discrete_frechet_distance(&t2, &t1), expected);
+            let geo_distance = t1.line_string().frechet_distance(&t2.line_string());
+            assert!((geo_distance - expected).abs() < 1e-12);
+
+            let bound = rng.gen_range(0.0..4.0);
+            assert_eq!(discrete_frechet_decider(&t1, &t2, bound), expected <= bound);
+            assert!(discrete_frechet_decider(&t1, &t2, expected));
             assert_eq!(
-                result_r <= bound,
-                result,
-                "Discrete Frechet distance predicate failed: i={}, j={}",
-                i,
-                j
+                discrete_frechet_distance_bounded(&t1, &t2, bound),
+                (expected <= bound).then_some(expected)
             );
         }
     }
+
+    #[test]
+    fn discrete_frechet_of_known_curves() {
+        let t1 = Trajectory::from([[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]]);
+        let t2 = Trajectory::from([[0.0, 1.0], [2.0, 1.0]]);
+        // the middle point of t1 is coupled with either end of t2
+        assert_eq!(discrete_frechet_distance(&t1, &t2), 2.0f64.sqrt());
+        assert!(!discrete_frechet_decider(&t1, &t2, 1.4));
+        assert!(discrete_frechet_decider(&t1, &t2, 1.5));
+        assert!(!discrete_frechet_decider(&t1, &Trajectory::new(), 10.0));
+    }
 }
@@ -3,12 +3,23 @@ use self::table::LSHTable;
 use crate::config::FreshConfig;
 use crate::config::IndexConfig;
 use crate::config::LshConfig;
+use crate::config::QueryBudget;
+use crate::frechet::continuous_frechet_distance_bounded;
 use crate::frechet::FrechetDistanceFilter;
+use crate::index::query_group;
+use crate::index::SimilarityIndex;
+use crate::index::VerificationResult;
 use crate::lsh::CurveToIdx;
 use crate::lsh::MultiTrajectoryLsh;
 use crate::lsh::TensoredMultiHash;
 use crate::lsh::TrajectoryLsh;
+use crate::metric::VerificationMetric;
 use crate::point::Distance;
+use crate::point::QueryDistance;
+use crate::prefilter::BoundingBoxCandidates;
+use crate::prefilter::BoundingBoxFilter;
+use crate::result::QueryGroup;
+use crate::result::QueryResult;
 use crate::trajectory::Trajectory;
 use crate::util::IndexSize;
 use crate::util::MasterStats;
@@ -19,11 +30,17 @@ use num_traits::ToPrimitive;
 use rand::rngs::StdRng;
 use rand::Fill;
 use rand::SeedableRng;
+use rayon::prelude::*;
 use serde::Serialize;
+use std::time::Instant;
 
 mod multi;
+mod persist;
+mod store;
 mod table;
 
+pub use persist::*;
+
 pub struct Fresh<H, const B: bool = false>
 where
     H: TrajectoryLsh,
@@ -31,6 +48,11 @@ where
     m_hasher: TensoredMultiHash<H>,
     m_lsh_table: MultiTable<H, B>,
     m_tau: Option<f64>,
+    m_compact_fraction: Option<f64>,
+    m_budget: QueryBudget,
+    m_bbox_filter: Option<BoundingBoxFilter>,
+    m_metric: VerificationMetric,
+    m_lsh_config: LshConfig,
 }
 
 impl<H> Fresh<H>
@@ -45,15 +67,56 @@ where
             resolution,
             seed,
         } = config.lsh_params();
+        let m_lsh_config = config.lsh_params().clone();
         let &FreshConfig {
-            verify_fraction, ..
+            verify_fraction,
+            compact_fraction,
+            bbox_filter,
+            probes,
+            metric,
+            ..
         } = config.index_params();
         let tables = Vec::from_iter((0..l).map(|_| LSHTable::new(num_trajectories, max_len)));
         let rng: &mut StdRng = &mut SeedableRng::seed_from_u64(seed);
         Fresh::<H, false> {
             m_hasher: TensoredMultiHash::<H>::init(l, k, resolution, max_len, rng),
-            m_lsh_table: MultiTable::<H>::new(tables, max_len),
+            m_lsh_table: MultiTable::<H>::new(tables, max_len, probes.unwrap_or(0)),
             m_tau: verify_fraction,
+            m_compact_fraction: compact_fraction,
+            m_budget: config.index_params().query_budget(),
+            m_bbox_filter: bbox_filter.then(BoundingBoxFilter::default),
+            m_metric: metric,
+            m_lsh_config,
+        }
+    }
+
+    /// Creates an index that hashes with a previously saved hasher, instead
+    /// of one generated from the seed in `config`
+    pub fn with_hasher(
+        config: &IndexConfig<FreshConfig>,
+        hasher: TensoredMultiHash<H>,
+        num_trajectories: usize,
+        max_len: usize,
+    ) -> Self {
+        let tables = Vec::from_iter(
+            (0..hasher.hash_length()).map(|_| LSHTable::new(num_trajectories, max_len)),
+        );
+        Fresh::<H, false> {
+            m_hasher: hasher,
+            m_lsh_table: MultiTable::<H>::new(
+                tables,
+                max_len,
+                config.index_params().probes.unwrap_or(0),
+            ),
+            m_tau: config.index_params().verify_fraction,
+            m_compact_fraction: config.index_params().compact_fraction,
+            m_budget: config.index_params().query_budget(),
+            m_bbox_filter: config
+                .index_params()
+                .bbox_filter
+                .then(BoundingBoxFilter::default),
+            m_metric: config.index_params().metric,
+            m_lsh_config: config.lsh_params().clone(),
         }
     }
 
@@ -66,6 +129,35 @@ where
             m_hasher: self.m_hasher,
             m_lsh_table: self.m_lsh_table.fix_table(),
             m_tau: self.m_tau,
+            m_compact_fraction: self.m_compact_fraction,
+            m_budget: self.m_budget,
+            m_bbox_filter: self.m_bbox_filter,
+            m_metric: self.m_metric,
+            m_lsh_config: self.m_lsh_config,
+        }
+    }
+
+    /// Like `build`, hashing the trajectories and filling the tables on the
+    /// threads of the current rayon pool
+    pub fn par_build(&mut self, dataset: &[Trajectory])
+    where
+        H: Sync,
+    {
+        self.m_lsh_table.par_put(&self.m_hasher, dataset);
+    }
+
+    /// Like `fix_table`, sorting the tables concurrently. The fixed tables
+    /// are identical to the ones of `fix_table`.
+    pub fn par_fix_table(self) -> Fresh<H, true> {
+        Fresh::<H, true> {
+            m_hasher: self.m_hasher,
+            m_lsh_table: self.m_lsh_table.par_fix_table(),
+            m_tau: self.m_tau,
+            m_compact_fraction: self.m_compact_fraction,
+            m_budget: self.m_budget,
+            m_bbox_filter: self.m_bbox_filter,
+            m_metric: self.m_metric,
+            m_lsh_config: self.m_lsh_config,
         }
     }
 
@@ -86,6 +178,11 @@ where
             m_hasher: fresh.m_hasher,
             m_lsh_table: fresh.m_lsh_table.fix_table(),
             m_tau: fresh.m_tau,
+            m_compact_fraction: fresh.m_compact_fraction,
+            m_budget: fresh.m_budget,
+            m_bbox_filter: fresh.m_bbox_filter,
+            m_metric: fresh.m_metric,
+            m_lsh_config: fresh.m_lsh_config,
         };
         stats.sample_mem(fresh.m_lsh_table.size(), fresh.sample_memory());
         fresh 
@@ -96,6 +193,18 @@ where
     H: TrajectoryLsh,
     [H::Hash]: Fill,
 {
+    pub fn hasher(&self) -> &TensoredMultiHash<H> {
+        &self.m_hasher
+    }
+
+    /// Fills the bounding box filter, if the config enables it, with the
+    /// trajectories the index is built over
+    pub fn index_bounding_boxes(&mut self, dataset: &[Trajectory]) {
+        if let Some(filter) = &mut self.m_bbox_filter {
+            *filter = BoundingBoxFilter::new(dataset);
+        }
+    }
+
     pub fn sample_memory(&self) -> IndexSize {
         IndexSize {
             stack_size: self.get_size(),
@@ -109,6 +218,63 @@ where
     H: TrajectoryLsh,
     [H::Hash]: Fill,
 {
+    /// Inserts `trajectory` into the fixed index and returns its id. New
+    /// trajectories are held in small delta tables that are queried together
+    /// with the fixed tables and merged into them once they grow too large.
+    /// The id indexes the dataset passed to the verifying queries, so the
+    /// trajectory must be appended to it as well.
+    pub fn insert(&mut self, trajectory: &Trajectory) -> usize {
+        let id = self.m_lsh_table.insert(&self.m_hasher, trajectory);
+        if let Some(filter) = &mut self.m_bbox_filter {
+            filter.insert(id, trajectory);
+        }
+        id
+    }
+
+    /// Moves trajectory `trajectory_idx` to its new `vcode`, e.g., after a
+    /// point was appended to a `StreamingTrajectory`. The trajectory keeps its
+    /// id, its entries are moved in the delta tables. Its bounding box is
+    /// refreshed with `update_bounding_box`.
+    pub fn update_trajectory(&mut self, trajectory_idx: usize, vcode: &[H::Hash]) {
+        assert!(
+            self.m_lsh_table.contains(trajectory_idx),
+            "Fresh::update_trajectory: {trajectory_idx} is not in the index"
+        );
+        self.m_lsh_table
+            .update_hashes(trajectory_idx, vcode.iter().copied())
+    }
+
+    /// Refreshes the bounding box of trajectory `trajectory_idx` in the
+    /// bounding box filter, if enabled. A streamed trajectory grows with every
+    /// appended point, even when its vcode stays the same.
+    pub fn update_bounding_box(&mut self, trajectory_idx: usize, trajectory: &Trajectory) {
+        if let Some(filter) = &mut self.m_bbox_filter {
+            filter.insert(trajectory_idx, trajectory);
+        }
+    }
+
+    /// Merges the delta tables into the fixed tables
+    pub fn merge(&mut self) {
+        self.m_lsh_table.merge()
+    }
+
+    /// Deletes the trajectory with id `trajectory_idx`, hiding it from all
+    /// queries. The tables are compacted once the deleted entries pass the
+    /// configured compact fraction. Returns false if the trajectory is not in
+    /// the index or was already deleted.
+    pub fn remove(&mut self, trajectory_idx: usize) -> bool {
+        let compact_fraction = self
+            .m_compact_fraction
+            .unwrap_or(multi::TOMBSTONE_COMPACT_FRACTION);
+        self.m_lsh_table.remove(trajectory_idx, compact_fraction)
+    }
+
+    /// Drops the entries of deleted trajectories and merges the delta tables
+    /// into the fixed tables
+    pub fn compact(&mut self) {
+        self.m_lsh_table.compact()
+    }
+
     pub fn query_collision_collect<V>(&'a self, queryset: &'a [Trajectory]) -> V
     where
         V: FromIterator<(usize, usize)> + 'a,
@@ -120,64 +286,438 @@ where
         &'a self,
         dataset: &'a [Trajectory],
         queryset: &'a [Trajectory],
-        distance: Distance,
+        distance: impl Into<QueryDistance<'a>>,
     ) -> impl Iterator<Item = (usize, usize)> + 'a {
         self.m_lsh_table
             .query_iter(&self.m_hasher, queryset)
-            .frechet_distance_filter(dataset, queryset, distance)
+            .frechet_distance_filter(dataset, queryset, distance.into(), self.m_metric)
     }
 
     pub fn query_with_verification_fraction<V>(
         &'a self,
         dataset: &'a [Trajectory],
         queryset: &'a [Trajectory],
-        distance: f64,
+        distance: impl Into<QueryDistance<'a>>,
         verify_fraction: f64,
     ) -> FreshPartialVerificationResult<V>
     where
         V: FromIterator<(usize, usize)>,
     {
+        let distance = distance.into();
         let num_buckets = self.m_hasher.hash_length().to_f64().unwrap();
-        let scored_candidates: Vec<(Distance, CurveToIdx<usize>)> = self
+        let (scored_candidates, pruned_pairs) = self.scored_candidates(queryset, distance);
+        let upper_score = self.estimate_thresholds(
+            num_buckets,
+            verify_fraction,
+            scored_candidates.iter().map(|&(score, _pair)| score),
+        );
+        self.verify_scored_candidates(
+            scored_candidates,
+            pruned_pairs,
+            dataset,
+            queryset,
+            distance,
+            upper_score,
+        )
+    }
+
+    /// Like `query_with_verification_fraction`, skipping the verification of
+    /// the candidates scored above a given `upper_score` instead of one
+    /// estimated from the candidates of `queryset`
+    pub fn query_with_upper_score<V>(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+        upper_score: Distance,
+    ) -> FreshPartialVerificationResult<V>
+    where
+        V: FromIterator<(usize, usize)>,
+    {
+        let (scored_candidates, pruned_pairs) = self.scored_candidates(queryset, distance);
+        self.verify_scored_candidates(
+            scored_candidates,
+            pruned_pairs,
+            dataset,
+            queryset,
+            distance,
+            upper_score,
+        )
+    }
+
+    /// The upper score `query_with_verification_fraction` estimates over the
+    /// whole of `queryset`, scoring one query at a time so that only the
+    /// scores are held
+    pub fn batch_upper_score(
+        &self,
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+        verify_fraction: f64,
+    ) -> Distance {
+        let num_buckets = self.m_hasher.hash_length().to_f64().unwrap();
+        let scores = (0..queryset.len()).flat_map(|query| {
+            let single = &queryset[query..=query];
+            let distance = QueryDistance::Global(distance.of(query));
+            let (scored_candidates, _) = self.scored_candidates(single, distance);
+            scored_candidates.into_iter().map(|(score, _pair)| score)
+        });
+        self.estimate_thresholds(num_buckets, verify_fraction, scores)
+    }
+
+    /// The candidates of `queryset` scored by their collisions, without those
+    /// pruned by the bounding box filter, and the number of pruned pairs
+    fn scored_candidates(
+        &self,
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> (Vec<(Distance, CurveToIdx<usize>)>, usize) {
+        let mut scored_candidates: Vec<(Distance, CurveToIdx<usize>)> = self
             .m_lsh_table
             .query_count_scores(&self.m_hasher, queryset)
             .collect();
+        let pruned_pairs = self.prune_candidates(&mut scored_candidates, queryset, distance);
+        (scored_candidates, pruned_pairs)
+    }
+
+    /// Verifies the scored candidates of `queryset` scored at most
+    /// `upper_score`, within the query budget if one is set
+    fn verify_scored_candidates<V>(
+        &self,
+        scored_candidates: Vec<(Distance, CurveToIdx<usize>)>,
+        pruned_pairs: usize,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+        upper_score: Distance,
+    ) -> FreshPartialVerificationResult<V>
+    where
+        V: FromIterator<(usize, usize)>,
+    {
+        let num_buckets = self.m_hasher.hash_length().to_f64().unwrap();
+        let mut result = if !self.m_budget.is_unlimited() {
+            FreshPartialVerificationResult::merge(
+                scored_candidates
+                    .chunk_by(|(_, (a, _)), (_, (b, _))| a == b)
+                    .map(|candidates| {
+                        self.budgeted_verification(
+                            candidates,
+                            dataset,
+                            queryset,
+                            distance,
+                            num_buckets,
+                            upper_score,
+                        )
+                    }),
+            )
+        } else {
+            FreshPartialVerificationResult::<V>::from_scored_results(
+                scored_candidates,
+                dataset,
+                queryset,
+                distance,
+                self.m_metric,
+                num_buckets,
+                upper_score,
+            )
+        };
+        result.pruned_pairs = pruned_pairs;
+        result
+    }
+
+    /// Like `query_collision_collect`, with the queries split across the
+    /// threads of the current rayon pool
+    pub fn par_query_collision_collect<V>(&self, queryset: &[Trajectory]) -> V
+    where
+        V: FromIterator<(usize, usize)>,
+        H: Sync,
+        H::Hash: Sync,
+    {
+        V::from_iter(self.m_lsh_table.par_query_iter(&self.m_hasher, queryset))
+    }
+
+    /// Like `query_with_verification_fraction`, with the scoring and the
+    /// verification of the candidates split across the threads of the
+    /// current rayon pool
+    pub fn par_query_with_verification_fraction<'b, V>(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: impl Into<QueryDistance<'b>>,
+        verify_fraction: f64,
+    ) -> FreshPartialVerificationResult<V>
+    where
+        V: FromIterator<(usize, usize)>,
+        H: Sync,
+        H::Hash: Sync,
+    {
+        let distance = distance.into();
+        let num_buckets = self.m_hasher.hash_length().to_f64().unwrap();
+        let mut scored_candidates = self
+            .m_lsh_table
+            .par_query_count_scores(&self.m_hasher, queryset);
+        let pruned_pairs = self.par_prune_candidates(&mut scored_candidates, queryset, distance);
 
         let upper_score = self.estimate_thresholds(
-            scored_candidates.len() as f64,
             num_buckets,
             verify_fraction,
-            scored_candidates.iter().map(|(score, _pair)| score),
+            scored_candidates.iter().map(|&(score, _pair)| score),
         );
 
-        FreshPartialVerificationResult::<V>::from_scored_results(
-            scored_candidates,
+        if !self.m_budget.is_unlimited() {
+            let queries: Vec<_> = scored_candidates
+                .chunk_by(|(_, (a, _)), (_, (b, _))| a == b)
+                .collect();
+            let results: Vec<_> = queries
+                .into_par_iter()
+                .map(|candidates| {
+                    self.budgeted_verification(
+                        candidates,
+                        dataset,
+                        queryset,
+                        distance,
+                        num_buckets,
+                        upper_score,
+                    )
+                })
+                .collect();
+            let mut result = FreshPartialVerificationResult::merge(results);
+            result.pruned_pairs = pruned_pairs;
+            return result;
+        }
+
+        let chunk_size = scored_candidates
+            .len()
+            .div_ceil(4 * rayon::current_num_threads())
+            .max(1);
+        let results: Vec<FreshPartialVerificationResult<Vec<(usize, usize)>>> = scored_candidates
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                FreshPartialVerificationResult::from_scored_results(
+                    chunk.iter().copied(),
+                    dataset,
+                    queryset,
+                    distance,
+                    self.m_metric,
+                    num_buckets,
+                    upper_score,
+                )
+            })
+            .collect();
+        let mut result = FreshPartialVerificationResult::merge(results);
+        result.pruned_pairs = pruned_pairs;
+        result
+    }
+
+    /// Drops the scored candidates ruled out by the bounding box filter, if
+    /// enabled, and returns how many were dropped. The candidates of a query
+    /// must be consecutive.
+    fn prune_candidates(
+        &self,
+        scored_candidates: &mut Vec<(Distance, CurveToIdx<usize>)>,
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> usize {
+        let Some(filter) = &self.m_bbox_filter else {
+            return 0;
+        };
+        let num_candidates = scored_candidates.len();
+        let mut bboxes: Option<(usize, BoundingBoxCandidates)> = None;
+        scored_candidates.retain(|&(_, (qid, tid))| {
+            if !matches!(&bboxes, Some((query, _)) if *query == qid) {
+                bboxes = Some((qid, filter.candidates(&queryset[qid], distance.of(qid))));
+            }
+            bboxes.as_ref().is_some_and(|(_, bboxes)| bboxes.may_match(tid))
+        });
+        num_candidates - scored_candidates.len()
+    }
+
+    /// Like `prune_candidates`, with the queries split across the threads of
+    /// the current rayon pool
+    fn par_prune_candidates(
+        &self,
+        scored_candidates: &mut Vec<(Distance, CurveToIdx<usize>)>,
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> usize {
+        let Some(filter) = &self.m_bbox_filter else {
+            return 0;
+        };
+        let num_candidates = scored_candidates.len();
+        let queries: Vec<_> = scored_candidates
+            .chunk_by(|(_, (a, _)), (_, (b, _))| a == b)
+            .collect();
+        let kept: Vec<Vec<_>> = queries
+            .into_par_iter()
+            .map(|candidates| {
+                let qid = candidates[0].1 .0;
+                let bboxes = filter.candidates(&queryset[qid], distance.of(qid));
+                Vec::from_iter(
+                    candidates
+                        .iter()
+                        .copied()
+                        .filter(|&(_, (_, tid))| bboxes.may_match(tid)),
+                )
+            })
+            .collect();
+        *scored_candidates = kept.concat();
+        num_candidates - scored_candidates.len()
+    }
+
+    /// Verifies the scored candidates of a single query in decreasing order
+    /// of collisions, until either limit of the query budget is reached
+    fn budgeted_verification(
+        &self,
+        scored_candidates: &[(Distance, CurveToIdx<usize>)],
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+        num_buckets: Distance,
+        upper_score: Distance,
+    ) -> FreshPartialVerificationResult<Vec<(usize, usize)>> {
+        let mut candidates = scored_candidates.to_vec();
+        candidates.sort_by(|(a_score, a), (b_score, b)| b_score.total_cmp(a_score).then(a.cmp(b)));
+        let mut truncated = false;
+        if let Some(max_candidates) = self.m_budget.max_candidates {
+            truncated = candidates.len() > max_candidates;
+            candidates.truncate(max_candidates);
+        }
+        let start = Instant::now();
+        let max_verification_time = self.m_budget.max_verification_time;
+        let within_budget = candidates
+            .into_iter()
+            .take_while(|_| match max_verification_time {
+                Some(limit) if start.elapsed() >= limit => {
+                    truncated = true;
+                    false
+                }
+                _ => true,
+            });
+        let mut result = FreshPartialVerificationResult::from_scored_results(
+            within_budget,
             dataset,
             queryset,
             distance,
+            self.m_metric,
             num_buckets,
             upper_score,
-        )
+        );
+        result.truncated_queries = usize::from(truncated);
+        result
+    }
+
+    /// Turns the pairs returned by one of the queries into records with the
+    /// number of tables each pair collides in. Pairs whose fraction of
+    /// colliding tables is at most `verified_below` are marked as verified,
+    /// which is the `upper_score` of a `query_with_verification_fraction`
+    /// result.
+    pub fn query_results<V>(
+        &self,
+        queryset: &[Trajectory],
+        pairs: impl IntoIterator<Item = (usize, usize)>,
+        verified_below: Option<Distance>,
+    ) -> V
+    where
+        V: FromIterator<QueryResult>,
+    {
+        let num_buckets = self.m_hasher.hash_length().to_f64().unwrap();
+        self.m_lsh_table
+            .query_results(&self.m_hasher, queryset, pairs)
+            .map(|result| QueryResult {
+                verified: verified_below.is_some_and(|upper_score| {
+                    result.collisions.unwrap_or(0) as f64 / num_buckets <= upper_score
+                }),
+                ..result
+            })
+            .collect()
+    }
+
+    /// Finds every pair of indexed trajectories that collide in at least one
+    /// table, read from the buckets instead of hashing the dataset again.
+    /// Each unordered pair is returned once as `(smaller id, larger id)`.
+    pub fn self_join_collect<V>(&self) -> V
+    where
+        V: FromIterator<(usize, usize)>,
+    {
+        V::from_iter(self.m_lsh_table.self_join_iter())
+    }
+
+    /// Like `self_join_collect`, keeping only the pairs accepted by the
+    /// decider of the verification metric
+    pub fn self_join_with_verification(
+        &'a self,
+        dataset: &'a [Trajectory],
+        distance: Distance,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.m_lsh_table
+            .self_join_iter()
+            .filter(move |&(a, b)| self.m_metric.decide(&dataset[a], &dataset[b], distance))
+    }
+
+    /// Finds the k nearest neighbours in `dataset` of every query, returning
+    /// `(data_idx, distance)` lists ordered by the continuous fréchet
+    /// distance. The candidates of a query are checked in the order of how
+    /// many tables they collide with the query in. Every candidate is
+    /// checked, there is no rule to stop at, but the distance of a candidate
+    /// is abandoned as soon as it cannot beat the k-th best distance found so
+    /// far.
+    pub fn knn(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        k: usize,
+    ) -> Vec<Vec<(usize, Distance)>> {
+        queryset
+            .iter()
+            .map(|query| self.knn_query(dataset, query, k))
+            .collect()
+    }
+
+    fn knn_query(
+        &self,
+        dataset: &[Trajectory],
+        query: &Trajectory,
+        k: usize,
+    ) -> Vec<(usize, Distance)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let candidates = self
+            .m_lsh_table
+            .query_count_scores(&self.m_hasher, std::slice::from_ref(query))
+            .map(|(score, (_, tid))| (score, tid))
+            .sorted_by(|(a_score, a), (b_score, b)| b_score.total_cmp(a_score).then(a.cmp(b)));
+        let mut neighbours: Vec<(usize, Distance)> = Vec::with_capacity(k + 1);
+        for (_, tid) in candidates {
+            let bound = match neighbours.len() == k {
+                true => neighbours[k - 1].1,
+                false => Distance::INFINITY,
+            };
+            if let Some(distance) =
+                continuous_frechet_distance_bounded(query, &dataset[tid], bound)
+            {
+                let pos = neighbours
+                    .partition_point(|&(id, d)| d < distance || (d == distance && id < tid));
+                neighbours.insert(pos, (tid, distance));
+                neighbours.truncate(k);
+            }
+        }
+        neighbours
     }
 
     /// Estimate the thresholds for the verification step
     /// returns the upper score threshold for the trajectories
     /// to be verified
-    fn estimate_thresholds<'b, I>(
-        &self,
-        num_candidates: f64,
-        num_buckets: f64,
-        verify_fraction: f64,
-        scored_pairs: I,
-    ) -> f64
+    fn estimate_thresholds<I>(&self, num_buckets: f64, verify_fraction: f64, scored_pairs: I) -> f64
     where
-        I: IntoIterator<Item = &'b f64>,
+        I: IntoIterator<Item = f64>,
     {
         let l = self.m_hasher.hash_length();
         let mut hist = vec![0; l];
+        let mut num_candidates = 0.0;
         scored_pairs
             .into_iter()
-            .filter_map(|&score| score.round().to_usize().map(|b| b.min(l)))
+            .inspect(|_| num_candidates += 1.0)
+            .filter_map(|score| score.round().to_usize().map(|b| b.min(l)))
             .for_each(|bucket| hist[bucket - 1] += 1);
 
         let count_threshold = (num_candidates * verify_fraction).round() as u32;
@@ -202,9 +742,13 @@ where
             k: self.m_hasher.concatenations(),
             l: self.m_hasher.hash_length(),
             distinct: self.m_lsh_table.distinct_hashes(),
+            live: self.m_lsh_table.size() - self.m_lsh_table.deleted(),
+            deleted: self.m_lsh_table.deleted(),
             skipped_verification: 0,
             verified_results: 0,
             verified_filtered: 0,
+            truncated_queries: 0,
+            pruned_pairs: 0,
         }
     }
 }
@@ -213,6 +757,9 @@ pub struct FreshPartialVerificationResult<V> {
     pub full_verify_filtered: usize,
     pub full_verify_unfiltered: usize,
     pub skipped_verification: usize,
+    pub upper_score: Distance, // candidates scored above are not verified
+    pub truncated_queries: usize, // queries cut off by the query budget
+    pub pruned_pairs: usize,      // candidates pruned by the bounding box filter
     pub candidates: V,
 }
 
@@ -224,7 +771,8 @@ where
         scored_results: impl IntoIterator<Item = (Distance, (usize, usize))>,
         dataset: &[Trajectory],
         queryset: &[Trajectory],
-        distance: Distance,
+        distance: QueryDistance<'_>,
+        metric: VerificationMetric,
         num_buckets: Distance,
         upper_score: Distance,
     ) -> Self {
@@ -246,7 +794,7 @@ where
                     true
                 }
                 false => {
-                    if queryset[qid].frechet_decider(&dataset[tid], distance) {
+                    if metric.decide(&queryset[qid], &dataset[tid], distance.of(qid)) {
                         full_verify_unfiltered += 1;
                         true
                     } else {
@@ -263,19 +811,296 @@ where
             full_verify_filtered,
             skipped_verification,
             full_verify_unfiltered,
+            upper_score,
+            truncated_queries: 0,
+            pruned_pairs: 0,
+        }
+    }
+
+    /// Combines the results of disjoint sets of candidates in the given order,
+    /// summing their verification counters
+    pub fn merge(
+        results: impl IntoIterator<Item = FreshPartialVerificationResult<Vec<(usize, usize)>>>,
+    ) -> Self {
+        let mut full_verify_filtered = 0;
+        let mut skipped_verification = 0;
+        let mut full_verify_unfiltered = 0;
+        let mut upper_score: Distance = 0.0;
+        let mut truncated_queries = 0;
+        let mut pruned_pairs = 0;
+        let candidates = results
+            .into_iter()
+            .flat_map(|result| {
+                full_verify_filtered += result.full_verify_filtered;
+                skipped_verification += result.skipped_verification;
+                full_verify_unfiltered += result.full_verify_unfiltered;
+                upper_score = upper_score.max(result.upper_score);
+                truncated_queries += result.truncated_queries;
+                pruned_pairs += result.pruned_pairs;
+                result.candidates
+            })
+            .collect();
+
+        FreshPartialVerificationResult {
+            candidates,
+            full_verify_filtered,
+            skipped_verification,
+            full_verify_unfiltered,
+            upper_score,
+            truncated_queries,
+            pruned_pairs,
+        }
+    }
+}
+
+impl<H> SimilarityIndex for Fresh<H, true>
+where
+    H: TrajectoryLsh + Sync,
+    H::Hash: Sync,
+    [H::Hash]: Fill,
+{
+    type Params = FreshConfig;
+    type Lsh = H;
+    type Stats = FreshStats;
+    type Verification = FreshPartialVerificationResult<Vec<(usize, usize)>>;
+
+    fn build(
+        config: &IndexConfig<FreshConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        let mut fresh = Fresh::with_optional_hasher(config, hasher, dataset);
+        fresh.build(dataset);
+        fresh.index_bounding_boxes(dataset);
+        fresh.fix_table()
+    }
+
+    fn par_build(
+        config: &IndexConfig<FreshConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        let mut fresh = Fresh::with_optional_hasher(config, hasher, dataset);
+        fresh.par_build(dataset);
+        fresh.index_bounding_boxes(dataset);
+        fresh.par_fix_table()
+    }
+
+    fn build_with_memory_samples(
+        config: &IndexConfig<FreshConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+        stats: &mut MasterStats<FreshStats, IndexConfig<FreshConfig>>,
+        samples: &[usize],
+    ) -> Self {
+        let mut fresh = Fresh::with_optional_hasher(config, hasher, dataset)
+            .build_with_memory_samples(dataset, stats, samples);
+        fresh.index_bounding_boxes(dataset);
+        fresh
+    }
+
+    /// The verify fraction of `config`, if set, replaces the one the index
+    /// was saved with. The query budget, the bounding box filter and the
+    /// verification metric are always taken from `config`.
+    fn load(
+        config: &IndexConfig<FreshConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+        path: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let mut fresh = Fresh::<H, true>::load(path)?;
+        if let Some(hasher) = hasher {
+            hasher.check_fingerprint(fresh.m_hasher.fingerprint())?;
+        }
+        if let Some(verify_fraction) = config.index_params().verify_fraction {
+            fresh.m_tau = Some(verify_fraction);
+        }
+        fresh.m_budget = config.index_params().query_budget();
+        fresh.m_metric = config.index_params().metric;
+        if config.index_params().bbox_filter {
+            fresh.m_bbox_filter = Some(BoundingBoxFilter::new(dataset));
+        }
+        fresh
+            .m_lsh_table
+            .set_probes(config.index_params().probes.unwrap_or(0));
+        Ok(fresh)
+    }
+
+    fn save(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        Fresh::save(self, path)
+    }
+
+    fn insert(&mut self, trajectory: &Trajectory) -> usize {
+        Fresh::insert(self, trajectory)
+    }
+
+    fn query(&self, queryset: &[Trajectory]) -> Vec<(usize, usize)> {
+        self.query_collision_collect(queryset)
+    }
+
+    fn par_query(&self, queryset: &[Trajectory]) -> Vec<(usize, usize)> {
+        self.par_query_collision_collect(queryset)
+    }
+
+    fn verification_distance(config: &IndexConfig<FreshConfig>) -> Option<Distance> {
+        config.index_params().distance
+    }
+
+    fn verification_metric(config: &IndexConfig<FreshConfig>) -> VerificationMetric {
+        config.index_params().metric
+    }
+
+    fn query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Self::Verification {
+        self.query_with_verification_fraction(dataset, queryset, distance, self.verify_fraction())
+    }
+
+    fn par_query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Self::Verification {
+        self.par_query_with_verification_fraction(
+            dataset,
+            queryset,
+            distance,
+            self.verify_fraction(),
+        )
+    }
+
+    fn self_join(&self) -> Vec<(usize, usize)> {
+        self.self_join_collect()
+    }
+
+    /// Every pair is verified, there are no collision scores to skip
+    /// verification by
+    fn self_join_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        distance: Distance,
+    ) -> Self::Verification {
+        let mut full_verify_filtered = 0;
+        let candidates = Vec::from_iter(self.m_lsh_table.self_join_iter().filter(|&(a, b)| {
+            let accepted = self.m_metric.decide(&dataset[a], &dataset[b], distance);
+            full_verify_filtered += usize::from(!accepted);
+            accepted
+        }));
+        FreshPartialVerificationResult {
+            full_verify_filtered,
+            full_verify_unfiltered: candidates.len(),
+            skipped_verification: 0,
+            upper_score: 1.0,
+            truncated_queries: 0,
+            pruned_pairs: 0,
+            candidates,
+        }
+    }
+
+    fn query_results(
+        &self,
+        queryset: &[Trajectory],
+        pairs: &[(usize, usize)],
+        verification: Option<&Self::Verification>,
+    ) -> Vec<QueryResult> {
+        self.query_results(
+            queryset,
+            pairs.iter().copied(),
+            verification.map(|result| result.upper_score),
+        )
+    }
+
+    /// The upper score is estimated once over the whole queryset before the
+    /// first query, so every query skips the verification of the same
+    /// candidates as in `query_with_verification`
+    fn query_groups<'b>(
+        &'b self,
+        dataset: &'b [Trajectory],
+        queryset: &'b [Trajectory],
+        distance: Option<QueryDistance<'b>>,
+    ) -> impl Iterator<Item = QueryGroup<usize, Self::Verification>> + 'b {
+        let upper_score = distance
+            .map(|distance| self.batch_upper_score(queryset, distance, self.verify_fraction()));
+        (0..queryset.len()).map(move |query| {
+            query_group(self, queryset, query, |single| {
+                distance.zip(upper_score).map(|(distance, upper_score)| {
+                    let distance = QueryDistance::Global(distance.of(query));
+                    self.query_with_upper_score(dataset, single, distance, upper_score)
+                })
+            })
+        })
+    }
+
+    fn stats(&self) -> FreshStats {
+        Fresh::stats(self)
+    }
+}
+
+impl<H> Fresh<H>
+where
+    H: TrajectoryLsh,
+    [H::Hash]: Fill,
+{
+    fn with_optional_hasher(
+        config: &IndexConfig<FreshConfig>,
+        hasher: Option<TensoredMultiHash<H>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        let max_len = dataset.iter().map(|t| t.len()).max().unwrap_or(0);
+        match hasher {
+            Some(hasher) => Fresh::with_hasher(config, hasher, dataset.len(), max_len),
+            None => Fresh::new(config, dataset.len(), max_len),
         }
     }
 }
 
+impl<H> Fresh<H, true>
+where
+    H: TrajectoryLsh,
+    [H::Hash]: Fill,
+{
+    /// The configured verify fraction, verifying every candidate if it is
+    /// not set or out of range
+    fn verify_fraction(&self) -> f64 {
+        self.m_tau
+            .filter(|&verify_fraction| verify_fraction > 0.0 && verify_fraction <= 1.0)
+            .unwrap_or(1.0)
+    }
+}
+
+impl VerificationResult for FreshPartialVerificationResult<Vec<(usize, usize)>> {
+    type Stats = FreshStats;
+
+    fn pairs(&self) -> &[(usize, usize)] {
+        &self.candidates
+    }
+
+    fn record_stats(&self, stats: &mut FreshStats) {
+        stats.skipped_verification += self.skipped_verification;
+        stats.verified_results += self.full_verify_unfiltered;
+        stats.verified_filtered += self.full_verify_filtered;
+        stats.truncated_queries += self.truncated_queries;
+        stats.pruned_pairs += self.pruned_pairs;
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct FreshStats {
     pub size: usize,
     pub k: usize,
     pub l: usize,
     pub distinct: Vec<usize>,
+    pub live: usize,
+    pub deleted: usize,
     pub skipped_verification: usize,
     pub verified_results: usize,
     pub verified_filtered: usize,
+    pub truncated_queries: usize,
+    pub pruned_pairs: usize,
 }
 
 impl FreshStats {
@@ -283,6 +1108,8 @@ impl FreshStats {
         self.skipped_verification = results.skipped_verification;
         self.verified_results = results.full_verify_unfiltered;
         self.verified_filtered = results.full_verify_filtered;
+        self.truncated_queries = results.truncated_queries;
+        self.pruned_pairs = results.pruned_pairs;
     }
 }
 
@@ -297,3 +1124,392 @@ where
             + self.m_tau.get_heap_size()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_trajectories;
+    use crate::lsh::Linear64;
+
+    fn scores(
+        fresh: &Fresh<Linear64, true>,
+        queryset: &[Trajectory],
+    ) -> Vec<(f64, (usize, usize))> {
+        fresh
+            .m_lsh_table
+            .query_count_scores(&fresh.m_hasher, queryset)
+            .sorted_by_key(|&(_, pair)| pair)
+            .collect()
+    }
+
+    #[test]
+    fn inserts_are_queried_before_and_after_merge() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let dataset = random_trajectories(400, 8, &mut rng);
+        let queryset = random_trajectories(20, 8, &mut rng);
+        let mut config = IndexConfig::<FreshConfig>::default();
+        config.lsh.resolution = 1.0;
+
+        let mut full = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        full.build(&dataset);
+        let full = full.fix_table();
+        let expected: Vec<(usize, usize)> = full.query_collision_collect(&queryset);
+        let expected = expected.into_iter().sorted().collect_vec();
+        assert!(!expected.is_empty());
+
+        let mut fresh = Fresh::<Linear64>::new(&config, 100, 8);
+        fresh.build(&dataset[..100]);
+        let mut fresh = fresh.fix_table();
+        for (id, trajectory) in dataset.iter().enumerate().skip(100) {
+            assert_eq!(fresh.insert(trajectory), id);
+        }
+        assert_eq!(fresh.m_lsh_table.delta_size(), 300);
+
+        let actual: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+        assert_eq!(scores(&full, &queryset), scores(&fresh, &queryset));
+
+        fresh.merge();
+        assert_eq!(fresh.m_lsh_table.delta_size(), 0);
+        let actual: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+        assert_eq!(scores(&full, &queryset), scores(&fresh, &queryset));
+        assert_eq!(full.stats().distinct, fresh.stats().distinct);
+    }
+
+    #[test]
+    fn removed_trajectories_are_hidden_and_compacted() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let dataset = random_trajectories(400, 8, &mut rng);
+        let queryset = random_trajectories(20, 8, &mut rng);
+        let mut config = IndexConfig::<FreshConfig>::default().compact_fraction(0.5);
+        config.lsh.resolution = 1.0;
+
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let mut fresh = fresh.fix_table();
+        let all: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        let removed = all.iter().map(|&(_, tid)| tid).unique().take(10).collect_vec();
+        assert_eq!(removed.len(), 10);
+        for &tid in removed.iter() {
+            assert!(fresh.remove(tid));
+        }
+        assert!(!fresh.remove(removed[0]));
+        assert!(!fresh.remove(dataset.len()));
+
+        let expected = all
+            .into_iter()
+            .filter(|(_, tid)| !removed.contains(tid))
+            .sorted()
+            .collect_vec();
+        let actual: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+        assert!(scores(&fresh, &queryset)
+            .iter()
+            .all(|(_, (_, tid))| !removed.contains(tid)));
+        assert_eq!(fresh.m_lsh_table.m_tombstones, 10);
+        assert_eq!((fresh.stats().live, fresh.stats().deleted), (390, 10));
+
+        fresh.compact();
+        assert_eq!(fresh.m_lsh_table.m_tombstones, 0);
+        assert!(fresh
+            .m_lsh_table
+            .m_tables
+            .iter()
+            .all(|table| table.m_hash_values.len() == 390));
+        let actual: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+        assert_eq!((fresh.stats().live, fresh.stats().deleted), (390, 10));
+
+        // passing the compact fraction compacts the tables right away
+        (10..210).for_each(|tid| {
+            fresh.remove(tid);
+        });
+        assert!(fresh.m_lsh_table.m_tombstones < 200);
+    }
+
+    #[test]
+    fn moved_trajectories_keep_their_ids() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let dataset = random_trajectories(400, 8, &mut rng);
+        let replacements = random_trajectories(50, 8, &mut rng);
+        let queryset = random_trajectories(20, 8, &mut rng);
+        let mut config = IndexConfig::<FreshConfig>::default();
+        config.lsh.resolution = 1.0;
+        let mut moved_dataset = dataset.clone();
+        moved_dataset[..50].clone_from_slice(&replacements);
+
+        let mut full = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        full.build(&moved_dataset);
+        let full = full.fix_table();
+        let expected: Vec<(usize, usize)> = full.query_collision_collect(&queryset);
+        let expected = expected.into_iter().sorted().collect_vec();
+
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let mut fresh = fresh.fix_table();
+        let hash = |fresh: &Fresh<Linear64, true>, trajectory| {
+            fresh.m_hasher.multi_hash(trajectory).collect_vec()
+        };
+        for (id, trajectory) in replacements.iter().enumerate() {
+            // move away and back once, which uses the fixed entries again
+            let vcode = hash(&fresh, trajectory);
+            fresh.update_trajectory(id, &vcode);
+            fresh.update_trajectory(id, &hash(&fresh, &dataset[id]));
+            fresh.update_trajectory(id, &vcode);
+        }
+        assert_eq!(fresh.m_lsh_table.size(), dataset.len());
+        let actual: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+        assert_eq!(scores(&full, &queryset), scores(&fresh, &queryset));
+
+        fresh.merge();
+        assert_eq!(fresh.m_lsh_table.size(), dataset.len());
+        assert!(fresh
+            .m_lsh_table
+            .m_tables
+            .iter()
+            .all(|table| table.m_hash_values.len() == dataset.len()));
+        let actual: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+    }
+
+    #[test]
+    fn self_join_matches_query_with_dataset() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut dataset = random_trajectories(200, 8, &mut rng);
+        let config = IndexConfig::<FreshConfig>::default();
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let mut fresh = fresh.fix_table();
+
+        // a copy of trajectory 0 in the delta tables collides with it everywhere
+        dataset.push(dataset[0].clone());
+        assert_eq!(fresh.insert(&dataset[200]), 200);
+        let expected = fresh
+            .query_collision_collect::<Vec<(usize, usize)>>(&dataset)
+            .into_iter()
+            .filter(|&(q, d)| q < d)
+            .sorted()
+            .collect_vec();
+        let actual = fresh.self_join_collect::<Vec<_>>();
+        assert!(expected.contains(&(0, 200)));
+        assert_eq!(expected, actual.into_iter().sorted().collect_vec());
+
+        let verified = fresh
+            .self_join_with_verification(&dataset, 0.5)
+            .sorted()
+            .collect_vec();
+        let expected = expected
+            .into_iter()
+            .filter(|&(a, b)| dataset[a].frechet_decider(&dataset[b], 0.5))
+            .collect_vec();
+        assert_eq!(expected, verified);
+    }
+
+    #[test]
+    fn parallel_queries_match_sequential_queries() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let config = IndexConfig::<FreshConfig>::default().resolution(2.0);
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let fresh = fresh.fix_table();
+
+        let expected = fresh.query_collision_collect::<Vec<_>>(&queryset);
+        assert!(!expected.is_empty());
+        assert_eq!(expected, fresh.par_query_collision_collect::<Vec<_>>(&queryset));
+
+        let expected =
+            fresh.query_with_verification_fraction::<Vec<_>>(&dataset, &queryset, 1.0, 0.5);
+        let actual =
+            fresh.par_query_with_verification_fraction::<Vec<_>>(&dataset, &queryset, 1.0, 0.5);
+        assert_eq!(expected.skipped_verification, actual.skipped_verification);
+        assert_eq!(expected.full_verify_filtered, actual.full_verify_filtered);
+        assert_eq!(expected.full_verify_unfiltered, actual.full_verify_unfiltered);
+        assert_eq!(expected.candidates, actual.candidates);
+    }
+
+    #[test]
+    fn probes_extend_the_candidates() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let candidates = |config: IndexConfig<FreshConfig>| {
+            let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+            fresh.build(&dataset);
+            fresh.fix_table().query_collision_collect::<Vec<_>>(&queryset)
+        };
+        let config = IndexConfig::<FreshConfig>::default().resolution(2.0);
+        let exact = candidates(config.clone());
+        let probed = candidates(config.clone().probes(4));
+        assert_eq!(exact, candidates(config.probes(0)));
+        assert!(probed.len() > exact.len());
+        assert!(exact.iter().all(|pair| probed.contains(pair)));
+    }
+
+    #[test]
+    fn parallel_build_matches_serial_build() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let config = IndexConfig::<FreshConfig>::default().resolution(2.0);
+
+        let mut serial = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        serial.build(&dataset[..100]);
+        serial.build(&dataset[100..]);
+        let serial = serial.fix_table();
+        let mut parallel = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        parallel.par_build(&dataset[..100]);
+        parallel.par_build(&dataset[100..]);
+        let parallel = parallel.par_fix_table();
+
+        assert_eq!(serial.m_lsh_table.size(), parallel.m_lsh_table.size());
+        for (a, b) in serial
+            .m_lsh_table
+            .m_tables
+            .iter()
+            .zip(&parallel.m_lsh_table.m_tables)
+        {
+            let entries = |table: &LSHTable<u64, true>| {
+                (0..table.m_hash_values.len())
+                    .map(|idx| (table.m_hash_values.hash(idx), table.m_hash_values.id(idx)))
+                    .collect_vec()
+            };
+            assert_eq!(entries(a), entries(b));
+            assert_eq!(a.m_buckets, b.m_buckets);
+        }
+        // every trajectory is stored under its own id
+        let ids = serial.m_lsh_table.m_tables[0].m_hash_values.len();
+        assert_eq!(
+            (0..ids)
+                .map(|idx| serial.m_lsh_table.m_tables[0].m_hash_values.id(idx))
+                .sorted()
+                .collect_vec(),
+            (0..dataset.len()).collect_vec()
+        );
+    }
+
+    #[test]
+    fn per_query_distances_verify_each_query_with_its_own_threshold() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let config = IndexConfig::<FreshConfig>::default().resolution(2.0);
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let fresh = fresh.fix_table();
+
+        // a verify fraction of 1 verifies every candidate
+        let radii = Vec::from_iter((0..queryset.len()).map(|q| [0.5, 3.0][q % 2]));
+        let verify = |distance: f64| {
+            fresh
+                .query_with_verification_fraction::<Vec<_>>(&dataset, &queryset, distance, 1.0)
+                .candidates
+        };
+        let (strict, loose) = (verify(0.5), verify(3.0));
+        let expected = Vec::from_iter(
+            loose
+                .iter()
+                .filter(|&&(q, _)| q % 2 == 1)
+                .chain(strict.iter().filter(|&&(q, _)| q % 2 == 0))
+                .copied()
+                .sorted(),
+        );
+        let actual = fresh.query_with_verification_fraction::<Vec<_>>(
+            &dataset,
+            &queryset,
+            radii.as_slice(),
+            1.0,
+        );
+        assert_ne!(strict.len(), loose.len());
+        assert_eq!(expected, actual.candidates.into_iter().sorted().collect_vec());
+    }
+
+    #[test]
+    fn query_results_carry_collision_counts() {
+        let mut rng = StdRng::seed_from_u64(37);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let queryset = random_trajectories(40, 8, &mut rng);
+        let config = IndexConfig::<FreshConfig>::default().resolution(2.0);
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let fresh = fresh.fix_table();
+
+        let pairs = fresh.query_collision_collect::<Vec<_>>(&queryset);
+        let results: Vec<QueryResult> = fresh.query_results(&queryset, pairs, None);
+        let expected = scores(&fresh, &queryset);
+        assert_eq!(results.len(), expected.len());
+        for (result, (score, pair)) in results.iter().sorted_by_key(|r| r.pair()).zip(expected) {
+            assert_eq!(result.pair(), pair);
+            assert_eq!(result.collisions, Some(score as u32));
+            assert!(!result.verified);
+        }
+
+        let verification =
+            fresh.query_with_verification_fraction::<Vec<_>>(&dataset, &queryset, 1.0, 0.5);
+        let upper_score = verification.upper_score;
+        let results: Vec<QueryResult> =
+            fresh.query_results(&queryset, verification.candidates, Some(upper_score));
+        assert_eq!(
+            results.iter().filter(|result| result.verified).count(),
+            verification.full_verify_unfiltered
+        );
+    }
+
+    #[test]
+    fn query_groups_skip_verification_like_the_batch_query() {
+        let mut rng = StdRng::seed_from_u64(41);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let queryset = &dataset[..30];
+        let config = IndexConfig::<FreshConfig>::default()
+            .resolution(2.0)
+            .verify_fraction(0.5);
+        let fresh = <Fresh<Linear64, true> as SimilarityIndex>::build(&config, None, &dataset);
+
+        let batch =
+            SimilarityIndex::query_with_verification(&fresh, &dataset, queryset, 1.0.into());
+        assert!(batch.skipped_verification > 0);
+        let groups = Vec::from_iter(fresh.query_groups(&dataset, queryset, Some(1.0.into())));
+        let pairs = Vec::from_iter(groups.iter().flat_map(|group| {
+            assert_eq!(group.verification.as_ref().unwrap().upper_score, batch.upper_score);
+            group.results.iter().map(QueryResult::pair)
+        }));
+        assert_eq!(pairs, batch.candidates);
+        let skipped: usize = groups
+            .iter()
+            .map(|group| group.verification.as_ref().unwrap().skipped_verification)
+            .sum();
+        assert_eq!(skipped, batch.skipped_verification);
+    }
+
+    #[test]
+    fn knn_matches_brute_force_when_everything_collides() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let dataset = random_trajectories(100, 8, &mut rng);
+        let queryset = random_trajectories(5, 8, &mut rng);
+        let mut config = IndexConfig::<FreshConfig>::default();
+        config.lsh.resolution = 1000.0;
+
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let fresh = fresh.fix_table();
+        assert!(fresh.knn(&dataset, &queryset, 0).iter().all(Vec::is_empty));
+        let results = fresh.knn(&dataset, &queryset, 7);
+        assert_eq!(results.len(), queryset.len());
+        for (query, neighbours) in queryset.iter().zip(results) {
+            let expected = dataset
+                .iter()
+                .map(|trajectory| query.frechet_distance(trajectory))
+                .enumerate()
+                .sorted_by(|(a_id, a), (b_id, b)| a.total_cmp(b).then(a_id.cmp(b_id)))
+                .take(7)
+                .collect_vec();
+            assert_eq!(neighbours.len(), 7);
+            for ((id, distance), (expected_id, expected)) in neighbours.into_iter().zip(expected) {
+                assert_eq!(id, expected_id);
+                assert!((distance - expected).abs() < 1e-9);
+            }
+        }
+    }
+}
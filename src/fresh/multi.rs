@@ -5,19 +5,36 @@ use crate::lsh::MultiTrajectoryLsh;
 use crate::lsh::TensoredMultiHash;
 use crate::lsh::TrajectoryLsh;
 use crate::point::Distance;
+use crate::result::QueryResult;
 use crate::trajectory::Trajectory;
 use get_size::GetSize;
 use itertools::Itertools;
 use rand::Fill;
+use rayon::prelude::*;
+
+/// The delta tables are merged into the fixed tables once they hold more than
+/// this fraction of the indexed trajectories
+pub const DELTA_MERGE_FRACTION: f64 = 0.1;
+
+/// The delta tables are never merged while they hold fewer trajectories
+pub const DELTA_MERGE_MIN: usize = 1024;
+
+/// The tables are compacted once this fraction of their entries belongs to
+/// deleted trajectories, unless configured otherwise
+pub const TOMBSTONE_COMPACT_FRACTION: f64 = 0.25;
 
 pub struct MultiTable<H, const F: bool = false>
 where
     H: TrajectoryLsh,
     H::Hash: VCodeTools,
 {
-    m_tables: Vec<LSHTable<H::Hash, F>>,
-    m_max_curve_length: usize,
-    m_size: usize,
+    pub(super) m_tables: Vec<LSHTable<H::Hash, F>>,
+    pub(super) m_max_curve_length: usize,
+    pub(super) m_size: usize,
+    pub(super) m_delta_size: usize, // number of inserts and moves since the last merge
+    pub(super) m_deleted: usize,    // number of deleted trajectories
+    pub(super) m_tombstones: usize, // number of deleted trajectories not yet compacted
+    pub(super) m_probes: usize,     // extra buckets probed in every table per query
 }
 
 impl<H> MultiTable<H>
@@ -26,16 +43,20 @@ where
     H::Hash: VCodeTools + Send + Sync,
     [H::Hash]: Fill,
 {
-    pub fn new(tables: Vec<LSHTable<H::Hash>>, max_curve_length: usize) -> Self {
+    pub fn new(tables: Vec<LSHTable<H::Hash>>, max_curve_length: usize, probes: usize) -> Self {
         MultiTable {
             m_size: 0,
             m_tables: tables,
             m_max_curve_length: max_curve_length,
+            m_delta_size: 0,
+            m_deleted: 0,
+            m_tombstones: 0,
+            m_probes: probes,
         }
     }
 
     pub fn put(&mut self, hasher: &TensoredMultiHash<H>, dataset: &[Trajectory]) {
-        // let mut m_tables = self.m_tables;
+        let offset = self.m_size;
         self.m_size += dataset.len();
         dataset
             .iter()
@@ -44,7 +65,32 @@ where
                 self.m_tables
                     .iter_mut()
                     .zip(hasher.multi_hash(trajectory))
-                    .for_each(|(table, hash)| table.put(trajectory_id, hash));
+                    .for_each(|(table, hash)| table.put(offset + trajectory_id, hash));
+            });
+    }
+
+    /// Like `put`, hashing the trajectories and then filling the tables on
+    /// the threads of the current rayon pool
+    pub fn par_put(&mut self, hasher: &TensoredMultiHash<H>, dataset: &[Trajectory])
+    where
+        H: Sync,
+    {
+        let offset = self.m_size;
+        self.m_size += dataset.len();
+        let hashes: Vec<Vec<H::Hash>> = dataset
+            .par_iter()
+            .map(|trajectory| hasher.multi_hash(trajectory).collect())
+            .collect();
+        self.m_tables
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(l, table)| {
+                hashes
+                    .iter()
+                    .enumerate()
+                    .for_each(|(trajectory_id, hashes)| {
+                        table.put(offset + trajectory_id, hashes[l])
+                    });
             });
     }
 
@@ -57,6 +103,27 @@ where
                 .collect(),
             m_max_curve_length: self.m_max_curve_length,
             m_size: self.m_size,
+            m_delta_size: self.m_delta_size,
+            m_deleted: self.m_deleted,
+            m_tombstones: self.m_tombstones,
+            m_probes: self.m_probes,
+        }
+    }
+
+    /// Like `fix_table`, sorting the tables concurrently
+    pub fn par_fix_table(self) -> MultiTable<H, true> {
+        MultiTable {
+            m_tables: self
+                .m_tables
+                .into_par_iter()
+                .map(|table| table.fix_table())
+                .collect(),
+            m_max_curve_length: self.m_max_curve_length,
+            m_size: self.m_size,
+            m_delta_size: self.m_delta_size,
+            m_deleted: self.m_deleted,
+            m_tombstones: self.m_tombstones,
+            m_probes: self.m_probes,
         }
     }
 }
@@ -67,6 +134,105 @@ where
     H::Hash: VCodeTools,
     [H::Hash]: Fill,
 {
+    /// Inserts `trajectory` into the delta tables under the next id, and
+    /// merges the delta tables once they grow past `DELTA_MERGE_FRACTION`
+    pub fn insert(&mut self, hasher: &TensoredMultiHash<H>, trajectory: &Trajectory) -> usize {
+        self.insert_hashes(hasher.multi_hash(trajectory))
+    }
+
+    /// Inserts a trajectory by its hashes, one for every table
+    pub fn insert_hashes(&mut self, hashes: impl IntoIterator<Item = H::Hash>) -> usize {
+        let trajectory_id = self.m_size;
+        self.m_size += 1;
+        self.m_delta_size += 1;
+        self.m_tables
+            .iter_mut()
+            .zip(hashes)
+            .for_each(|(table, hash)| table.insert(trajectory_id, hash));
+        self.merge_if_full();
+        trajectory_id
+    }
+
+    /// Moves `trajectory_id` to new hashes, one for every table, keeping its
+    /// id. The moved entries count towards `DELTA_MERGE_FRACTION` like inserts.
+    pub fn update_hashes(
+        &mut self,
+        trajectory_id: usize,
+        hashes: impl IntoIterator<Item = H::Hash>,
+    ) {
+        self.m_delta_size += 1;
+        self.m_tables
+            .iter_mut()
+            .zip(hashes)
+            .for_each(|(table, hash)| table.update(trajectory_id, hash));
+        self.merge_if_full();
+    }
+
+    fn merge_if_full(&mut self) {
+        if self.m_delta_size >= DELTA_MERGE_MIN
+            && self.m_delta_size as f64 > DELTA_MERGE_FRACTION * self.m_size as f64
+        {
+            self.merge();
+        }
+    }
+
+    /// Whether `trajectory_id` is in the index and not deleted
+    pub fn contains(&self, trajectory_id: usize) -> bool {
+        trajectory_id < self.m_size
+            && !self.m_tables.iter().any(|table| table.is_deleted(trajectory_id))
+    }
+
+    /// Merges the delta tables into the fixed tables
+    pub fn merge(&mut self) {
+        if self.m_delta_size > 0 {
+            self.compact();
+        }
+    }
+
+    /// Marks `trajectory_id` as deleted in every table, and compacts the
+    /// tables once the deleted entries pass `compact_fraction` of all entries.
+    /// Returns false if the trajectory is not in the index.
+    pub fn remove(&mut self, trajectory_id: usize, compact_fraction: f64) -> bool {
+        if trajectory_id >= self.m_size {
+            return false;
+        }
+        let removed = self
+            .m_tables
+            .iter_mut()
+            .fold(false, |removed, table| table.remove(trajectory_id) | removed);
+        if removed {
+            self.m_deleted += 1;
+            self.m_tombstones += 1;
+            let entries = self.m_size - self.m_deleted + self.m_tombstones;
+            if self.m_tombstones as f64 > compact_fraction * entries as f64 {
+                self.compact();
+            }
+        }
+        removed
+    }
+
+    /// Merges the delta tables into the fixed tables and drops the entries of
+    /// deleted trajectories
+    pub fn compact(&mut self) {
+        self.m_tables.iter_mut().for_each(|table| table.compact());
+        self.m_delta_size = 0;
+        self.m_tombstones = 0;
+    }
+
+    pub fn delta_size(&self) -> usize {
+        self.m_delta_size
+    }
+
+    /// The ids of the deleted trajectories, which are marked in every table
+    pub fn deleted_ids(&self) -> Vec<usize> {
+        match self.m_tables.first() {
+            Some(table) => (0..self.m_size)
+                .filter(|&idx| table.is_deleted(idx))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn query_iter<'a>(
         &'a self,
         hasher: &'a TensoredMultiHash<H>,
@@ -98,6 +264,108 @@ where
         scores.into_iter()
     }
 
+    /// Turns result pairs into records carrying the number of tables each
+    /// pair collides in. The collisions are counted once for every run of
+    /// pairs with the same query, so the pairs must be grouped by query, as
+    /// every query of the index returns them.
+    pub fn query_results<'a>(
+        &'a self,
+        hasher: &'a TensoredMultiHash<H>,
+        queryset: &'a [Trajectory],
+        pairs: impl IntoIterator<Item = CurveToIdx<usize>> + 'a,
+    ) -> impl Iterator<Item = QueryResult> + 'a {
+        let mut counters = vec![0f64; self.m_size];
+        let mut counted = None;
+        let mut counted_queries = vec![false; queryset.len()];
+        pairs.into_iter().map(move |(qid, tid)| {
+            if counted != Some(qid) {
+                debug_assert!(!counted_queries[qid], "pairs of query {qid} are not grouped");
+                counted_queries[qid] = true;
+                let _ = self.count_collisions(hasher, &queryset[qid], &mut counters);
+                counted = Some(qid);
+            }
+            QueryResult {
+                collisions: Some(counters[tid] as u32),
+                ..QueryResult::new(qid, tid)
+            }
+        })
+    }
+
+    /// Like `query_iter`, with the queries split across the threads of the
+    /// current rayon pool. The pairs are ordered by query id.
+    pub fn par_query_iter(
+        &self,
+        hasher: &TensoredMultiHash<H>,
+        queryset: &[Trajectory],
+    ) -> Vec<CurveToIdx<usize>>
+    where
+        H: Sync,
+        H::Hash: Sync,
+    {
+        queryset
+            .par_iter()
+            .enumerate()
+            .flat_map_iter(|(qid, query)| {
+                self.collision_iter(hasher, query)
+                    .unique()
+                    .map(move |did| (qid, did))
+            })
+            .collect()
+    }
+
+    /// Like `query_count_scores`, with the queries split across the threads
+    /// of the current rayon pool. Every thread counts into its own counters.
+    pub fn par_query_count_scores(
+        &self,
+        hasher: &TensoredMultiHash<H>,
+        queryset: &[Trajectory],
+    ) -> Vec<(Distance, CurveToIdx<usize>)>
+    where
+        H: Sync,
+        H::Hash: Sync,
+    {
+        queryset
+            .par_iter()
+            .enumerate()
+            .map_init(
+                || vec![0f64; self.m_size],
+                |counters, (qid, query)| {
+                    self.count_collisions(hasher, query, counters)
+                        .map(|(tid, collisions)| (*collisions, (qid, tid)))
+                        .collect::<Vec<_>>()
+                },
+            )
+            .flatten_iter()
+            .collect()
+    }
+
+    /// Pairs the trajectories that share a bucket in any of the tables. Each
+    /// unordered pair is returned once as `(smaller id, larger id)`, from the
+    /// smaller id and the first table the two collide in.
+    pub fn self_join_iter(&self) -> impl Iterator<Item = CurveToIdx<usize>> + '_ {
+        let hashes: Vec<Vec<Option<H::Hash>>> = self
+            .m_tables
+            .iter()
+            .map(|table| table.hashes_by_id(self.m_size))
+            .collect();
+        (0..self.m_size).flat_map(move |i| {
+            let hashes = &hashes;
+            self.m_tables
+                .iter()
+                .zip(hashes)
+                .enumerate()
+                .filter_map(|(l, (table, table_hashes))| Some((l, table, table_hashes[i]?)))
+                .flat_map(|(l, table, hash)| {
+                    table.collision_iter(hash).filter(move |&candidate| {
+                        candidate > i
+                            && hashes[..l].iter().all(|h| h[i] != h[candidate])
+                    })
+                })
+                .map(|candidate| (i, candidate))
+                .collect_vec()
+        })
+    }
+
     fn collision_iter<'a>(
         &'a self,
         hasher: &'a TensoredMultiHash<H>,
@@ -105,9 +373,32 @@ where
     ) -> impl Iterator<Item = usize> + 'a {
         self.m_tables
             .iter()
-            .zip(hasher.multi_hash(query))
-            .filter_map(|(table, hash)| table.collision_iter(hash))
-            .flatten()
+            .zip(self.query_hashes(hasher, query))
+            .flat_map(|(table, hashes)| {
+                hashes
+                    .into_iter()
+                    .flat_map(move |hash| table.collision_iter(hash))
+            })
+    }
+
+    /// The hashes of `query` looked up in every table. Without probes this
+    /// is the exact hash only, otherwise the probed buckets follow it. The
+    /// buckets of a table are disjoint, so no trajectory collides twice in
+    /// one table.
+    fn query_hashes(
+        &self,
+        hasher: &TensoredMultiHash<H>,
+        query: &Trajectory,
+    ) -> Vec<Vec<H::Hash>> {
+        if self.m_probes == 0 {
+            hasher.multi_hash(query).map(|hash| vec![hash]).collect()
+        } else {
+            hasher.multi_probe_hash(query, self.m_probes)
+        }
+    }
+
+    pub fn set_probes(&mut self, probes: usize) {
+        self.m_probes = probes;
     }
 
     /// Count collisions for a given query.
@@ -122,9 +413,11 @@ where
         counters.fill(0.0);
         self.m_tables
             .iter()
-            .zip(hasher.multi_hash(query))
-            .fold(counters, |acc, (table, hash)| {
-                table.count_colissions(hash, acc)
+            .zip(self.query_hashes(hasher, query))
+            .fold(counters, |acc, (table, hashes)| {
+                hashes
+                    .into_iter()
+                    .fold(acc, |acc, hash| table.count_colissions(hash, acc))
             })
             .iter()
             .enumerate()
@@ -149,6 +442,10 @@ where
     pub fn size(&self) -> usize {
         self.m_size
     }
+
+    pub fn deleted(&self) -> usize {
+        self.m_deleted
+    }
 }
 
 impl<H, const B: bool> GetSize for MultiTable<H, B>
@@ -162,5 +459,69 @@ where
             .sum::<usize>()
             + self.m_max_curve_length.get_heap_size()
             + self.m_size.get_heap_size()
+            + self.m_delta_size.get_heap_size()
+            + self.m_deleted.get_heap_size()
+            + self.m_tombstones.get_heap_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lsh::Linear64;
+    use crate::test_util::random_trajectories;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn fixed_tables(
+        hasher: &TensoredMultiHash<Linear64>,
+        chunks: &[&[Trajectory]],
+    ) -> MultiTable<Linear64, true> {
+        let n = chunks.iter().map(|chunk| chunk.len()).sum();
+        let tables = Vec::from_iter((0..hasher.hash_length()).map(|_| LSHTable::new(n, 8)));
+        let mut table = MultiTable::<Linear64>::new(tables, 8, 0);
+        chunks.iter().for_each(|chunk| table.put(hasher, chunk));
+        table.fix_table()
+    }
+
+    #[test]
+    fn chunked_puts_match_a_single_put() {
+        // each put continues at the ids of the trajectories put before it,
+        // instead of overwriting the first slots of the tables
+        let mut rng = StdRng::seed_from_u64(9);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let hasher = TensoredMultiHash::<Linear64>::init(8, 2, 1.0, 8, &mut rng);
+        let single = fixed_tables(&hasher, &[&dataset]);
+        let chunked = fixed_tables(&hasher, &[&dataset[..120], &dataset[120..]]);
+
+        let expected = Vec::from_iter(single.query_iter(&hasher, &dataset).sorted());
+        assert!((0..dataset.len()).all(|id| expected.contains(&(id, id))));
+        assert_eq!(expected, Vec::from_iter(chunked.query_iter(&hasher, &dataset).sorted()));
+    }
+
+    #[test]
+    fn query_results_count_like_the_scored_query() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let hasher = TensoredMultiHash::<Linear64>::init(8, 2, 1.0, 8, &mut rng);
+        let table = fixed_tables(&hasher, &[&dataset]);
+        let queryset = &dataset[..20];
+
+        let (scores, pairs): (Vec<_>, Vec<_>) =
+            table.query_count_scores(&hasher, queryset).unzip();
+        let results = Vec::from_iter(table.query_results(&hasher, queryset, pairs.clone()));
+        assert!(results.iter().zip(&pairs).all(|(r, &pair)| (r.query, r.candidate) == pair));
+        assert!(results.iter().zip(&scores).all(|(r, &s)| r.collisions == Some(s as u32)));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not grouped")]
+    fn query_results_need_pairs_grouped_by_query() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let dataset = random_trajectories(50, 8, &mut rng);
+        let hasher = TensoredMultiHash::<Linear64>::init(8, 2, 1.0, 8, &mut rng);
+        let table = fixed_tables(&hasher, &[&dataset]);
+        table.query_results(&hasher, &dataset, [(0, 0), (1, 1), (0, 2)]).for_each(drop);
     }
 }
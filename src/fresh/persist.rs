@@ -0,0 +1,350 @@
+use super::multi::MultiTable;
+use super::store::read_hash;
+use super::store::write_hash;
+use super::store::HashValues;
+use super::store::MappedHashValues;
+use super::table::LSHTable;
+use super::Fresh;
+use crate::config::map_master_path;
+use crate::config::master_result_dir;
+use crate::config::LshConfig;
+use crate::config::QueryBudget;
+use crate::dyft::VCodeTools;
+use crate::lsh::TensoredMultiHash;
+use crate::lsh::TrajectoryLsh;
+use crate::metric::VerificationMetric;
+use anyhow::anyhow;
+use anyhow::Result;
+use bitvec::vec::BitVec;
+use itertools::Itertools;
+use memmap2::Mmap;
+use rand::Fill;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Magic bytes at the start of every persisted FRESH index file
+pub const FRESH_INDEX_MAGIC: &[u8; 8] = b"FRESHIDX";
+
+/// Version of the on-disk layout, bumped whenever the layout changes
+pub const FRESH_INDEX_VERSION: u32 = 3;
+
+/// Sections are aligned to this many bytes from the start of the file
+const FRESH_INDEX_ALIGN: usize = 8;
+
+/// The header of a persisted FRESH index, followed by the serialized hasher
+/// and the table sections. Section offsets are relative to the first aligned
+/// byte after the hasher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreshIndexHeader {
+    pub version: u32,
+    pub hash_bits: usize,
+    pub lsh: LshConfig,
+    pub max_curve_length: usize,
+    pub size: usize,
+    pub tau: Option<f64>,
+    pub compact_fraction: Option<f64>,
+    pub deleted: Vec<usize>,
+    pub tombstones: usize,
+    pub hasher_fingerprint: u64,
+    pub tables: Vec<FreshTableHeader>,
+}
+
+/// The layout of one fixed LSH table. Its sections hold `len` hashes, `len`
+/// trajectory ids and `num_buckets` (hash, start, end) records.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreshTableHeader {
+    pub len: usize,
+    pub distinct: usize,
+    pub num_buckets: usize,
+    pub hashes: usize,
+    pub ids: usize,
+    pub buckets: usize,
+}
+
+fn align(offset: usize) -> usize {
+    offset.div_ceil(FRESH_INDEX_ALIGN) * FRESH_INDEX_ALIGN
+}
+
+fn pad<W: Write>(writer: &mut W, written: usize) -> std::io::Result<usize> {
+    let padding = align(written) - written;
+    writer.write_all(&[0u8; FRESH_INDEX_ALIGN][..padding])?;
+    Ok(written + padding)
+}
+
+impl<T: VCodeTools> LSHTable<T, true> {
+    fn section_header(&self, offset: usize) -> FreshTableHeader {
+        let len = self.m_hash_values.len();
+        let hashes = offset;
+        let ids = align(hashes + len * std::mem::size_of::<T>());
+        let buckets = ids + len * std::mem::size_of::<u64>();
+        FreshTableHeader {
+            len,
+            distinct: self.m_distinct,
+            num_buckets: self.m_buckets.len(),
+            hashes,
+            ids,
+            buckets,
+        }
+    }
+
+    fn section_end(header: &FreshTableHeader) -> usize {
+        align(header.buckets + header.num_buckets * (std::mem::size_of::<T>() + 16))
+    }
+
+    fn write_sections<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let len = self.m_hash_values.len();
+        for idx in 0..len {
+            write_hash(self.m_hash_values.hash(idx), writer)?;
+        }
+        let mut written = pad(writer, len * std::mem::size_of::<T>())?;
+        for idx in 0..len {
+            writer.write_all(&(self.m_hash_values.id(idx) as u64).to_le_bytes())?;
+        }
+        written += len * std::mem::size_of::<u64>();
+        for (&hash, &(start, end)) in self.m_buckets.iter().sorted_by_key(|(_, &range)| range) {
+            write_hash(hash, writer)?;
+            writer.write_all(&(start as u64).to_le_bytes())?;
+            writer.write_all(&(end as u64).to_le_bytes())?;
+        }
+        written += self.m_buckets.len() * (std::mem::size_of::<T>() + 16);
+        pad(writer, written)?;
+        Ok(())
+    }
+
+    fn from_mapped(
+        mmap: &Arc<Mmap>,
+        header: &FreshTableHeader,
+        data: usize,
+        max_curve_length: usize,
+        deleted: &[usize],
+    ) -> Self {
+        let width = std::mem::size_of::<T>();
+        let record = width + 16;
+        let start = data + header.buckets;
+        let m_buckets = (0..header.num_buckets)
+            .map(|i| {
+                let bytes = &mmap[start + i * record..start + (i + 1) * record];
+                let begin = u64::from_le_bytes(bytes[width..width + 8].try_into().unwrap());
+                let end = u64::from_le_bytes(bytes[width + 8..].try_into().unwrap());
+                (read_hash(&bytes[..width]), (begin as usize, end as usize))
+            })
+            .collect::<HashMap<_, _>>();
+        LSHTable {
+            m_hash_values: HashValues::Mapped(MappedHashValues::new(
+                mmap.clone(),
+                data + header.hashes,
+                data + header.ids,
+                header.len,
+            )),
+            m_buckets,
+            m_max_curve_length: max_curve_length,
+            m_distinct: header.distinct,
+            m_delta: HashMap::new(),
+            m_delta_hashes: HashMap::new(),
+            m_tombstones: deleted.iter().fold(BitVec::new(), |mut tombstones, &idx| {
+                if idx >= tombstones.len() {
+                    tombstones.resize(idx + 1, false);
+                }
+                tombstones.set(idx, true);
+                tombstones
+            }),
+        }
+    }
+}
+
+impl<H> Fresh<H, true>
+where
+    H: TrajectoryLsh,
+    [H::Hash]: Fill,
+{
+    pub fn header(&self) -> FreshIndexHeader {
+        let mut offset = 0;
+        let tables = self
+            .m_lsh_table
+            .m_tables
+            .iter()
+            .map(|table| {
+                let header = table.section_header(offset);
+                offset = LSHTable::<H::Hash, true>::section_end(&header);
+                header
+            })
+            .collect();
+        FreshIndexHeader {
+            version: FRESH_INDEX_VERSION,
+            hash_bits: H::Hash::N_DIM,
+            lsh: self.m_lsh_config.clone(),
+            max_curve_length: self.m_lsh_table.m_max_curve_length,
+            size: self.m_lsh_table.m_size,
+            tau: self.m_tau,
+            compact_fraction: self.m_compact_fraction,
+            deleted: self.m_lsh_table.deleted_ids(),
+            tombstones: self.m_lsh_table.m_tombstones,
+            hasher_fingerprint: self.m_hasher.fingerprint(),
+            tables,
+        }
+    }
+
+    /// Saves the fixed tables to `path`, relative to $MASTER_RESULT_DIR if set.
+    /// Inserts still held in the delta tables must be merged first. The file
+    /// is written next to `path` and renamed over it, so an index mapped from
+    /// `path` keeps its contents.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        if self.m_lsh_table.delta_size() > 0 {
+            return Err(anyhow!(
+                "FRESH index has {} unmerged inserts, merge them before saving",
+                self.m_lsh_table.delta_size()
+            ));
+        }
+        let path = map_master_path(path, master_result_dir());
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        self.write_to(File::create(&tmp_path)?)?;
+        std::fs::rename(&tmp_path, &path).map_err(|e| anyhow!(e))
+    }
+
+    fn write_to(&self, file: File) -> Result<()> {
+        let mut writer = BufWriter::new(file);
+        let header = rmp_serde::to_vec_named(&self.header())?;
+        let hasher = rmp_serde::to_vec(&self.m_hasher)?;
+        writer.write_all(FRESH_INDEX_MAGIC)?;
+        writer.write_all(&(header.len() as u64).to_le_bytes())?;
+        writer.write_all(&header)?;
+        writer.write_all(&(hasher.len() as u64).to_le_bytes())?;
+        writer.write_all(&hasher)?;
+        pad(
+            &mut writer,
+            FRESH_INDEX_MAGIC.len() + 16 + header.len() + hasher.len(),
+        )?;
+        for table in self.m_lsh_table.m_tables.iter() {
+            table.write_sections(&mut writer)?;
+        }
+        writer.flush().map_err(|e| anyhow!(e))
+    }
+
+    /// Opens an index saved with `save` from `path`, relative to $MASTER_RESULT_DIR if set.
+    /// The hash values are memory mapped rather than read into memory.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(map_master_path(path, master_result_dir()))?;
+        // SAFETY: the mapping is read only and index files are never written
+        // in place, `save` renames a new file over the old one, so the mapped
+        // file is neither truncated nor changed by this crate while it is
+        // mapped. Index files must not be modified in place by other processes.
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+        if mmap.len() < 16 || &mmap[..8] != FRESH_INDEX_MAGIC {
+            return Err(anyhow!("not a FRESH index file"));
+        }
+        let header_len = u64::from_le_bytes(mmap[8..16].try_into().unwrap()) as usize;
+        let header: FreshIndexHeader = rmp_serde::from_slice(
+            mmap.get(16..16 + header_len)
+                .ok_or_else(|| anyhow!("truncated FRESH index header"))?,
+        )?;
+        if header.version != FRESH_INDEX_VERSION {
+            return Err(anyhow!(
+                "FRESH index file has version {}, expected {}",
+                header.version,
+                FRESH_INDEX_VERSION
+            ));
+        }
+        if header.hash_bits != H::Hash::N_DIM {
+            return Err(anyhow!(
+                "FRESH index file was built with {}-bit hashes, expected {}-bit hashes",
+                header.hash_bits,
+                H::Hash::N_DIM
+            ));
+        }
+        let hasher_len = mmap
+            .get(16 + header_len..24 + header_len)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or_else(|| anyhow!("truncated FRESH index file"))?;
+        let m_hasher: TensoredMultiHash<H> = rmp_serde::from_slice(
+            mmap.get(24 + header_len..24 + header_len + hasher_len)
+                .ok_or_else(|| anyhow!("truncated FRESH index file"))?,
+        )?;
+        m_hasher.check_fingerprint(header.hasher_fingerprint)?;
+        let data = align(24 + header_len + hasher_len);
+        let end = header
+            .tables
+            .last()
+            .map_or(0, LSHTable::<H::Hash, true>::section_end);
+        if data + end > mmap.len() {
+            return Err(anyhow!("truncated FRESH index file"));
+        }
+
+        let m_tables = header
+            .tables
+            .iter()
+            .map(|table| {
+                LSHTable::from_mapped(
+                    &mmap,
+                    table,
+                    data,
+                    header.max_curve_length,
+                    &header.deleted,
+                )
+            })
+            .collect();
+        Ok(Fresh {
+            m_hasher,
+            m_lsh_table: MultiTable {
+                m_tables,
+                m_max_curve_length: header.max_curve_length,
+                m_size: header.size,
+                m_delta_size: 0,
+                m_deleted: header.deleted.len(),
+                m_tombstones: header.tombstones,
+                m_probes: 0,
+            },
+            m_tau: header.tau,
+            m_compact_fraction: header.compact_fraction,
+            m_budget: QueryBudget::default(),
+            m_bbox_filter: None,
+            m_metric: VerificationMetric::default(),
+            m_lsh_config: header.lsh,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_trajectories;
+    use crate::config::FreshConfig;
+    use crate::config::IndexConfig;
+    use crate::lsh::Linear64;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let dataset = random_trajectories(500, 8, &mut rng);
+        let queryset = random_trajectories(20, 8, &mut rng);
+        let mut config = IndexConfig::<FreshConfig>::default();
+        config.lsh.resolution = 1.0;
+
+        let mut fresh = Fresh::<Linear64>::new(&config, dataset.len(), 8);
+        fresh.build(&dataset);
+        let mut fresh = fresh.fix_table();
+        assert!(fresh.remove(3));
+
+        let path = std::env::temp_dir().join(format!("fresh-{}.idx", std::process::id()));
+        fresh.save(&path).unwrap();
+        let loaded = Fresh::<Linear64, true>::load(&path).unwrap();
+        // saving over the mapped file must leave the loaded index intact
+        loaded.save(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(fresh.stats().distinct, loaded.stats().distinct);
+        assert_eq!(loaded.stats().deleted, 1);
+        let expected: Vec<(usize, usize)> = fresh.query_collision_collect(&queryset);
+        let actual: Vec<(usize, usize)> = loaded.query_collision_collect(&queryset);
+        assert!(!expected.is_empty());
+        assert_eq!(expected, actual);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::dyft::VCodeTools;
+use get_size::GetSize;
+use memmap2::Mmap;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The sorted (hash, trajectory_idx) pairs of an LSH table, either owned by
+/// the table or read directly from a memory mapped index file.
+pub enum HashValues<T> {
+    Owned(Vec<(T, usize)>),
+    Mapped(MappedHashValues<T>),
+}
+
+/// A view of the hash and id sections of a memory mapped index file. Hashes
+/// are stored as fixed-width little endian integers of `size_of::<T>()`
+/// bytes, and ids as little endian `u64`.
+pub struct MappedHashValues<T> {
+    m_mmap: Arc<Mmap>,
+    m_hashes: usize, // byte offset of the hash section
+    m_ids: usize,    // byte offset of the id section
+    m_len: usize,
+    m_marker: PhantomData<T>,
+}
+
+impl<T: VCodeTools> MappedHashValues<T> {
+    pub fn new(mmap: Arc<Mmap>, hashes: usize, ids: usize, len: usize) -> Self {
+        assert!(hashes + len * std::mem::size_of::<T>() <= mmap.len());
+        assert!(ids + len * std::mem::size_of::<u64>() <= mmap.len());
+        MappedHashValues {
+            m_mmap: mmap,
+            m_hashes: hashes,
+            m_ids: ids,
+            m_len: len,
+            m_marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn hash(&self, idx: usize) -> T {
+        let width = std::mem::size_of::<T>();
+        let start = self.m_hashes + idx * width;
+        read_hash(&self.m_mmap[start..start + width])
+    }
+
+    #[inline]
+    pub fn id(&self, idx: usize) -> usize {
+        let start = self.m_ids + idx * std::mem::size_of::<u64>();
+        let bytes = self.m_mmap[start..start + 8].try_into().unwrap();
+        u64::from_le_bytes(bytes) as usize
+    }
+}
+
+impl<T: VCodeTools> HashValues<T> {
+    pub fn len(&self) -> usize {
+        match self {
+            HashValues::Owned(values) => values.len(),
+            HashValues::Mapped(values) => values.m_len,
+        }
+    }
+
+    #[inline]
+    pub fn hash(&self, idx: usize) -> T {
+        match self {
+            HashValues::Owned(values) => values[idx].0,
+            HashValues::Mapped(values) => values.hash(idx),
+        }
+    }
+
+    #[inline]
+    pub fn id(&self, idx: usize) -> usize {
+        match self {
+            HashValues::Owned(values) => values[idx].1,
+            HashValues::Mapped(values) => values.id(idx),
+        }
+    }
+
+    /// The owned hash values of a table that is still being built
+    pub fn owned_mut(&mut self) -> &mut Vec<(T, usize)> {
+        match self {
+            HashValues::Owned(values) => values,
+            HashValues::Mapped(_) => {
+                panic!("HashValues::owned_mut: mapped hash values are read only")
+            }
+        }
+    }
+}
+
+impl<T> GetSize for HashValues<T>
+where
+    T: GetSize,
+{
+    fn get_heap_size(&self) -> usize {
+        match self {
+            HashValues::Owned(values) => values.get_heap_size(),
+            // the mapped pages are owned by the operating system
+            HashValues::Mapped(_) => 0,
+        }
+    }
+}
+
+pub fn write_hash<T, W>(hash: T, writer: &mut W) -> std::io::Result<()>
+where
+    T: VCodeTools,
+    W: Write,
+{
+    let width = std::mem::size_of::<T>();
+    let bytes = hash.to_u128().unwrap().to_le_bytes();
+    writer.write_all(&bytes[..width])
+}
+
+pub fn read_hash<T>(bytes: &[u8]) -> T
+where
+    T: VCodeTools,
+{
+    let mut buf = [0u8; 16];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    T::from_u128(u128::from_le_bytes(buf)).unwrap()
+}
@@ -1,28 +1,41 @@
+use super::store::HashValues;
 use crate::dyft::VCodeTools;
 use crate::lsh::CurveToIdx;
+use bitvec::vec::BitVec;
 use get_size::GetSize;
 use std::collections::HashMap;
 
 #[derive(GetSize)]
 pub struct LSHTable<T, const F: bool = false> {
-    m_hash_values: Vec<(T, usize)>,           // (hash, trajectory_idx)
-    m_buckets: HashMap<T, CurveToIdx<usize>>, // (hash, (start, end))
-    m_max_curve_length: usize,
-    m_distinct: usize,
+    pub(super) m_hash_values: HashValues<T>, // (hash, trajectory_idx)
+    pub(super) m_buckets: HashMap<T, CurveToIdx<usize>>, // (hash, (start, end))
+    pub(super) m_max_curve_length: usize,
+    pub(super) m_distinct: usize,
+    pub(super) m_delta: HashMap<T, Vec<usize>>, // (hash, trajectory_ids) inserted after fixing
+    pub(super) m_delta_hashes: HashMap<usize, T>, // trajectory_idx -> hash of its delta entry
+    #[get_size(size_fn = tombstones_heap_size)]
+    pub(super) m_tombstones: BitVec, // trajectory_idx -> deleted
+}
+
+fn tombstones_heap_size(tombstones: &BitVec) -> usize {
+    tombstones.capacity() / 8
 }
 
 impl<T: VCodeTools> LSHTable<T, false> {
     pub fn new(num_curves: usize, max_curve_length: usize) -> Self {
         LSHTable::<T, false> {
-            m_hash_values: vec![(T::zero(), 0); num_curves],
+            m_hash_values: HashValues::Owned(vec![(T::zero(), 0); num_curves]),
             m_buckets: HashMap::with_capacity(0),
             m_max_curve_length: max_curve_length,
             m_distinct: 0,
+            m_delta: HashMap::new(),
+            m_delta_hashes: HashMap::new(),
+            m_tombstones: BitVec::new(),
         }
     }
 
     pub fn put(&mut self, index: usize, hash: T) {
-        if let Some((h, idx)) = self.m_hash_values.get_mut(index) {
+        if let Some((h, idx)) = self.m_hash_values.owned_mut().get_mut(index) {
             *h = hash;
             *idx = index;
         }
@@ -30,49 +43,32 @@ impl<T: VCodeTools> LSHTable<T, false> {
 
     #[allow(dead_code)]
     pub fn fix_table(mut self) -> LSHTable<T, true> {
-        self.m_hash_values.sort();
-        let mut distinct = 1;
-        let mut begin_idx = 0;
-        let (mut last, _) = self
-            .m_hash_values
-            .first_mut()
-            .expect("LSHTable::fix_table: cannot fix an empty table!");
-        let n = self.m_hash_values.len();
-
-        for idx_i in 1..n {
-            let (hash, _) = self.m_hash_values[idx_i];
-            if idx_i == n - 1 || last != hash {
-                distinct += 1;
-                self.m_buckets.insert(last, (begin_idx, idx_i));
-                begin_idx = idx_i;
-                if idx_i != n - 1 {
-                    last = hash;
-                }
-            }
-        }
-
+        self.m_hash_values.owned_mut().sort();
+        let m_buckets = bucket_ranges(&self.m_hash_values);
         LSHTable::<T, true> {
             m_hash_values: self.m_hash_values,
-            m_buckets: self.m_buckets,
+            m_distinct: m_buckets.len(),
+            m_buckets,
             m_max_curve_length: self.m_max_curve_length,
-            m_distinct: distinct,
+            m_delta: self.m_delta,
+            m_delta_hashes: self.m_delta_hashes,
+            m_tombstones: self.m_tombstones,
         }
     }
 
     #[allow(dead_code)]
     pub fn new_fix_table(mut self) -> LSHTable<T, true> {
+        let hash_values = self.m_hash_values.owned_mut();
         let mut distinct = 1;
         let mut begin_idx = 0;
-        let (mut last, _) = self
-            .m_hash_values
+        let (mut last, _) = hash_values
             .first_mut()
             .expect("LSHTable::fix_table: cannot fix an empty table!");
-        let n = self.m_hash_values.len();
+        let n = hash_values.len();
 
-        self.m_hash_values.shrink_to_fit();
-        self.m_hash_values.sort_unstable();
-        let m_buckets: HashMap<T, CurveToIdx<usize>> = self
-            .m_hash_values
+        hash_values.shrink_to_fit();
+        hash_values.sort_unstable();
+        let m_buckets: HashMap<T, CurveToIdx<usize>> = hash_values
             .iter()
             .skip(1)
             .zip(1..n)
@@ -98,30 +94,121 @@ impl<T: VCodeTools> LSHTable<T, false> {
             m_hash_values: self.m_hash_values,
             m_max_curve_length: self.m_max_curve_length,
             m_distinct: distinct,
+            m_delta: self.m_delta,
+            m_delta_hashes: self.m_delta_hashes,
+            m_tombstones: self.m_tombstones,
         }
     }
 }
 
 impl<T: VCodeTools> LSHTable<T, true> {
-    pub fn collision_iter<'a>(&'a self, hash: T) -> Option<impl Iterator<Item = usize> + 'a> {
+    /// Iterates the live trajectories colliding with `hash` in the fixed
+    /// table and in the delta table. The fixed entry of a trajectory with an
+    /// entry in the delta table is stale, the trajectory was moved.
+    pub fn collision_iter<'a>(&'a self, hash: T) -> impl Iterator<Item = usize> + 'a {
+        let fixed = self
+            .fixed_iter(hash)
+            .filter(|idx| !self.m_delta_hashes.contains_key(idx));
+        let delta = self.m_delta.get(&hash).into_iter().flatten().copied();
+        fixed.chain(delta).filter(|&idx| !self.is_deleted(idx))
+    }
+
+    fn fixed_iter(&self, hash: T) -> impl Iterator<Item = usize> + '_ {
         self.m_buckets
             .get(&hash)
-            .map(|&(start, end)| (start..end).map(|idx| self.m_hash_values[idx].1))
+            .into_iter()
+            .flat_map(|&(start, end)| (start..end).map(|idx| self.m_hash_values.id(idx)))
     }
 
-    pub fn count_colissions<'a>(&self, hash: T, counters: &'a mut [f64]) -> &'a mut [f64] {
-        if let Some(collisions) = self.collision_iter(hash) {
-            collisions.for_each(|idx| {
-                counters[idx] += 1.0;
-            });
+    /// The hash of every live trajectory below `size`, by trajectory id
+    pub fn hashes_by_id(&self, size: usize) -> Vec<Option<T>> {
+        let mut hashes = vec![None; size];
+        let fixed = (0..self.m_hash_values.len())
+            .map(|idx| (self.m_hash_values.hash(idx), self.m_hash_values.id(idx)))
+            .filter(|(_, id)| !self.m_delta_hashes.contains_key(id));
+        let delta = self.m_delta_hashes.iter().map(|(&id, &hash)| (hash, id));
+        fixed
+            .chain(delta)
+            .filter(|&(_, id)| id < size && !self.is_deleted(id))
+            .for_each(|(hash, id)| hashes[id] = Some(hash));
+        hashes
+    }
+
+    pub fn is_deleted(&self, index: usize) -> bool {
+        self.m_tombstones.get(index).is_some_and(|bit| *bit)
+    }
+
+    /// Marks a trajectory as deleted. Its entry stays in the table, hidden
+    /// from the collisions, until the table is compacted. Returns false if
+    /// the trajectory was already deleted.
+    pub fn remove(&mut self, index: usize) -> bool {
+        if self.is_deleted(index) {
+            return false;
+        }
+        if index >= self.m_tombstones.len() {
+            self.m_tombstones.resize(index + 1, false);
         }
+        self.m_tombstones.set(index, true);
+        true
+    }
+
+    /// Inserts a trajectory into the delta table, it is only visible to the
+    /// buckets of the fixed table after `compact`
+    pub fn insert(&mut self, index: usize, hash: T) {
+        self.m_delta.entry(hash).or_default().push(index);
+        self.m_delta_hashes.insert(index, hash);
+    }
+
+    /// Moves a trajectory to `hash`, keeping its index. Its old entry in the
+    /// delta table is dropped, while an old entry in the fixed table is only
+    /// hidden until `compact`.
+    pub fn update(&mut self, index: usize, hash: T) {
+        if let Some(old) = self.m_delta_hashes.remove(&index) {
+            if let Some(ids) = self.m_delta.get_mut(&old) {
+                ids.retain(|&id| id != index);
+                if ids.is_empty() {
+                    self.m_delta.remove(&old);
+                }
+            }
+        }
+        // a trajectory moved back to the hash of its fixed entry uses it again
+        if !self.fixed_iter(hash).any(|id| id == index) {
+            self.insert(index, hash);
+        }
+    }
+
+    /// Merges the delta table into the fixed table and drops the entries of
+    /// deleted trajectories, rebuilding the bucket ranges. A memory mapped
+    /// table is read into memory in the process.
+    pub fn compact(&mut self) {
+        let moved = std::mem::take(&mut self.m_delta_hashes);
+        let mut hash_values: Vec<(T, usize)> = (0..self.m_hash_values.len())
+            .map(|idx| (self.m_hash_values.hash(idx), self.m_hash_values.id(idx)))
+            .filter(|(_, id)| !moved.contains_key(id))
+            .chain(
+                self.m_delta
+                    .drain()
+                    .flat_map(|(hash, ids)| ids.into_iter().map(move |id| (hash, id))),
+            )
+            .filter(|&(_, id)| !self.m_tombstones.get(id).is_some_and(|bit| *bit))
+            .collect();
+        hash_values.sort_unstable();
+        self.m_hash_values = HashValues::Owned(hash_values);
+        self.m_buckets = bucket_ranges(&self.m_hash_values);
+        self.m_distinct = self.m_buckets.len();
+    }
+
+    pub fn count_colissions<'a>(&self, hash: T, counters: &'a mut [f64]) -> &'a mut [f64] {
+        self.collision_iter(hash).for_each(|idx| {
+            counters[idx] += 1.0;
+        });
         counters
     }
 
     #[allow(dead_code)]
     pub fn for_each_collision(&self, hash: T, visited: &mut [bool], callback: impl Fn(usize)) {
-        if let Some(collisions) = self.collision_iter(hash) {
-            collisions.for_each(|idx| match visited.get_mut(idx) {
+        self.collision_iter(hash)
+            .for_each(|idx| match visited.get_mut(idx) {
                 Some(visited) => {
                     if !*visited {
                         *visited = true;
@@ -130,10 +217,57 @@ impl<T: VCodeTools> LSHTable<T, true> {
                 }
                 None => {}
             });
-        }
     }
 
     pub fn distinct_hashes(&self) -> usize {
         self.m_distinct
+            + self
+                .m_delta
+                .keys()
+                .filter(|hash| !self.m_buckets.contains_key(hash))
+                .count()
+    }
+}
+
+/// Maps every hash of the sorted `hash_values` to the range of its bucket
+fn bucket_ranges<T: VCodeTools>(hash_values: &HashValues<T>) -> HashMap<T, CurveToIdx<usize>> {
+    let mut m_buckets = HashMap::new();
+    let mut begin_idx = 0;
+    for idx in 1..=hash_values.len() {
+        let last = hash_values.hash(idx - 1);
+        if idx == hash_values.len() || hash_values.hash(idx) != last {
+            m_buckets.insert(last, (begin_idx, idx));
+            begin_idx = idx;
+        }
+    }
+    m_buckets
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixed_table(hashes: &[u64]) -> LSHTable<u64, true> {
+        let mut table = LSHTable::<u64>::new(hashes.len(), 1);
+        hashes.iter().enumerate().for_each(|(id, &hash)| table.put(id, hash));
+        table.fix_table()
+    }
+
+    #[test]
+    fn fix_table_buckets_every_entry() {
+        // a unique last hash
+        let table = fixed_table(&[3, 1, 1, 2, 2, 2, 5]);
+        assert_eq!(
+            table.m_buckets,
+            HashMap::from([(1, (0, 2)), (2, (2, 5)), (3, (5, 6)), (5, (6, 7))])
+        );
+        assert_eq!(table.distinct_hashes(), 4);
+        assert_eq!(Vec::from_iter(table.collision_iter(5)), vec![6]);
+
+        // a shared last hash
+        let table = fixed_table(&[2, 1, 2, 1]);
+        assert_eq!(table.m_buckets, HashMap::from([(1, (0, 2)), (2, (2, 4))]));
+        assert_eq!(table.distinct_hashes(), 2);
+        assert_eq!(Vec::from_iter(table.collision_iter(2)).len(), 2);
     }
 }
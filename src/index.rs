@@ -0,0 +1,406 @@
+use crate::config::IndexConfig;
+use crate::lsh::TensoredMultiHash;
+use crate::lsh::TrajectoryLsh;
+use crate::metric::VerificationMetric;
+use crate::point::Distance;
+use crate::point::QueryDistance;
+use crate::result::QueryGroup;
+use crate::result::QueryResult;
+use crate::trajectory::Trajectory;
+use crate::util::MasterStats;
+use anyhow::Result;
+use clap::Args;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Instant;
+
+/// The interface shared by the trajectory similarity indexes, which the
+/// binaries and benchmarks are written against. Trajectories are identified
+/// by their position in the dataset the index was built over, followed by
+/// the inserted ones in the order of their insertion. Results are
+/// `(query, candidate)` pairs.
+pub trait SimilarityIndex: Sized {
+    /// The index specific part of the `IndexConfig`
+    type Params: Args;
+    type Lsh: TrajectoryLsh;
+    type Stats: Serialize;
+    type Verification: VerificationResult<Stats = Self::Stats>;
+
+    /// Builds the index over `dataset`, hashing with `hasher` if given
+    /// instead of one generated from the seed in `config`
+    fn build(
+        config: &IndexConfig<Self::Params>,
+        hasher: Option<TensoredMultiHash<Self::Lsh>>,
+        dataset: &[Trajectory],
+    ) -> Self;
+
+    /// Like `build`, on the threads of the current rayon pool
+    fn par_build(
+        config: &IndexConfig<Self::Params>,
+        hasher: Option<TensoredMultiHash<Self::Lsh>>,
+        dataset: &[Trajectory],
+    ) -> Self {
+        Self::build(config, hasher, dataset)
+    }
+
+    /// Like `build`, sampling the memory usage once the first `samples[i]`
+    /// trajectories of `dataset` are indexed
+    fn build_with_memory_samples(
+        config: &IndexConfig<Self::Params>,
+        hasher: Option<TensoredMultiHash<Self::Lsh>>,
+        dataset: &[Trajectory],
+        stats: &mut MasterStats<Self::Stats, IndexConfig<Self::Params>>,
+        samples: &[usize],
+    ) -> Self;
+
+    /// Loads an index saved with `save` that was built over `dataset`
+    fn load(
+        config: &IndexConfig<Self::Params>,
+        hasher: Option<TensoredMultiHash<Self::Lsh>>,
+        dataset: &[Trajectory],
+        path: &Path,
+    ) -> Result<Self>;
+
+    fn save(&self, path: &Path) -> Result<()>;
+
+    /// Inserts `trajectory` and returns its id. The trajectory must be
+    /// appended to the dataset passed to the verifying queries as well.
+    fn insert(&mut self, trajectory: &Trajectory) -> usize;
+
+    /// Finds the candidates of every query, without verifying them
+    fn query(&self, queryset: &[Trajectory]) -> Vec<(usize, usize)>;
+
+    /// Like `query`, with the queries split across the threads of the
+    /// current rayon pool
+    fn par_query(&self, queryset: &[Trajectory]) -> Vec<(usize, usize)> {
+        self.query(queryset)
+    }
+
+    /// The fréchet distance threshold `config` enables the verifying queries
+    /// with, if any
+    fn verification_distance(config: &IndexConfig<Self::Params>) -> Option<Distance>;
+
+    /// The distance `config` verifies the candidates with
+    fn verification_metric(config: &IndexConfig<Self::Params>) -> VerificationMetric;
+
+    /// Finds the candidates of every query, of which those the index is not
+    /// confident about are verified with the decider of the verification
+    /// metric
+    fn query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Self::Verification;
+
+    /// Like `query_with_verification`, with the queries split across the
+    /// threads of the current rayon pool
+    fn par_query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Self::Verification {
+        self.query_with_verification(dataset, queryset, distance)
+    }
+
+    /// Finds every pair of similar trajectories of the index. Each unordered
+    /// pair is returned once as `(smaller id, larger id)`.
+    fn self_join(&self) -> Vec<(usize, usize)>;
+
+    /// Like `self_join`, verifying the pairs like `query_with_verification`
+    fn self_join_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        distance: Distance,
+    ) -> Self::Verification;
+
+    /// Turns result pairs into records with what the index knows about them.
+    /// `verification` is the result the pairs were taken from, if they come
+    /// from a verifying query.
+    fn query_results(
+        &self,
+        queryset: &[Trajectory],
+        pairs: &[(usize, usize)],
+        verification: Option<&Self::Verification>,
+    ) -> Vec<QueryResult>;
+
+    /// Queries one query at a time and yields its results as soon as it
+    /// finishes, ordered by query, so that the results of large querysets
+    /// never have to be held at once. The candidates are verified like
+    /// `query_with_verification` if `distance` is given. Indexes that
+    /// estimate their thresholds from the candidates override this to
+    /// estimate them over the whole queryset, as the batch queries do.
+    fn query_groups<'a>(
+        &'a self,
+        dataset: &'a [Trajectory],
+        queryset: &'a [Trajectory],
+        distance: Option<QueryDistance<'a>>,
+    ) -> impl Iterator<Item = QueryGroup<usize, Self::Verification>> + 'a {
+        (0..queryset.len()).map(move |query| {
+            query_group(self, queryset, query, |single| {
+                distance.map(|distance| {
+                    let distance = QueryDistance::Global(distance.of(query));
+                    self.query_with_verification(dataset, single, distance)
+                })
+            })
+        })
+    }
+
+    fn stats(&self) -> Self::Stats;
+}
+
+/// Runs query `query` of `queryset` on its own as a group of `query_groups`.
+/// `verify` verifies the candidates of the single query, or returns `None`
+/// to only collect them.
+pub(crate) fn query_group<I>(
+    index: &I,
+    queryset: &[Trajectory],
+    query: usize,
+    verify: impl FnOnce(&[Trajectory]) -> Option<I::Verification>,
+) -> QueryGroup<usize, I::Verification>
+where
+    I: SimilarityIndex,
+{
+    let start = Instant::now();
+    let single = &queryset[query..=query];
+    let verification = verify(single);
+    let pairs = match &verification {
+        Some(verification) => verification.pairs().to_vec(),
+        None => index.query(single),
+    };
+    let results = index
+        .query_results(single, &pairs, verification.as_ref())
+        .into_iter()
+        .map(|result| QueryResult { query, ..result })
+        .collect();
+    QueryGroup {
+        query,
+        results,
+        time: start.elapsed(),
+        verification,
+    }
+}
+
+/// The pairs accepted by a verifying query together with its counters
+pub trait VerificationResult {
+    type Stats;
+
+    fn pairs(&self) -> &[(usize, usize)];
+
+    /// Adds the verification counters to the stats of the index
+    fn record_stats(&self, stats: &mut Self::Stats);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_trajectories;
+    use crate::config::FreshConfig;
+    use crate::config::MartConfig;
+    use crate::dyft::DyftIndex;
+    use crate::fresh::Fresh;
+    use crate::lsh::Linear64;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn inserted_trajectories_are_found<I>(config: &IndexConfig<I::Params>)
+    where
+        I: SimilarityIndex,
+    {
+        let mut rng = StdRng::seed_from_u64(5);
+        let dataset = random_trajectories(250, 8, &mut rng);
+        let mut index = I::build(config, None, &dataset[..200]);
+        for (i, trajectory) in dataset[200..].iter().enumerate() {
+            assert_eq!(index.insert(trajectory), 200 + i);
+        }
+
+        let queryset = &dataset[200..];
+        let expected = Vec::from_iter((0..queryset.len()).map(|i| (i, 200 + i)));
+        let results = index.query(queryset);
+        assert!(expected.iter().all(|pair| results.contains(pair)));
+
+        let verification = index.query_with_verification(&dataset, queryset, 0.5.into());
+        assert!(expected.iter().all(|pair| verification.pairs().contains(pair)));
+        let records = index.query_results(queryset, verification.pairs(), Some(&verification));
+        assert_eq!(records.len(), verification.pairs().len());
+    }
+
+    fn budget_limits_verified_candidates<I>(config: &IndexConfig<I::Params>)
+    where
+        I: SimilarityIndex,
+    {
+        let mut rng = StdRng::seed_from_u64(9);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let index = I::build(config, None, &dataset);
+        let queryset = &dataset[..40];
+        let verification = index.query_with_verification(&dataset, queryset, 5.0.into());
+        let pairs = verification.pairs();
+        assert!(!pairs.is_empty());
+        assert!((0..queryset.len()).all(|i| pairs.iter().filter(|(q, _)| *q == i).count() <= 1));
+    }
+
+    #[test]
+    fn budget_limits_candidates_per_query() {
+        budget_limits_verified_candidates::<DyftIndex<Linear64>>(
+            &IndexConfig::<MartConfig>::default()
+                .resolution(1.0)
+                .with_splitthreshold(4)
+                .with_radius(8)
+                .with_errors(8)
+                .with_max_candidates(1),
+        );
+        budget_limits_verified_candidates::<Fresh<Linear64, true>>(
+            &IndexConfig::<FreshConfig>::default()
+                .resolution(1.0)
+                .max_candidates(1),
+        );
+    }
+
+    fn bbox_filter_only_prunes_mismatches<I>(
+        config: &IndexConfig<I::Params>,
+        filtered_config: &IndexConfig<I::Params>,
+    ) -> I::Verification
+    where
+        I: SimilarityIndex,
+    {
+        let mut rng = StdRng::seed_from_u64(11);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let queryset = random_trajectories(30, 8, &mut rng);
+        let verification = I::build(config, None, &dataset)
+            .query_with_verification(&dataset, &queryset, 0.5.into());
+        let filtered = I::build(filtered_config, None, &dataset)
+            .query_with_verification(&dataset, &queryset, 0.5.into());
+        assert!(filtered.pairs().iter().all(|pair| verification.pairs().contains(pair)));
+        for &(query, candidate) in verification.pairs() {
+            if !filtered.pairs().contains(&(query, candidate)) {
+                assert!(!queryset[query].frechet_decider(&dataset[candidate], 0.5));
+            }
+        }
+        filtered
+    }
+
+    #[test]
+    fn bbox_filter_prunes_candidates() {
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(8)
+            .with_errors(8);
+        let verification = bbox_filter_only_prunes_mismatches::<DyftIndex<Linear64>>(
+            &config,
+            &config.clone().with_bbox_filter(),
+        );
+        assert!(verification.pruned_pair_count() > 0);
+
+        let config = IndexConfig::<FreshConfig>::default().resolution(1.0);
+        let verification = bbox_filter_only_prunes_mismatches::<Fresh<Linear64, true>>(
+            &config,
+            &config.clone().bbox_filter(),
+        );
+        assert!(verification.pruned_pairs > 0);
+    }
+
+    fn metrics_nest_the_verified_pairs<I>(
+        config: &IndexConfig<I::Params>,
+        with_metric: impl Fn(IndexConfig<I::Params>, VerificationMetric) -> IndexConfig<I::Params>,
+    ) where
+        I: SimilarityIndex,
+        I::Params: Clone,
+    {
+        let mut rng = StdRng::seed_from_u64(19);
+        let dataset = random_trajectories(300, 8, &mut rng);
+        let queryset = random_trajectories(30, 8, &mut rng);
+        let pairs = |metric| {
+            let config = with_metric(config.clone(), metric);
+            assert_eq!(I::verification_metric(&config), metric);
+            let verification = I::build(&config, None, &dataset)
+                .query_with_verification(&dataset, &queryset, 4.0.into());
+            verification.pairs().to_vec()
+        };
+        let is_subset =
+            |a: &[(usize, usize)], b: &[(usize, usize)]| a.iter().all(|p| b.contains(p));
+        let dtw = pairs(VerificationMetric::Dtw);
+        let discrete = pairs(VerificationMetric::DiscreteFrechet);
+        assert!(dtw.len() < discrete.len());
+        assert!(is_subset(&dtw, &discrete));
+        assert!(is_subset(&discrete, &pairs(VerificationMetric::ContinuousFrechet)));
+        assert!(is_subset(&discrete, &pairs(VerificationMetric::Hausdorff)));
+    }
+
+    #[test]
+    fn verification_metric_picks_the_decider() {
+        metrics_nest_the_verified_pairs::<DyftIndex<Linear64>>(
+            &IndexConfig::<MartConfig>::default()
+                .resolution(1.0)
+                .with_splitthreshold(4)
+                .with_radius(8)
+                .with_errors(8),
+            IndexConfig::with_metric,
+        );
+        metrics_nest_the_verified_pairs::<Fresh<Linear64, true>>(
+            &IndexConfig::<FreshConfig>::default().resolution(1.0),
+            IndexConfig::metric,
+        );
+    }
+
+    fn query_groups_match_batch_queries<I>(config: &IndexConfig<I::Params>)
+    where
+        I: SimilarityIndex,
+    {
+        let mut rng = StdRng::seed_from_u64(13);
+        let dataset = random_trajectories(200, 8, &mut rng);
+        let queryset = &dataset[..20];
+        let index = I::build(config, None, &dataset);
+
+        let groups = Vec::from_iter(index.query_groups(&dataset, queryset, None));
+        assert_eq!(Vec::from_iter(groups.iter().map(|group| group.query)), Vec::from_iter(0..20));
+        let mut pairs = Vec::from_iter(
+            groups
+                .iter()
+                .flat_map(|group| group.results.iter().map(QueryResult::pair)),
+        );
+        pairs.sort();
+        let mut expected = index.query(queryset);
+        expected.sort();
+        assert!(!expected.is_empty());
+        assert_eq!(pairs, expected);
+
+        for group in index.query_groups(&dataset, queryset, Some(1.0.into())) {
+            let verification = group.verification.unwrap();
+            assert_eq!(group.results.len(), verification.pairs().len());
+            for result in &group.results {
+                assert_eq!(result.query, group.query);
+                assert!(expected.contains(&result.pair()));
+            }
+        }
+    }
+
+    #[test]
+    fn query_groups_stream_the_results_of_each_query() {
+        query_groups_match_batch_queries::<DyftIndex<Linear64>>(
+            &IndexConfig::<MartConfig>::default()
+                .resolution(1.0)
+                .with_splitthreshold(4)
+                .with_radius(2)
+                .with_errors(2),
+        );
+        query_groups_match_batch_queries::<Fresh<Linear64, true>>(
+            &IndexConfig::<FreshConfig>::default().resolution(1.0),
+        );
+    }
+
+    #[test]
+    fn indexes_insert_and_query_through_the_trait() {
+        inserted_trajectories_are_found::<DyftIndex<Linear64>>(
+            &IndexConfig::<MartConfig>::default()
+                .resolution(1.0)
+                .with_splitthreshold(4)
+                .with_radius(2)
+                .with_errors(2),
+        );
+        inserted_trajectories_are_found::<Fresh<Linear64, true>>(
+            &IndexConfig::<FreshConfig>::default().resolution(1.0),
+        );
+    }
+}
@@ -7,38 +7,66 @@ use crate::config::master_query_dir;
 use crate::config::master_result_dir;
 use crate::dyft::VCodeArray;
 use crate::dyft::VCodeTools;
+use crate::lsh::format_fingerprint;
+use crate::lsh::parse_fingerprint;
+use crate::lsh::TensoredMultiHash;
+use crate::lsh::TrajectoryLsh;
+use crate::lsh::HASHER_FINGERPRINT_KEY;
 use crate::point::PointMatrix;
+use crate::result::QueryGroup;
+use crate::result::QueryResult;
+use crate::subtrajectory::SubtrajectoryMatch;
 use crate::trajectory::Trajectory;
 use crate::trajectory::TrajectoryDataset;
 use anyhow::anyhow;
 use anyhow::Result;
+use arrow::array::Array;
 use arrow::array::ArrayRef;
+use arrow::array::BooleanArray;
 use arrow::array::Float64Array;
 use arrow::array::Int64Array;
 use arrow::array::PrimitiveArray;
 use arrow::array::RecordBatch;
+use arrow::array::UInt32Array;
 use arrow::array::UInt64Array;
 use arrow::buffer::ScalarBuffer;
 use arrow::datatypes::ArrowPrimitiveType;
 use indexmap::IndexMap;
+use num_traits::FromPrimitive;
 use num_traits::ToPrimitive;
 use parquet::arrow::arrow_reader::ArrowReaderOptions;
 use parquet::arrow::arrow_reader::ParquetRecordBatchReader;
 use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
 use parquet::arrow::ArrowWriter;
 use parquet::file::properties::WriterProperties;
+use parquet::file::reader::FileReader;
+use parquet::file::reader::SerializedFileReader;
 use parquet::format::FileMetaData;
+use parquet::format::KeyValue;
+use serde::Serialize;
 use std::fs::File;
+use std::io::BufWriter;
 use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 const ID_COL: &str = "id";
 const LAT_COL: &str = "lat";
 const LON_COL: &str = "lon";
+const RADIUS_COL: &str = "radius";
 
 const RESULT_ID_COL: &str = "query";
 const RESULT_CANDIDATE_COL: &str = "candidate";
+const RESULT_HAMMING_COL: &str = "hamming_distance";
+const RESULT_COLLISIONS_COL: &str = "collisions";
+const RESULT_VERIFIED_COL: &str = "verified";
+const RESULT_FRECHET_COL: &str = "frechet_distance";
+const RESULT_START_COL: &str = "start";
+const RESULT_END_COL: &str = "end";
+
+/// Rows per record batch of the streaming result writers
+const RESULT_BATCH_ROWS: usize = 1 << 16;
 
 pub fn trajectory_dataset<'a>(path: impl AsRef<Path>) -> Result<TrajectoryDataset<'a>> {
     trajectoryset_parquet(map_master_path(path, master_data_dir()))
@@ -57,8 +85,12 @@ fn parquet_reader(path: impl AsRef<Path>) -> Result<ParquetRecordBatchReader> {
     builder.build().map_err(|e| e.into())
 }
 
+/// Reads the points of a trajectory set, grouped by their id. An optional
+/// radius column holds the verification threshold of each trajectory,
+/// which is taken from the first point of the trajectory.
 fn trajectoryset_parquet<'a>(path: impl AsRef<Path>) -> Result<TrajectoryDataset<'a>> {
     let mut data: IndexMap<TrajectoryID<'a>, Trajectory> = IndexMap::new();
+    let mut radii: IndexMap<TrajectoryID<'a>, f64> = IndexMap::new();
     let mut reader = parquet_reader(path)?;
     while let Some(Ok(records)) = reader.next() {
         let id_col = records
@@ -83,6 +115,19 @@ fn trajectoryset_parquet<'a>(path: impl AsRef<Path>) -> Result<TrajectoryDataset
             .downcast_ref::<Float64Array>()
             .ok_or(anyhow!("lon column is not a f64 array"))?;
 
+        if let Some(radius_col) = records.column_by_name(RADIUS_COL) {
+            let radius_array = radius_col
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .ok_or(anyhow!("radius column is not a f64 array"))?;
+            if radius_array.null_count() > 0 {
+                return Err(anyhow!("radius column contains nulls"));
+            }
+            for (&id, &radius) in id_array.values().iter().zip(radius_array.values()) {
+                radii.entry(id.into()).or_insert(radius);
+            }
+        }
+
         for (&id, point) in id_array.values().into_iter().into_iter().zip(
             x_array
                 .values()
@@ -95,7 +140,18 @@ fn trajectoryset_parquet<'a>(path: impl AsRef<Path>) -> Result<TrajectoryDataset
                 .append_point(point);
         }
     }
-    Ok(TrajectoryDataset::new(data))
+    let dataset = match radii.is_empty() {
+        true => TrajectoryDataset::new(data),
+        false => {
+            let radii = data
+                .keys()
+                .map(|id| radii.get(id).copied())
+                .collect::<Option<Vec<_>>>()
+                .ok_or(anyhow!("radius column is missing in some record batches"))?;
+            TrajectoryDataset::new(data).with_radii(radii)
+        }
+    };
+    Ok(dataset)
 }
 
 pub fn write_query_results<'a, P, I>(path: P, results: I) -> Result<FileMetaData>
@@ -119,9 +175,138 @@ where
     )
 }
 
+/// Writes result records like `write_query_results`, with a column for each
+/// field of the records. Fields the index did not fill in are null.
+pub fn write_query_result_records<'a, P, I>(path: P, results: I) -> Result<FileMetaData>
+where
+    I: IntoIterator<Item = QueryResult<TrajectoryID<'a>>>,
+    P: AsRef<Path>,
+{
+    let results: Vec<_> = results.into_iter().collect();
+    let batch = query_result_batch(&results)?;
+    let file = File::create(map_master_path(path, master_result_dir()))?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+    writer.write(&batch)?;
+    Ok(writer.close()?)
+}
+
+/// Writes the result groups of `SimilarityIndex::query_groups` as they
+/// arrive, with the columns of `write_query_result_records`. The results are
+/// buffered into record batches of about `RESULT_BATCH_ROWS` rows.
+pub fn write_query_groups_parquet<'a, P, I, V>(path: P, groups: I) -> Result<FileMetaData>
+where
+    I: IntoIterator<Item = QueryGroup<TrajectoryID<'a>, V>>,
+    P: AsRef<Path>,
+{
+    let file = File::create(map_master_path(path, master_result_dir()))?;
+    let mut writer = ArrowWriter::try_new(file, query_result_batch(&[])?.schema(), None)?;
+    let mut buffer = Vec::with_capacity(RESULT_BATCH_ROWS);
+    for group in groups {
+        buffer.extend(group.results);
+        if buffer.len() >= RESULT_BATCH_ROWS {
+            writer.write(&query_result_batch(&buffer)?)?;
+            buffer.clear();
+        }
+    }
+    if !buffer.is_empty() {
+        writer.write(&query_result_batch(&buffer)?)?;
+    }
+    Ok(writer.close()?)
+}
+
+/// Writes the result groups of `SimilarityIndex::query_groups` as JSON
+/// Lines, one object per query with its results, as they arrive
+pub fn write_query_groups_jsonl<'a, P, I, V>(path: P, groups: I) -> Result<()>
+where
+    I: IntoIterator<Item = QueryGroup<TrajectoryID<'a>, V>>,
+    P: AsRef<Path>,
+{
+    let file = File::create(map_master_path(path, master_result_dir()))?;
+    let mut writer = BufWriter::new(file);
+    for group in groups {
+        let line = QueryGroupLine {
+            query: group.query.value(),
+            time: group.time,
+            results: Vec::from_iter(group.results.iter().map(|result| QueryResultLine {
+                candidate: result.candidate.value(),
+                hamming_distance: result.hamming_distance,
+                collisions: result.collisions,
+                verified: result.verified,
+                frechet_distance: result.frechet_distance,
+            })),
+        };
+        serde_json::to_writer(&mut writer, &line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(writer.flush()?)
+}
+
+#[derive(Serialize)]
+struct QueryGroupLine {
+    query: i64,
+    time: Duration,
+    results: Vec<QueryResultLine>,
+}
+
+#[derive(Serialize)]
+struct QueryResultLine {
+    candidate: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hamming_distance: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collisions: Option<u32>,
+    verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frechet_distance: Option<f64>,
+}
+
+/// The columns of result records, with the same schema for every batch
+fn query_result_batch(results: &[QueryResult<TrajectoryID<'_>>]) -> Result<RecordBatch> {
+    let queries = Int64Array::from_iter_values(results.iter().map(|result| result.query.value()));
+    let candidates =
+        Int64Array::from_iter_values(results.iter().map(|result| result.candidate.value()));
+    let hamming = UInt32Array::from_iter(results.iter().map(|result| result.hamming_distance));
+    let collisions = UInt32Array::from_iter(results.iter().map(|result| result.collisions));
+    let verified = BooleanArray::from_iter(results.iter().map(|result| Some(result.verified)));
+    let frechet = Float64Array::from_iter(results.iter().map(|result| result.frechet_distance));
+    Ok(RecordBatch::try_from_iter_with_nullable(vec![
+        (RESULT_ID_COL, Arc::new(queries) as ArrayRef, false),
+        (RESULT_CANDIDATE_COL, Arc::new(candidates) as ArrayRef, false),
+        (RESULT_HAMMING_COL, Arc::new(hamming) as ArrayRef, true),
+        (RESULT_COLLISIONS_COL, Arc::new(collisions) as ArrayRef, true),
+        (RESULT_VERIFIED_COL, Arc::new(verified) as ArrayRef, false),
+        (RESULT_FRECHET_COL, Arc::new(frechet) as ArrayRef, true),
+    ])?)
+}
+
+/// Writes subtrajectory matches like `write_query_results`, with the range of
+/// points of the candidate trajectory that matched
+pub fn write_subtrajectory_matches<'a, P, I>(path: P, matches: I) -> Result<FileMetaData>
+where
+    I: IntoIterator<Item = SubtrajectoryMatch<TrajectoryID<'a>>>,
+    P: AsRef<Path>,
+{
+    let matches: Vec<_> = matches.into_iter().collect();
+    let queries = Int64Array::from_iter_values(matches.iter().map(|m| m.query.value()));
+    let candidates = Int64Array::from_iter_values(matches.iter().map(|m| m.trajectory.value()));
+    let starts = UInt64Array::from_iter_values(matches.iter().map(|m| m.start as u64));
+    let ends = UInt64Array::from_iter_values(matches.iter().map(|m| m.end as u64));
+    let file = File::create(map_master_path(path, master_result_dir()))?;
+    write_record_batch(
+        vec![
+            (RESULT_ID_COL, Arc::new(queries) as ArrayRef),
+            (RESULT_CANDIDATE_COL, Arc::new(candidates) as ArrayRef),
+            (RESULT_START_COL, Arc::new(starts) as ArrayRef),
+            (RESULT_END_COL, Arc::new(ends) as ArrayRef),
+        ],
+        file,
+    )
+}
+
 pub fn write_vcodes_parquet<const B: usize, T>(
     path: impl AsRef<Path>,
     vcodes: VCodeArray<T>,
+    fingerprint: Option<u64>,
 ) -> Result<FileMetaData>
 where
     T: VCodeTools,
@@ -140,13 +325,14 @@ where
         )
     });
     let file = File::create(map_master_path(path, master_result_dir()))?;
-    write_record_batch(column_iter, file)
+    write_record_batch_with_metadata(column_iter, file, fingerprint_metadata(fingerprint))
 }
 
 pub fn write_hashes_parquet<T>(
     path: impl AsRef<Path>,
     hashes: impl Iterator<Item = impl Iterator<Item = T::Native>>,
     ids: impl Iterator<Item = i64>,
+    fingerprint: Option<u64>,
 ) -> Result<FileMetaData>
 where
     T: ArrowPrimitiveType,
@@ -158,18 +344,234 @@ where
             Arc::new(PrimitiveArray::<T>::from_iter_values(hash.into_iter())) as ArrayRef,
         )
     });
-    write_record_batch(cols, file)
+    write_record_batch_with_metadata(cols, file, fingerprint_metadata(fingerprint))
+}
+
+/// Reads a vcode file written by `write_vcodes_parquet`, rejecting it unless
+/// its vcodes were produced by `hasher`
+pub fn read_vcodes_parquet<H>(
+    path: impl AsRef<Path>,
+    hasher: &TensoredMultiHash<H>,
+) -> Result<VCodeArray<H::Hash>>
+where
+    H: TrajectoryLsh,
+{
+    let path = map_master_path(path, master_result_dir());
+    check_hasher_fingerprint(&path, hasher)?;
+    let mut columns: Vec<Vec<u64>> = Vec::new();
+    for records in parquet_reader(&path)? {
+        let records = records?;
+        columns.resize_with(records.num_columns(), Vec::new);
+        for (column, array) in columns.iter_mut().zip(records.columns()) {
+            let array = array
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .ok_or(anyhow!("vcode column is not a u64 array"))?;
+            column.extend(array.values().iter());
+        }
+    }
+    let num_codes = columns.first().map_or(0, Vec::len);
+    let hashes = (0..num_codes)
+        .flat_map(|j| columns.iter().map(move |column| column[j]))
+        .map(|hash| H::Hash::from_u64(hash).ok_or(anyhow!("hash {hash} does not fit a vcode")))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(VCodeArray::from_hashes(hashes.into_iter(), columns.len()))
+}
+
+/// Reads a hash file written by `write_hashes_parquet` as `(id, hashes)`
+/// pairs, rejecting it unless its hashes were produced by `hasher`
+pub fn read_hashes_parquet<T, H>(
+    path: impl AsRef<Path>,
+    hasher: &TensoredMultiHash<H>,
+) -> Result<Vec<(i64, Vec<T::Native>)>>
+where
+    T: ArrowPrimitiveType,
+    H: TrajectoryLsh,
+{
+    let path = map_master_path(path, master_result_dir());
+    check_hasher_fingerprint(&path, hasher)?;
+    let mut hashes: IndexMap<i64, Vec<T::Native>> = IndexMap::new();
+    for records in parquet_reader(&path)? {
+        let records = records?;
+        for (field, array) in records.schema().fields().iter().zip(records.columns()) {
+            let id: i64 = field.name().parse()?;
+            let array = array
+                .as_any()
+                .downcast_ref::<PrimitiveArray<T>>()
+                .ok_or(anyhow!("hash column {id} has an unexpected type"))?;
+            hashes.entry(id).or_default().extend(array.values().iter());
+        }
+    }
+    Ok(hashes.into_iter().collect())
+}
+
+/// Reads the fingerprint of the hasher that produced a vcode or hash file, if any
+pub fn read_hasher_fingerprint(path: impl AsRef<Path>) -> Result<Option<u64>> {
+    parquet_fingerprint(&map_master_path(path, master_result_dir()))
+}
+
+fn parquet_fingerprint(path: &Path) -> Result<Option<u64>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|kvs| kvs.iter().find(|kv| kv.key == HASHER_FINGERPRINT_KEY))
+        .and_then(|kv| kv.value.as_deref())
+        .map(parse_fingerprint)
+        .transpose()
+}
+
+fn check_hasher_fingerprint<H>(path: &Path, hasher: &TensoredMultiHash<H>) -> Result<()>
+where
+    H: TrajectoryLsh,
+{
+    let fingerprint = parquet_fingerprint(path)?
+        .ok_or(anyhow!("{} has no hasher fingerprint", path.display()))?;
+    hasher.check_fingerprint(fingerprint)
+}
+
+fn fingerprint_metadata(fingerprint: Option<u64>) -> Option<Vec<KeyValue>> {
+    fingerprint.map(|fingerprint| {
+        vec![KeyValue::new(
+            HASHER_FINGERPRINT_KEY.to_string(),
+            format_fingerprint(fingerprint),
+        )]
+    })
 }
 
 pub fn write_record_batch<I, F, W>(iter: I, writer: W) -> Result<FileMetaData>
+where
+    I: IntoIterator<Item = (F, ArrayRef)>,
+    F: AsRef<str>,
+    W: Write + Send,
+{
+    write_record_batch_with_metadata(iter, writer, None)
+}
+
+pub fn write_record_batch_with_metadata<I, F, W>(
+    iter: I,
+    writer: W,
+    metadata: Option<Vec<KeyValue>>,
+) -> Result<FileMetaData>
 where
     I: IntoIterator<Item = (F, ArrayRef)>,
     F: AsRef<str>,
     W: Write + Send,
 {
     let batch = RecordBatch::try_from_iter(iter)?;
-    let props = WriterProperties::default();
+    let props = WriterProperties::builder()
+        .set_key_value_metadata(metadata)
+        .build();
     let mut writer = ArrowWriter::try_new(writer, batch.schema(), Some(props))?;
     writer.write(&batch)?;
     Ok(writer.close()?)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lsh::Linear64;
+    use crate::lsh::MultiTrajectoryLsh;
+    use crate::result::MapIds;
+    use crate::test_util::random_trajectories;
+    use arrow::datatypes::UInt64Type;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn write_trajectories(path: &Path, radius: Option<Vec<f64>>) -> Result<()> {
+        let mut columns = vec![
+            (ID_COL, Arc::new(Int64Array::from(vec![1, 1, 2, 2, 3])) as ArrayRef),
+            (LAT_COL, Arc::new(Float64Array::from(vec![0.0, 1.0, 2.0, 3.0, 4.0])) as ArrayRef),
+            (LON_COL, Arc::new(Float64Array::from(vec![0.0, 1.0, 2.0, 3.0, 4.0])) as ArrayRef),
+        ];
+        if let Some(radius) = radius {
+            columns.push((RADIUS_COL, Arc::new(Float64Array::from(radius)) as ArrayRef));
+        }
+        write_record_batch(columns, File::create(path)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn radius_column_is_read_per_trajectory() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("radius-{}.parquet", std::process::id()));
+        write_trajectories(&path, Some(vec![0.5, 0.5, 1.0, 1.0, 2.0]))?;
+        let dataset = trajectoryset_parquet(&path)?;
+        assert_eq!(dataset.len(), 3);
+        assert_eq!(dataset.radii(), Some([0.5, 1.0, 2.0].as_slice()));
+        assert_eq!(dataset.take(Some(2)).radii(), Some([0.5, 1.0].as_slice()));
+
+        write_trajectories(&path, None)?;
+        assert_eq!(trajectoryset_parquet(&path)?.radii(), None);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn hash_files_are_read_back_by_their_hasher_only() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let dataset = random_trajectories(5, 4, &mut rng);
+        let hasher = TensoredMultiHash::<Linear64>::init(4, 2, 1.0, 4, &mut rng);
+        let other = TensoredMultiHash::<Linear64>::init(4, 2, 1.0, 4, &mut rng);
+        let vcodes = VCodeArray::from_hashes(
+            dataset.iter().flat_map(|t| hasher.multi_hash(t)),
+            hasher.hash_length(),
+        );
+        let dir = std::env::temp_dir();
+
+        let path = dir.join(format!("vcodes-{}.parquet", std::process::id()));
+        write_vcodes_parquet::<64, u64>(&path, vcodes, Some(hasher.fingerprint()))?;
+        assert_eq!(read_hasher_fingerprint(&path)?, Some(hasher.fingerprint()));
+        let read = read_vcodes_parquet(&path, &hasher)?;
+        assert_eq!(read.size(), dataset.len());
+        assert!(dataset
+            .iter()
+            .enumerate()
+            .all(|(i, t)| read.access(i) == hasher.multi_hash(t).collect::<Vec<_>>()));
+        assert!(read_vcodes_parquet(&path, &other).is_err());
+
+        write_hashes_parquet::<UInt64Type>(
+            &path,
+            dataset.iter().map(|t| hasher.multi_hash(t)),
+            0..dataset.len() as i64,
+            Some(hasher.fingerprint()),
+        )?;
+        let read = read_hashes_parquet::<UInt64Type, _>(&path, &hasher)?;
+        assert_eq!(read.len(), dataset.len());
+        assert_eq!(read[2], (2, hasher.multi_hash(&dataset[2]).collect()));
+        assert!(read_hashes_parquet::<UInt64Type, _>(&path, &other).is_err());
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn query_groups_are_written_as_they_arrive() -> Result<()> {
+        let groups = || {
+            (0..3).map(|query| QueryGroup::<TrajectoryID, ()> {
+                query: TrajectoryID::new(query),
+                results: Vec::from_iter((0..query).map(|candidate| QueryResult {
+                    collisions: Some(2),
+                    ..QueryResult::new(0, 0).map_ids(query.into(), candidate.into())
+                })),
+                time: Duration::from_millis(1),
+                verification: None,
+            })
+        };
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("groups-{}.parquet", std::process::id()));
+        write_query_groups_parquet(&path, groups())?;
+        let rows: usize = parquet_reader(&path)?.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+        std::fs::remove_file(path)?;
+
+        let path = dir.join(format!("groups-{}.jsonl", std::process::id()));
+        write_query_groups_jsonl(&path, groups())?;
+        let lines = std::fs::read_to_string(&path)?;
+        let lines = Vec::from_iter(lines.lines());
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with(r#"{"query":2,"#));
+        assert!(lines[2].contains(r#""results":[{"candidate":0,"collisions":2,"verified":false}"#));
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
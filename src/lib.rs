@@ -22,10 +22,18 @@ pub mod id;
 pub mod io;
 pub mod lsh;
 pub mod point;
+pub mod result;
 pub mod trajectory;
 pub mod util;
 pub mod fresh;
 pub mod frechet;
+pub mod index;
+pub mod subtrajectory;
+pub mod prefilter;
+pub mod metric;
 pub mod config;
 pub mod params;
-pub mod benchmarks;
\ No newline at end of file
+pub mod benchmarks;
+pub mod cli;
+#[cfg(test)]
+mod test_util;
\ No newline at end of file
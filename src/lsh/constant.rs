@@ -1,4 +1,6 @@
 use super::linear::LinearFactorLsh;
+use super::linear::LinearHashState;
+use super::streaming::StreamingTrajectoryLsh;
 use super::traits::TrajectoryLsh;
 use super::util::random_pertrubation;
 use super::Resolution;
@@ -10,8 +12,10 @@ use crate::trajectory::Trajectory;
 use get_size::GetSize;
 use rand::Fill;
 use rand::Rng;
+use serde::Deserialize;
+use serde::Serialize;
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct ConstantFactorLsh<T> {
     inner_lsh: LinearFactorLsh<T>,
     data_perturbations: Vec<Point>,
@@ -24,6 +28,9 @@ fn perturbe<'a>(n: usize, resolution: f64, rng: &'a mut impl Rng) -> impl Iterat
 }
 
 impl<T> ConstantFactorLsh<T> {
+    /// Perturbs and snaps every point to the grid. Trajectories longer than
+    /// the `max_len` the hash was initialized with wrap around the
+    /// perturbations.
     fn perturb_trajectory(
         trajectory: &Trajectory,
         coefficients: &[Point],
@@ -31,10 +38,8 @@ impl<T> ConstantFactorLsh<T> {
     ) -> Trajectory {
         let mut last_point = [Resolution::MAX, Resolution::MAX];
         Trajectory::from_iter(trajectory.iter().enumerate().filter_map(|(i, point)| {
-            let [x, y] = [
-                ((point.x + coefficients[i][0]) / delta).round(),
-                ((point.y + coefficients[i][1]) / delta).round(),
-            ];
+            let [dx, dy] = coefficients[i % coefficients.len()];
+            let [x, y] = [((point.x + dx) / delta).round(), ((point.y + dy) / delta).round()];
             if x != last_point[0] || y != last_point[1] {
                 last_point = [x, y];
                 Some(PointMatrix::new(x, y))
@@ -79,4 +84,51 @@ where
             self.delta,
         ))
     }
+
+    fn resolution(&self) -> Distance {
+        self.delta
+    }
+}
+
+/// The state of a constant factor hash while it is folded over the points
+/// of a trajectory
+#[derive(Debug, Clone)]
+pub struct ConstantHashState<T> {
+    point_idx: usize,
+    last: Point, // last perturbed grid cell
+    inner: LinearHashState<T>,
+}
+
+impl<T> StreamingTrajectoryLsh for ConstantFactorLsh<T>
+where
+    T: VCodeTools,
+    [T]: Fill,
+{
+    type State = ConstantHashState<T>;
+
+    fn init_state(&self) -> Self::State {
+        ConstantHashState {
+            point_idx: 0,
+            last: [Resolution::MAX, Resolution::MAX],
+            inner: self.inner_lsh.init_state(),
+        }
+    }
+
+    fn hash_point(&self, state: &mut Self::State, point: &PointMatrix) {
+        let [dx, dy] = self.data_perturbations[state.point_idx % self.data_perturbations.len()];
+        let [x, y] = [
+            ((point.x + dx) / self.delta).round(),
+            ((point.y + dy) / self.delta).round(),
+        ];
+        state.point_idx += 1;
+        if x != state.last[0] || y != state.last[1] {
+            state.last = [x, y];
+            self.inner_lsh
+                .hash_point(&mut state.inner, &PointMatrix::new(x, y));
+        }
+    }
+
+    fn state_hash(&self, state: &Self::State) -> Self::Hash {
+        self.inner_lsh.state_hash(&state.inner)
+    }
 }
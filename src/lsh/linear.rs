@@ -1,14 +1,19 @@
+use super::streaming::StreamingTrajectoryLsh;
 use super::traits::TrajectoryLsh;
 use super::util::random_coefficients;
 use super::util::random_shift_grid;
 use crate::dyft::VCodeTools;
 use crate::point::Distance;
 use crate::point::Point;
+use crate::point::PointMatrix;
 use crate::trajectory::Trajectory;
 use get_size::GetSize;
+use itertools::Itertools;
 use rand::Fill;
+use serde::Deserialize;
+use serde::Serialize;
 
-#[derive(GetSize)]
+#[derive(GetSize, Serialize, Deserialize)]
 pub struct LinearFactorLsh<T> {
     delta: Distance,
     shift: Point,
@@ -32,33 +37,109 @@ where
 
     // https://github.com/Cecca/FRESH/blob/d7740ed59b1566bf77f6a54ed3423e6a3d62e230/core/hash.h#L142C41-L142C47
     fn hash(&self, trajectory: &Trajectory) -> Self::Hash {
-        let delta = self.delta;
-        let [shift_x, shift_y] = self.shift;
+        let mut state = self.init_state();
         trajectory
             .iter()
-            .fold(
-                (T::zero(), 0, [Distance::MAX, Distance::MAX]),
-                |(acc, coeff_idx, [last_x, last_y]): (T, usize, Point), &point| {
-                    let [x, y] = [
-                        ((point.x + shift_x) / delta).round(),
-                        ((point.y + shift_y) / delta).round(),
-                    ];
-                    if x != last_x || y != last_y {
-                        let coeff_x =
-                            T::wrap_to_t(x).wrapping_mul(&self.rand_coefficients[coeff_idx]);
-                        let coeff_y =
-                            T::wrap_to_t(y).wrapping_mul(&self.rand_coefficients[coeff_idx + 1]);
-                        (
-                            acc.wrapping_add(&coeff_x.wrapping_add(&coeff_y)),
-                            coeff_idx + 1,
-                            [x, y],
-                        )
-                    } else {
-                        (acc, coeff_idx, [last_x, last_y])
+            .for_each(|point| self.hash_point(&mut state, point));
+        self.state_hash(&state)
+    }
+
+    fn resolution(&self) -> Distance {
+        self.delta
+    }
+
+    /// Moves one coordinate of one point to the neighbouring cell on the
+    /// side the point is closest to, scored by the distance of the point to
+    /// that side in units of the cell size
+    fn probe_hashes(&self, trajectory: &Trajectory, probes: usize) -> Vec<(Distance, T)> {
+        let positions = Vec::from_iter(trajectory.iter().map(|point| self.grid_position(point)));
+        let cells = Vec::from_iter(positions.iter().map(|position| position.map(Distance::round)));
+        positions
+            .iter()
+            .enumerate()
+            .flat_map(|(i, position)| {
+                (0..2).map(move |axis| {
+                    let offset = position[axis] - position[axis].round();
+                    (0.5 - offset.abs(), i, axis, offset.signum())
+                })
+            })
+            .sorted_by(|a, b| a.0.total_cmp(&b.0))
+            .take(probes)
+            .map(|(score, i, axis, direction)| {
+                let mut state = self.init_state();
+                cells.iter().enumerate().for_each(|(j, &cell)| {
+                    let mut cell = cell;
+                    if j == i {
+                        cell[axis] += direction;
                     }
-                },
-            )
-            .0
-            .shr(T::N_DIM / 2)
+                    self.push_cell(&mut state, cell);
+                });
+                (score, self.state_hash(&state))
+            })
+            .collect()
+    }
+}
+
+impl<T> LinearFactorLsh<T>
+where
+    T: VCodeTools,
+{
+    /// The position of `point` on the shifted grid, in units of the cell size
+    fn grid_position(&self, point: &PointMatrix) -> Point {
+        let [shift_x, shift_y] = self.shift;
+        [(point.x + shift_x) / self.delta, (point.y + shift_y) / self.delta]
+    }
+
+    fn cell(&self, point: &PointMatrix) -> Point {
+        self.grid_position(point).map(Distance::round)
+    }
+
+    /// Extends the hash by the grid cell `[x, y]`, skipping repeated cells.
+    /// Trajectories longer than the `max_len` the hash was initialized with,
+    /// e.g. streamed ones, wrap around the coefficients.
+    fn push_cell(&self, state: &mut LinearHashState<T>, [x, y]: Point) {
+        if x != state.last[0] || y != state.last[1] {
+            let n = self.rand_coefficients.len();
+            let coeff_x =
+                T::wrap_to_t(x).wrapping_mul(&self.rand_coefficients[state.coeff_idx % n]);
+            let coeff_y =
+                T::wrap_to_t(y).wrapping_mul(&self.rand_coefficients[(state.coeff_idx + 1) % n]);
+            state.acc = state.acc.wrapping_add(&coeff_x.wrapping_add(&coeff_y));
+            state.coeff_idx += 1;
+            state.last = [x, y];
+        }
     }
-}
\ No newline at end of file
+}
+
+/// The state of a linear factor hash while it is folded over the points of
+/// a trajectory
+#[derive(Debug, Clone)]
+pub struct LinearHashState<T> {
+    acc: T,
+    coeff_idx: usize,
+    last: Point, // last grid cell
+}
+
+impl<T> StreamingTrajectoryLsh for LinearFactorLsh<T>
+where
+    T: VCodeTools,
+    [T]: Fill,
+{
+    type State = LinearHashState<T>;
+
+    fn init_state(&self) -> Self::State {
+        LinearHashState {
+            acc: T::zero(),
+            coeff_idx: 0,
+            last: [Distance::MAX, Distance::MAX],
+        }
+    }
+
+    fn hash_point(&self, state: &mut Self::State, point: &PointMatrix) {
+        self.push_cell(state, self.cell(point));
+    }
+
+    fn state_hash(&self, state: &Self::State) -> Self::Hash {
+        state.acc.shr(T::N_DIM / 2)
+    }
+}
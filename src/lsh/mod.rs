@@ -2,6 +2,8 @@
 
 mod constant;
 mod linear;
+mod persist;
+mod streaming;
 mod tensored;
 mod util;
 mod traits;
@@ -11,6 +13,8 @@ pub use traits::MultiTrajectoryLsh;
 pub use linear::LinearFactorLsh;
 pub use constant::ConstantFactorLsh;
 pub use tensored::TensoredMultiHash;
+pub use persist::*;
+pub use streaming::*;
 
 pub type CurveToIdx<T> = (T, T);
 
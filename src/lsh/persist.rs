@@ -0,0 +1,171 @@
+use super::tensored::TensoredMultiHash;
+use super::traits::TrajectoryLsh;
+use crate::config::map_master_path;
+use crate::config::master_result_dir;
+use crate::dyft::VCodeTools;
+use anyhow::anyhow;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+/// Magic bytes at the start of every exported hash family
+pub const HASHER_MAGIC: &[u8; 8] = b"LSHASHER";
+
+/// Version of the on-disk layout, bumped whenever the layout changes
+pub const HASHER_VERSION: u32 = 1;
+
+/// Key of the hasher fingerprint in the key-value metadata of parquet files
+pub const HASHER_FINGERPRINT_KEY: &str = "master.hasher_fingerprint";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64-bit FNV-1a hash of `bytes`
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+pub fn format_fingerprint(fingerprint: u64) -> String {
+    format!("{:016x}", fingerprint)
+}
+
+pub fn parse_fingerprint(fingerprint: &str) -> Result<u64> {
+    u64::from_str_radix(fingerprint, 16)
+        .map_err(|e| anyhow!("invalid hasher fingerprint {:?}: {}", fingerprint, e))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HasherHeader {
+    pub version: u32,
+    pub hash_bits: usize,
+    pub fingerprint: u64,
+}
+
+impl<H> TensoredMultiHash<H>
+where
+    H: TrajectoryLsh,
+{
+    /// A fingerprint of the hash family, i.e. of all its hash functions,
+    /// shift grids, perturbations and coefficients. Two hashers with the
+    /// same fingerprint produce the same hashes.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(&rmp_serde::to_vec(self).expect("TensoredMultiHash is serializable"))
+    }
+
+    /// Writes the hash family to `writer`, prefixed by the magic bytes and the header
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let body = rmp_serde::to_vec(self)?;
+        let header = HasherHeader {
+            version: HASHER_VERSION,
+            hash_bits: H::Hash::N_DIM,
+            fingerprint: fnv1a(&body),
+        };
+        writer.write_all(HASHER_MAGIC)?;
+        rmp_serde::encode::write_named(writer, &header)?;
+        writer.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Reads a hash family written by `write_to`, rejecting it if it was
+    /// written with a different hash width or its fingerprint does not match
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != HASHER_MAGIC {
+            return Err(anyhow!("not a hasher file"));
+        }
+        let mut de = rmp_serde::Deserializer::new(reader);
+        let header = HasherHeader::deserialize(&mut de)?;
+        if header.version != HASHER_VERSION {
+            return Err(anyhow!(
+                "hasher file has version {}, expected {}",
+                header.version,
+                HASHER_VERSION
+            ));
+        }
+        if header.hash_bits != H::Hash::N_DIM {
+            return Err(anyhow!(
+                "hasher file has {}-bit hashes, expected {}-bit hashes",
+                header.hash_bits,
+                H::Hash::N_DIM
+            ));
+        }
+        let hasher = Self::deserialize(&mut de)?;
+        let fingerprint = hasher.fingerprint();
+        if fingerprint != header.fingerprint {
+            return Err(anyhow!(
+                "hasher file has fingerprint {}, but its hash family has fingerprint {}",
+                format_fingerprint(header.fingerprint),
+                format_fingerprint(fingerprint)
+            ));
+        }
+        Ok(hasher)
+    }
+
+    /// Saves the hash family to `path`, relative to $MASTER_RESULT_DIR if set
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(map_master_path(path, master_result_dir()))?;
+        let mut writer = BufWriter::new(file);
+        self.write_to(&mut writer)?;
+        writer.flush().map_err(|e| anyhow!(e))
+    }
+
+    /// Loads a hash family saved with `save` from `path`, relative to $MASTER_RESULT_DIR if set
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(map_master_path(path, master_result_dir()))?;
+        Self::read_from(&mut BufReader::new(file))
+    }
+
+    /// Checks that `fingerprint`, as stored in a vcode or index file, was
+    /// produced by this hash family
+    pub fn check_fingerprint(&self, fingerprint: u64) -> Result<()> {
+        if fingerprint != self.fingerprint() {
+            return Err(anyhow!(
+                "hashes were produced by hasher {}, but the current hasher is {}",
+                format_fingerprint(fingerprint),
+                format_fingerprint(self.fingerprint())
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lsh::Constant64;
+    use crate::lsh::Linear32;
+    use crate::lsh::MultiTrajectoryLsh;
+    use crate::trajectory::Trajectory;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let hasher =
+            TensoredMultiHash::<Constant64>::init(8, 4, 1.0, 16, &mut StdRng::seed_from_u64(7));
+        let mut bytes = Vec::new();
+        hasher.write_to(&mut bytes).unwrap();
+        let loaded = TensoredMultiHash::<Constant64>::read_from(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(hasher.fingerprint(), loaded.fingerprint());
+        let trajectory = Trajectory::from((0..16).map(|i| [i as f64 * 0.3, i as f64 * -0.7]));
+        assert_eq!(
+            hasher.multi_hash(&trajectory).collect::<Vec<_>>(),
+            loaded.multi_hash(&trajectory).collect::<Vec<_>>()
+        );
+
+        let other =
+            TensoredMultiHash::<Constant64>::init(8, 4, 1.0, 16, &mut StdRng::seed_from_u64(8));
+        assert_ne!(hasher.fingerprint(), other.fingerprint());
+        assert!(TensoredMultiHash::<Linear32>::read_from(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use super::tensored::TensoredMultiHash;
+use super::traits::TrajectoryLsh;
+use crate::dyft::VCodeTools;
+use crate::point::PointMatrix;
+use crate::trajectory::Trajectory;
+use num_traits::WrappingAdd;
+use num_traits::WrappingMul;
+use num_traits::Zero;
+use std::ops::Shr;
+
+/// A trajectory hash that is a fold over the points of the trajectory, such
+/// that it can be extended as points are appended instead of rehashing the
+/// whole trajectory
+pub trait StreamingTrajectoryLsh: TrajectoryLsh {
+    type State: Clone;
+
+    /// The state of the empty trajectory
+    fn init_state(&self) -> Self::State;
+
+    /// Extends the state by the next point of the trajectory
+    fn hash_point(&self, state: &mut Self::State, point: &PointMatrix);
+
+    /// The hash of the points folded into the state so far
+    fn state_hash(&self, state: &Self::State) -> Self::Hash;
+}
+
+/// The states of every hash function of a `TensoredMultiHash` for one trajectory
+#[derive(Clone)]
+pub struct StreamingHashState<H>
+where
+    H: StreamingTrajectoryLsh,
+{
+    m_left: Vec<Vec<H::State>>,
+    m_right: Vec<Vec<H::State>>,
+}
+
+impl<H> TensoredMultiHash<H>
+where
+    H: StreamingTrajectoryLsh,
+{
+    pub fn init_state(&self) -> StreamingHashState<H> {
+        let init = |functions: &Vec<Vec<H>>| {
+            Vec::from_iter(
+                functions
+                    .iter()
+                    .map(|fns| Vec::from_iter(fns.iter().map(|f| f.init_state()))),
+            )
+        };
+        StreamingHashState {
+            m_left: init(&self.m_left_fns),
+            m_right: init(&self.m_right_fns),
+        }
+    }
+
+    /// Extends the hash state of every function by `point`
+    pub fn hash_point(&self, state: &mut StreamingHashState<H>, point: &PointMatrix) {
+        let extend = |functions: &Vec<Vec<H>>, states: &mut Vec<Vec<H::State>>| {
+            functions
+                .iter()
+                .flatten()
+                .zip(states.iter_mut().flatten())
+                .for_each(|(f, state)| f.hash_point(state, point));
+        };
+        extend(&self.m_left_fns, &mut state.m_left);
+        extend(&self.m_right_fns, &mut state.m_right);
+    }
+
+    /// The vcode of the points hashed into `state`, equal to the `multi_hash`
+    /// of the trajectory made of those points
+    pub fn state_hash<'a>(
+        &'a self,
+        state: &'a StreamingHashState<H>,
+    ) -> impl Iterator<Item = H::Hash> + 'a {
+        let left = self.inner_tensored_state_hash(&self.m_left_fns, &state.m_left);
+        let right = self.inner_tensored_state_hash(&self.m_right_fns, &state.m_right);
+        self.inner_multi_hash(left, right, self.m_repititions)
+            .take(self.m_hashes)
+    }
+
+    fn inner_tensored_state_hash<'a>(
+        &'a self,
+        functions: &'a [Vec<H>],
+        states: &'a [Vec<H::State>],
+    ) -> impl Iterator<Item = H::Hash> + Clone + 'a {
+        functions.iter().zip(states).map(move |(fns, states)| {
+            fns.iter()
+                .zip(states)
+                .zip(self.m_rand_coefficients.iter())
+                .fold(H::Hash::zero(), |acc, ((f, state), coeff)| {
+                    acc.wrapping_add(&f.state_hash(state).wrapping_mul(coeff))
+                })
+                .shr(H::Hash::N_DIM / 2)
+        })
+    }
+}
+
+/// A trajectory that grows point by point, e.g., the track of a moving
+/// object, together with the hash state and vcode of its points so far
+pub struct StreamingTrajectory<H>
+where
+    H: StreamingTrajectoryLsh,
+{
+    m_trajectory: Trajectory,
+    m_state: StreamingHashState<H>,
+    m_vcode: Vec<H::Hash>,
+}
+
+impl<H> StreamingTrajectory<H>
+where
+    H: StreamingTrajectoryLsh,
+{
+    pub fn new(hasher: &TensoredMultiHash<H>) -> Self {
+        let m_state = hasher.init_state();
+        let m_vcode = hasher.state_hash(&m_state).collect();
+        StreamingTrajectory {
+            m_trajectory: Trajectory::new(),
+            m_state,
+            m_vcode,
+        }
+    }
+
+    pub fn trajectory(&self) -> &Trajectory {
+        &self.m_trajectory
+    }
+
+    pub fn vcode(&self) -> &[H::Hash] {
+        &self.m_vcode
+    }
+
+    /// Appends `point` to the trajectory and updates its vcode. Returns the
+    /// new vcode if it changed, in which case the trajectory must be moved
+    /// in the index it is stored in.
+    pub fn append_point(
+        &mut self,
+        hasher: &TensoredMultiHash<H>,
+        point: PointMatrix,
+    ) -> Option<&[H::Hash]> {
+        self.m_trajectory.append_point(point);
+        hasher.hash_point(&mut self.m_state, &point);
+        let vcode: Vec<H::Hash> = hasher.state_hash(&self.m_state).collect();
+        if vcode != self.m_vcode {
+            self.m_vcode = vcode;
+            Some(&self.m_vcode)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::IndexConfig;
+    use crate::config::MartConfig;
+    use crate::dyft::DyftIndex;
+    use crate::lsh::Constant64;
+    use crate::lsh::Linear64;
+    use crate::lsh::MultiTrajectoryLsh;
+    use crate::test_util::random_walk;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn streamed_vcodes_match<H>(hasher: &TensoredMultiHash<H>, points: &[PointMatrix])
+    where
+        H: StreamingTrajectoryLsh,
+        [H::Hash]: rand::Fill,
+    {
+        let mut streaming = StreamingTrajectory::new(hasher);
+        for &point in points {
+            let before = streaming.vcode().to_vec();
+            let changed = streaming.append_point(hasher, point).is_some();
+            let expected: Vec<_> = hasher.multi_hash(streaming.trajectory()).collect();
+            assert_eq!(expected, streaming.vcode());
+            assert_eq!(changed, before != expected);
+        }
+    }
+
+    #[test]
+    fn streamed_vcodes_match_full_hashes() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let points = random_walk(32, 0.5, &mut rng);
+        streamed_vcodes_match(
+            &TensoredMultiHash::<Linear64>::init(8, 2, 1.0, 32, &mut rng),
+            points.as_ref(),
+        );
+        streamed_vcodes_match(
+            &TensoredMultiHash::<Constant64>::init(8, 2, 1.0, 32, &mut rng),
+            points.as_ref(),
+        );
+    }
+
+    #[test]
+    fn streams_grow_past_the_hasher_max_len() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let points = random_walk(100, 0.5, &mut rng);
+        streamed_vcodes_match(
+            &TensoredMultiHash::<Linear64>::init(8, 2, 1.0, 8, &mut rng),
+            points.as_ref(),
+        );
+        streamed_vcodes_match(
+            &TensoredMultiHash::<Constant64>::init(8, 2, 1.0, 8, &mut rng),
+            points.as_ref(),
+        );
+    }
+
+    #[test]
+    fn streamed_trajectories_move_in_dyft() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let config = IndexConfig::<MartConfig>::default()
+            .resolution(1.0)
+            .with_splitthreshold(4)
+            .with_radius(0)
+            .with_errors(0)
+            .with_bbox_filter();
+        let mut index = DyftIndex::<Linear64>::new(&config, 16);
+        let mut objects = Vec::from_iter((0..50).map(|_| {
+            let streaming = StreamingTrajectory::new(index.hasher());
+            let id = index.insert_vcode(streaming.vcode());
+            (streaming, id, random_walk(16, 0.5, &mut rng))
+        }));
+        let mut moves = 0;
+        for (streaming, id, points) in objects.iter_mut() {
+            for &point in points.iter() {
+                if let Some(vcode) = streaming.append_point(index.hasher(), point) {
+                    index.update_trajectory(*id, vcode);
+                    moves += 1;
+                }
+                index.update_bounding_box(*id, streaming.trajectory());
+            }
+        }
+        assert!(moves > 0);
+        assert_eq!(index.size(), objects.len());
+        for (streaming, id, _) in objects.iter() {
+            assert!(index.query_trajectory(streaming.trajectory()).contains(id));
+        }
+    }
+}
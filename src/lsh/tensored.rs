@@ -3,6 +3,7 @@ use super::traits::TrajectoryLsh;
 use super::Resolution;
 use crate::dyft::VCodeArray;
 use crate::dyft::VCodeTools;
+use crate::point::Distance;
 use crate::trajectory::Trajectory;
 use get_size::GetSize;
 use itertools::Itertools;
@@ -11,18 +12,25 @@ use num_traits::WrappingMul;
 use num_traits::Zero;
 use rand::Fill;
 use rand::Rng;
+use serde::Deserialize;
+use serde::Serialize;
 use std::ops::Shr;
 
+/// The hash of a group of functions with its scored probe hashes
+type ProbedGroup<T> = (T, Vec<(Distance, T)>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
 pub struct TensoredMultiHash<H>
 where
     H: TrajectoryLsh,
 {
-    m_left_fns: Vec<Vec<H>>,
-    m_right_fns: Vec<Vec<H>>,
-    m_rand_coefficients: Vec<H::Hash>,
-    m_hashes: usize,
+    pub(super) m_left_fns: Vec<Vec<H>>,
+    pub(super) m_right_fns: Vec<Vec<H>>,
+    pub(super) m_rand_coefficients: Vec<H::Hash>,
+    pub(super) m_hashes: usize,
     m_concatenations: usize,
-    m_repititions: usize,
+    pub(super) m_repititions: usize,
 }
 
 impl<H> TensoredMultiHash<H>
@@ -79,6 +87,16 @@ where
     pub fn concatenations(&self) -> usize {
         self.m_concatenations
     }
+
+    /// The grid resolution of the hash functions, if there are any
+    pub fn resolution(&self) -> Option<Distance> {
+        self.m_left_fns
+            .iter()
+            .chain(self.m_right_fns.iter())
+            .flatten()
+            .next()
+            .map(H::resolution)
+    }
 }
 
 impl<'a, H> MultiTrajectoryLsh<'a> for TensoredMultiHash<H>
@@ -173,7 +191,7 @@ where
             .into_iter()
     }
 
-    fn inner_multi_hash<'a>(
+    pub(super) fn inner_multi_hash<'a>(
         &self,
         left: impl Iterator<Item = H::Hash> + 'a,
         right: impl Iterator<Item = H::Hash> + Clone + 'a,
@@ -226,6 +244,91 @@ where
     }
 }
 
+impl<H> TensoredMultiHash<H>
+where
+    H: TrajectoryLsh,
+{
+    /// The hashes of `trajectory` in every table for multi-probe queries.
+    /// Each table starts with the exact hash of `multi_hash`, followed by up
+    /// to `probes` distinct hashes of neighbouring buckets, the most
+    /// promising first. The probes of a table perturb one of the base hash
+    /// functions of its left or right group.
+    pub fn multi_probe_hash(&self, trajectory: &Trajectory, probes: usize) -> Vec<Vec<H::Hash>> {
+        let left = self.probe_groups(&self.m_left_fns, trajectory, probes);
+        let right = self.probe_groups(&self.m_right_fns, trajectory, probes);
+        left.iter()
+            .take(self.m_repititions)
+            .flat_map(|(l, l_probes)| {
+                right.iter().take(self.m_repititions).map(move |(r, r_probes)| {
+                    let mut hashes = vec![self.combine(*l, *r)];
+                    let ranked = l_probes
+                        .iter()
+                        .map(|&(score, l)| (score, self.combine(l, *r)))
+                        .chain(r_probes.iter().map(|&(score, r)| (score, self.combine(*l, r))))
+                        .sorted_by(|a, b| a.0.total_cmp(&b.0));
+                    for (_, hash) in ranked {
+                        if hashes.len() > probes {
+                            break;
+                        }
+                        if !hashes.contains(&hash) {
+                            hashes.push(hash);
+                        }
+                    }
+                    hashes
+                })
+            })
+            .take(self.m_hashes)
+            .collect()
+    }
+
+    /// The hash of every group of functions, with the scored hashes of the
+    /// group with one of its functions probing a neighbouring cell
+    fn probe_groups(
+        &self,
+        groups: &[Vec<H>],
+        trajectory: &Trajectory,
+        probes: usize,
+    ) -> Vec<ProbedGroup<H::Hash>> {
+        groups
+            .iter()
+            .map(|functions| {
+                let hashes = Vec::from_iter(functions.iter().map(|f| f.hash(trajectory)));
+                let group_probes = functions
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(idx, f)| {
+                        f.probe_hashes(trajectory, probes)
+                            .into_iter()
+                            .map(move |(score, hash)| (score, idx, hash))
+                    })
+                    .map(|(score, idx, hash)| {
+                        let mut probed = hashes.clone();
+                        probed[idx] = hash;
+                        (score, self.group_hash(&probed))
+                    })
+                    .collect();
+                (self.group_hash(&hashes), group_probes)
+            })
+            .collect()
+    }
+
+    fn group_hash(&self, hashes: &[H::Hash]) -> H::Hash {
+        hashes
+            .iter()
+            .zip(self.m_rand_coefficients.iter())
+            .fold(H::Hash::zero(), |acc, (hash, coeff)| {
+                acc.wrapping_add(&hash.wrapping_mul(coeff))
+            })
+            .shr(H::Hash::N_DIM / 2)
+    }
+
+    fn combine(&self, left: H::Hash, right: H::Hash) -> H::Hash {
+        left.wrapping_mul(&self.m_rand_coefficients[0])
+            .wrapping_add(&right.wrapping_mul(&self.m_rand_coefficients[1]))
+            .shr(H::Hash::N_DIM / 2)
+    }
+}
+
 impl<H> TensoredMultiHash<H>
 where
     H: TrajectoryLsh,
@@ -264,3 +367,30 @@ where
             + self.m_concatenations.get_heap_size()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lsh::Linear64;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn multi_probe_hash_extends_multi_hash() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let hasher = TensoredMultiHash::<Linear64>::init(16, 4, 1.0, 32, &mut rng);
+        let trajectory = Trajectory::from((0..10).map(|i| [i as f64 * 0.7, (i as f64).sin()]));
+        let exact: Vec<_> = hasher.multi_hash(&trajectory).collect();
+
+        let unprobed = hasher.multi_probe_hash(&trajectory, 0);
+        assert_eq!(unprobed, exact.iter().map(|&hash| vec![hash]).collect::<Vec<_>>());
+
+        let probed = hasher.multi_probe_hash(&trajectory, 3);
+        assert_eq!(probed.len(), exact.len());
+        for (hashes, hash) in probed.iter().zip(exact) {
+            assert_eq!(hashes[0], hash);
+            assert!(hashes.len() > 1 && hashes.len() <= 4);
+            assert!(hashes.iter().all_unique());
+        }
+    }
+}
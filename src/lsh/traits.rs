@@ -1,17 +1,34 @@
 use crate::dyft::VCodeTools;
+use crate::point::Distance;
 use crate::trajectory::Trajectory;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
-pub trait TrajectoryLsh {
+pub trait TrajectoryLsh: Serialize + DeserializeOwned {
     type Hash: VCodeTools;
 
     fn init(delta: f64, max_len: usize, rng: &mut impl rand::Rng) -> Self;
 
     fn hash(&self, trajectory: &Trajectory) -> Self::Hash;
 
+    /// The grid resolution the scheme was initialized with
+    fn resolution(&self) -> Distance;
+
     /// method to be overwritten by asymmetric hashing schemes
     fn hash_query(&self, trajectory: &Trajectory) -> Self::Hash {
         self.hash(trajectory)
     }
+
+    /// Up to `probes` hashes of cells neighbouring the one of `trajectory`,
+    /// each with a score, lower being more likely to hold a near neighbour.
+    /// Schemes without multi-probe support return no probes.
+    fn probe_hashes(
+        &self,
+        _trajectory: &Trajectory,
+        _probes: usize,
+    ) -> Vec<(Distance, Self::Hash)> {
+        Vec::new()
+    }
 }
 
 pub trait MultiTrajectoryLsh<'a> {
@@ -0,0 +1,150 @@
+use crate::frechet::continuous_frechet_decider;
+use crate::frechet::continuous_frechet_distance;
+use crate::frechet::discrete_frechet_decider;
+use crate::frechet::discrete_frechet_distance;
+use crate::point::Distance;
+use crate::point::PointMatrix;
+use crate::trajectory::Trajectory;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The distance the verifying queries decide the candidates with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerificationMetric {
+    /// Continuous fréchet distance
+    #[default]
+    ContinuousFrechet,
+    /// Discrete fréchet distance over the vertices
+    DiscreteFrechet,
+    /// Dynamic time warping, the smallest sum of vertex distances over the
+    /// couplings of the vertices
+    Dtw,
+    /// Hausdorff distance between the vertex sets
+    Hausdorff,
+}
+
+impl VerificationMetric {
+    /// Whether the distance of `t1` and `t2` is at most `bound`
+    pub fn decide(&self, t1: &Trajectory, t2: &Trajectory, bound: Distance) -> bool {
+        match self {
+            VerificationMetric::ContinuousFrechet => continuous_frechet_decider(t1, t2, bound),
+            VerificationMetric::DiscreteFrechet => discrete_frechet_decider(t1, t2, bound),
+            VerificationMetric::Dtw => dtw_decider(t1, t2, bound),
+            VerificationMetric::Hausdorff => hausdorff_decider(t1, t2, bound),
+        }
+    }
+
+    /// Whether the metric is one of the fréchet distances
+    pub fn is_frechet(&self) -> bool {
+        matches!(
+            self,
+            VerificationMetric::ContinuousFrechet | VerificationMetric::DiscreteFrechet
+        )
+    }
+
+    /// The fréchet distance of `t1` and `t2` this metric decides, or None if
+    /// it is not a fréchet distance
+    pub fn frechet_distance(&self, t1: &Trajectory, t2: &Trajectory) -> Option<Distance> {
+        match self {
+            VerificationMetric::ContinuousFrechet => Some(continuous_frechet_distance(t1, t2)),
+            VerificationMetric::DiscreteFrechet => Some(discrete_frechet_distance(t1, t2)),
+            VerificationMetric::Dtw | VerificationMetric::Hausdorff => None,
+        }
+    }
+}
+
+fn distance(a: &PointMatrix, b: &PointMatrix) -> Distance {
+    (b - a).norm()
+}
+
+/// Decides whether the dynamic time warping distance of `t1` and `t2` is at
+/// most `bound`, keeping two rows of the dynamic program. The costs only
+/// grow along a coupling, so the decision is made as soon as a whole row
+/// exceeds the bound. Empty curves are never within the bound.
+pub fn dtw_decider(t1: &Trajectory, t2: &Trajectory, bound: Distance) -> bool {
+    let (n, m) = (t1.len(), t2.len());
+    if n == 0 || m == 0 {
+        return false;
+    }
+    let mut rows: Vec<Distance> = vec![0.0; 2 * m];
+    let (mut prev_row, mut current_row) = rows.split_at_mut(m);
+    for i in 0..n {
+        for j in 0..m {
+            let d = distance(&t1[i], &t2[j]);
+            current_row[j] = d + match (i, j) {
+                (0, 0) => 0.0,
+                (0, _) => current_row[j - 1],
+                (_, 0) => prev_row[0],
+                _ => prev_row[j].min(prev_row[j - 1]).min(current_row[j - 1]),
+            };
+        }
+        if current_row.iter().all(|&cost| cost > bound) {
+            return false;
+        }
+        std::mem::swap(&mut prev_row, &mut current_row);
+    }
+    prev_row[m - 1] <= bound
+}
+
+/// Decides whether every vertex of each curve is within `bound` of a vertex
+/// of the other. Empty curves are never within the bound.
+pub fn hausdorff_decider(t1: &Trajectory, t2: &Trajectory, bound: Distance) -> bool {
+    let covered = |a: &Trajectory, b: &Trajectory| {
+        a.iter().all(|p| b.iter().any(|q| distance(p, q) <= bound))
+    };
+    t1.len() > 0 && t2.len() > 0 && covered(t1, t2) && covered(t2, t1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_curve;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// The dynamic time warping distance as the minimum over every coupling
+    /// of the summed distances along it
+    fn brute_force_dtw(t1: &Trajectory, t2: &Trajectory, i: usize, j: usize) -> Distance {
+        let d = distance(&t1[i], &t2[j]);
+        d + match (i, j) {
+            (0, 0) => 0.0,
+            (0, _) => brute_force_dtw(t1, t2, 0, j - 1),
+            (_, 0) => brute_force_dtw(t1, t2, i - 1, 0),
+            _ => brute_force_dtw(t1, t2, i - 1, j - 1)
+                .min(brute_force_dtw(t1, t2, i - 1, j))
+                .min(brute_force_dtw(t1, t2, i, j - 1)),
+        }
+    }
+
+    #[test]
+    fn metrics_decide_like_their_definitions() {
+        let mut rng = StdRng::seed_from_u64(17);
+        for _ in 0..300 {
+            let (t1, t2) = (random_curve(5, 3.0, &mut rng), random_curve(5, 3.0, &mut rng));
+            let dtw = brute_force_dtw(&t1, &t2, t1.len() - 1, t2.len() - 1);
+            assert!(VerificationMetric::Dtw.decide(&t1, &t2, dtw));
+            assert!(!VerificationMetric::Dtw.decide(&t1, &t2, dtw * 0.999));
+
+            let directed = |a: &Trajectory, b: &Trajectory| {
+                a.iter()
+                    .map(|p| {
+                        b.iter()
+                            .map(|q| distance(p, q))
+                            .fold(Distance::MAX, Distance::min)
+                    })
+                    .fold(0.0, Distance::max)
+            };
+            let hausdorff = directed(&t1, &t2).max(directed(&t2, &t1));
+            assert!(VerificationMetric::Hausdorff.decide(&t1, &t2, hausdorff));
+            assert!(!VerificationMetric::Hausdorff.decide(&t1, &t2, hausdorff * 0.999));
+
+            // hausdorff <= discrete fréchet <= dtw, continuous <= discrete fréchet
+            let discrete = discrete_frechet_distance(&t1, &t2);
+            assert!(VerificationMetric::DiscreteFrechet.decide(&t1, &t2, discrete));
+            assert!(VerificationMetric::ContinuousFrechet.decide(&t1, &t2, discrete));
+            assert!(discrete <= dtw && hausdorff <= discrete);
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use crate::config::master_config;
 use crate::config::IndexConfig;
+use crate::point::Distance;
+use crate::subtrajectory::WindowSize;
+use crate::subtrajectory::Windows;
 use anyhow::Result;
 use clap::Args;
 use clap::Parser;
@@ -22,6 +25,45 @@ pub struct BuildArgs {
     #[arg(short, long, help = "Path to the output file (optional)")]
     pub output: Option<PathBuf>,
 
+    #[arg(long, help = "Path to save the built index to (optional)")]
+    pub save_index: Option<PathBuf>,
+
+    #[arg(long, help = "Path to load a previously built index from (optional)")]
+    pub load_index: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Path to a saved hasher, used instead of one generated from the seed (optional)"
+    )]
+    pub hasher: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Number of threads to build and query with, 0 for one per core. Builds with memory samples stay serial, and DyFT only hashes the dataset in parallel, its trie is built serially (optional)"
+    )]
+    pub threads: Option<usize>,
+
+    #[arg(
+        long,
+        conflicts_with = "window_length",
+        help = "Index windows of this many points for subtrajectory search (optional)"
+    )]
+    pub window_points: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Index windows of at least this arc length for subtrajectory search (optional)"
+    )]
+    pub window_length: Option<Distance>,
+
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Number of points between window starts"
+    )]
+    pub window_step: usize,
+
     #[arg(help = "Number of indexed trajectories for each memory sample (optional)")]
     pub samples: Option<Vec<usize>>,
 }
@@ -30,6 +72,18 @@ pub struct BuildArgs {
 pub struct QueryArgs {
     #[arg(short, long, help = "Path to the query set")]
     pub queryset: PathBuf,
+
+    #[arg(
+        long,
+        help = "Compute the fréchet distance of every result pair under the verification metric, which must be a fréchet distance"
+    )]
+    pub frechet_distance: bool,
+
+    #[arg(
+        long,
+        help = "Answer one query at a time and write its results as soon as it finishes, as JSON Lines if the output file ends in .jsonl. The queries are answered on one thread, --threads only applies to the build. The stats only count the results of each query, and FRESH scores the whole queryset first to estimate its verification threshold"
+    )]
+    pub stream: bool,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -57,6 +111,12 @@ pub enum IndexCommandWithConfigPath {
 
     #[command(name = "query", about = "Build and query the index")]
     Query(QueryWithConfigPath),
+
+    #[command(
+        name = "join",
+        about = "Build the index and find all pairs of similar trajectories in the dataset"
+    )]
+    Join(BuildWithConfigPath),
 }
 
 impl IndexCommandWithConfigPath {
@@ -80,6 +140,12 @@ impl IndexCommandWithConfigPath {
                     query: q.query,
                 }))
             }
+            IndexCommandWithConfigPath::Join(j) => {
+                Ok(IndexCommandWithArgs::Join(BuildWithArgs::<T> {
+                    config: master_config(j.config_file)?,
+                    build: j.build,
+                }))
+            }
         }
     }
 }
@@ -121,6 +187,12 @@ where
 
     #[clap(name = "query", about = "Build and query the index")]
     Query(QueryWithArgs<T>),
+
+    #[clap(
+        name = "join",
+        about = "Build the index and find all pairs of similar trajectories in the dataset"
+    )]
+    Join(BuildWithArgs<T>),
 }
 
 impl<T> IndexCommandWithArgs<T>
@@ -138,43 +210,113 @@ where
 
     pub fn memory_samples(&self) -> Option<Vec<usize>> {
         match self {
-            IndexCommandWithArgs::Build(b) => b.build.samples.clone(),
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => {
+                b.build.samples.clone()
+            }
             IndexCommandWithArgs::Query(q) => q.build.samples.clone(),
         }
     }
 
     pub fn data_path(&self) -> &PathBuf {
         match self {
-            IndexCommandWithArgs::Build(b) => &b.build.dataset,
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => &b.build.dataset,
             IndexCommandWithArgs::Query(q) => &q.build.dataset,
         }
     }
 
     pub fn query_path(&self) -> Option<&PathBuf> {
         match self {
-            IndexCommandWithArgs::Build(_) => None,
+            IndexCommandWithArgs::Build(_) | IndexCommandWithArgs::Join(_) => None,
             IndexCommandWithArgs::Query(q) => Some(&q.query.queryset),
         }
     }
 
     pub fn output_path(&self) -> Option<&PathBuf> {
         match self {
-            IndexCommandWithArgs::Build(b) => b.build.output.as_ref(),
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => {
+                b.build.output.as_ref()
+            }
             IndexCommandWithArgs::Query(q) => q.build.output.as_ref(),
         }
     }
 
+    pub fn save_index_path(&self) -> Option<&PathBuf> {
+        match self {
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => {
+                b.build.save_index.as_ref()
+            }
+            IndexCommandWithArgs::Query(q) => q.build.save_index.as_ref(),
+        }
+    }
+
+    pub fn load_index_path(&self) -> Option<&PathBuf> {
+        match self {
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => {
+                b.build.load_index.as_ref()
+            }
+            IndexCommandWithArgs::Query(q) => q.build.load_index.as_ref(),
+        }
+    }
+
+    pub fn hasher_path(&self) -> Option<&PathBuf> {
+        match self {
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => {
+                b.build.hasher.as_ref()
+            }
+            IndexCommandWithArgs::Query(q) => q.build.hasher.as_ref(),
+        }
+    }
+
     pub fn index_config(&self) -> &IndexConfig<T> {
         match self {
-            IndexCommandWithArgs::Build(b) => &b.config,
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => &b.config,
             IndexCommandWithArgs::Query(q) => &q.config,
         }
     }
 
     pub fn n(&self) -> Option<usize> {
         match self {
-            IndexCommandWithArgs::Build(b) => b.build.n,
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => b.build.n,
             IndexCommandWithArgs::Query(q) => q.build.n,
         }
     }
+
+    pub fn threads(&self) -> Option<usize> {
+        match self {
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => b.build.threads,
+            IndexCommandWithArgs::Query(q) => q.build.threads,
+        }
+    }
+
+    /// The windows to index for subtrajectory search, if enabled
+    pub fn windows(&self) -> Option<Windows> {
+        let build = match self {
+            IndexCommandWithArgs::Build(b) | IndexCommandWithArgs::Join(b) => &b.build,
+            IndexCommandWithArgs::Query(q) => &q.build,
+        };
+        let size = match (build.window_points, build.window_length) {
+            (Some(points), _) => WindowSize::Points(points),
+            (None, Some(length)) => WindowSize::ArcLength(length),
+            (None, None) => return None,
+        };
+        Some(Windows::new(size, build.window_step))
+    }
+
+    pub fn frechet_distance(&self) -> bool {
+        match self {
+            IndexCommandWithArgs::Build(_) | IndexCommandWithArgs::Join(_) => false,
+            IndexCommandWithArgs::Query(q) => q.query.frechet_distance,
+        }
+    }
+
+    pub fn stream(&self) -> bool {
+        match self {
+            IndexCommandWithArgs::Build(_) | IndexCommandWithArgs::Join(_) => false,
+            IndexCommandWithArgs::Query(q) => q.query.stream,
+        }
+    }
+
+    pub fn is_join(&self) -> bool {
+        matches!(self, IndexCommandWithArgs::Join(_))
+    }
 }
@@ -4,6 +4,36 @@ pub type Distance = f64;
 pub type Point = [Distance; 2];
 pub type PointMatrix = Matrix2x1<Distance>;
 
+/// The fréchet distance threshold of the queries of a queryset, either one
+/// threshold for all queries or one for each query
+#[derive(Debug, Clone, Copy)]
+pub enum QueryDistance<'a> {
+    Global(Distance),
+    PerQuery(&'a [Distance]),
+}
+
+impl QueryDistance<'_> {
+    /// The threshold of the query with index `query`
+    pub fn of(&self, query: usize) -> Distance {
+        match self {
+            QueryDistance::Global(distance) => *distance,
+            QueryDistance::PerQuery(distances) => distances[query],
+        }
+    }
+}
+
+impl From<Distance> for QueryDistance<'_> {
+    fn from(distance: Distance) -> Self {
+        QueryDistance::Global(distance)
+    }
+}
+
+impl<'a> From<&'a [Distance]> for QueryDistance<'a> {
+    fn from(distances: &'a [Distance]) -> Self {
+        QueryDistance::PerQuery(distances)
+    }
+}
+
 pub struct PolarCoordinate {
     pub r: f64,
     pub theta: f64,
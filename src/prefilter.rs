@@ -0,0 +1,114 @@
+use crate::point::Distance;
+use crate::trajectory::Trajectory;
+use geo::BoundingRect;
+use geo::Rect;
+
+/// The bounding boxes of the indexed trajectories, used to prune candidates
+/// before verifying them. The vertices of two trajectories within fréchet
+/// distance `r` of each other are within `r` of each other, so each bounding
+/// box lies within the other one expanded by `r`.
+#[derive(Default)]
+pub struct BoundingBoxFilter {
+    m_boxes: Vec<Option<Rect<Distance>>>, // trajectory id -> bounding box
+}
+
+impl BoundingBoxFilter {
+    pub fn new(dataset: &[Trajectory]) -> Self {
+        BoundingBoxFilter {
+            m_boxes: dataset.iter().map(|trajectory| trajectory.bounding_rect()).collect(),
+        }
+    }
+
+    /// Sets the bounding box of trajectory `id`, replacing the one it had
+    pub fn insert(&mut self, id: usize, trajectory: &Trajectory) {
+        if id >= self.m_boxes.len() {
+            self.m_boxes.resize(id + 1, None);
+        }
+        self.m_boxes[id] = trajectory.bounding_rect();
+    }
+
+    /// Tests the indexed trajectories for being compatible with `query` being
+    /// within fréchet distance `distance` of them
+    pub fn candidates(&self, query: &Trajectory, distance: Distance) -> BoundingBoxCandidates {
+        BoundingBoxCandidates {
+            m_query: query.bounding_rect(),
+            m_distance: distance,
+            m_boxes: &self.m_boxes,
+        }
+    }
+}
+
+/// The result of `BoundingBoxFilter::candidates` for one query
+pub struct BoundingBoxCandidates<'a> {
+    m_query: Option<Rect<Distance>>,
+    m_distance: Distance,
+    m_boxes: &'a [Option<Rect<Distance>>],
+}
+
+impl BoundingBoxCandidates<'_> {
+    /// Whether candidate `id` may be within the fréchet distance of the
+    /// query. Trajectories without a bounding box in the filter are never
+    /// pruned.
+    pub fn may_match(&self, id: usize) -> bool {
+        match self.m_boxes.get(id).copied().flatten() {
+            Some(envelope) => self.m_query.is_some_and(|query| {
+                contains(&expand(&query, self.m_distance), &envelope)
+                    && contains(&expand(&envelope, self.m_distance), &query)
+            }),
+            None => true,
+        }
+    }
+}
+
+fn expand(envelope: &Rect<Distance>, distance: Distance) -> Rect<Distance> {
+    let (min, max) = (envelope.min(), envelope.max());
+    Rect::new(
+        (min.x - distance, min.y - distance),
+        (max.x + distance, max.y + distance),
+    )
+}
+
+fn contains(outer: &Rect<Distance>, inner: &Rect<Distance>) -> bool {
+    outer.min().x <= inner.min().x
+        && outer.min().y <= inner.min().y
+        && inner.max().x <= outer.max().x
+        && inner.max().y <= outer.max().y
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::random_walk;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn filter_never_prunes_matching_trajectories() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let dataset: Vec<_> = (0..200).map(|_| random_walk(6, 0.5, &mut rng)).collect();
+        let queryset: Vec<_> = (0..20).map(|_| random_walk(6, 0.5, &mut rng)).collect();
+        let mut filter = BoundingBoxFilter::new(&dataset[..150]);
+        dataset[150..]
+            .iter()
+            .enumerate()
+            .for_each(|(i, trajectory)| filter.insert(150 + i, trajectory));
+
+        let mut pruned = 0;
+        for query in &queryset {
+            let candidates = filter.candidates(query, 1.5);
+            for (id, trajectory) in dataset.iter().enumerate() {
+                if !candidates.may_match(id) {
+                    pruned += 1;
+                    assert!(!query.frechet_decider(trajectory, 1.5));
+                }
+            }
+            assert!(candidates.may_match(dataset.len()));
+        }
+        assert!(pruned > 0);
+
+        // a moved trajectory is tested against its new bounding box
+        assert!(!filter.candidates(&queryset[0], 0.0).may_match(0));
+        filter.insert(0, &queryset[0]);
+        assert!(filter.candidates(&queryset[0], 0.0).may_match(0));
+    }
+}
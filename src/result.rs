@@ -0,0 +1,128 @@
+use crate::id::TrajectoryID;
+use crate::metric::VerificationMetric;
+use crate::point::Distance;
+use crate::trajectory::Trajectory;
+use crate::util::map_to_trajectory_ids;
+use std::time::Duration;
+
+/// A query result pair together with what the index knew about it when it
+/// was reported, to analyse false positives without querying again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryResult<I = usize> {
+    pub query: I,
+    pub candidate: I,
+    /// Hamming distance between the vcodes of the pair (DyFT)
+    pub hamming_distance: Option<u32>,
+    /// Number of tables the pair collides in (FRESH)
+    pub collisions: Option<u32>,
+    /// Whether the pair was accepted by the fréchet decider, as opposed to
+    /// being reported on its hashes alone
+    pub verified: bool,
+    /// Fréchet distance of the pair under the verification metric, only
+    /// computed on request
+    pub frechet_distance: Option<Distance>,
+}
+
+impl QueryResult {
+    pub fn new(query: usize, candidate: usize) -> Self {
+        QueryResult {
+            query,
+            candidate,
+            hamming_distance: None,
+            collisions: None,
+            verified: false,
+            frechet_distance: None,
+        }
+    }
+
+    pub fn pair(&self) -> (usize, usize) {
+        (self.query, self.candidate)
+    }
+
+    /// Computes the fréchet distance of the pair under `metric`, which is
+    /// left unset if `metric` is not a fréchet distance
+    pub fn with_frechet_distance(
+        self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        metric: VerificationMetric,
+    ) -> Self {
+        let distance = metric.frechet_distance(&queryset[self.query], &dataset[self.candidate]);
+        QueryResult {
+            frechet_distance: distance,
+            ..self
+        }
+    }
+}
+
+/// The results of one query, reported as soon as the query finishes. `V`
+/// holds the verification counters of the query, see
+/// `SimilarityIndex::query_groups`.
+#[derive(Debug, Clone)]
+pub struct QueryGroup<I = usize, V = ()> {
+    pub query: I,
+    pub results: Vec<QueryResult<I>>,
+    /// Time spent finding and verifying the candidates of the query
+    pub time: Duration,
+    /// The verification result of the query, if its candidates were verified
+    pub verification: Option<V>,
+}
+
+impl<V> QueryGroup<usize, V> {
+    /// Replaces the query and candidate indices with their ids, dropping the
+    /// results whose ids are unknown
+    pub fn map_to_trajectory_ids<'a>(
+        self,
+        dataset: &'a [TrajectoryID<'a>],
+        queryset: &'a [TrajectoryID<'a>],
+    ) -> Option<QueryGroup<TrajectoryID<'a>, V>> {
+        let query = *queryset.get(self.query)?;
+        Some(QueryGroup {
+            query,
+            results: map_to_trajectory_ids(self.results, dataset, queryset).collect(),
+            time: self.time,
+            verification: self.verification,
+        })
+    }
+}
+
+/// A result item whose query and candidate indices can be replaced by ids,
+/// see `util::map_to_trajectory_ids`
+pub trait MapIds {
+    type Mapped<T>;
+
+    fn pair(&self) -> (usize, usize);
+
+    fn map_ids<T>(self, query: T, candidate: T) -> Self::Mapped<T>;
+}
+
+impl MapIds for (usize, usize) {
+    type Mapped<T> = (T, T);
+
+    fn pair(&self) -> (usize, usize) {
+        *self
+    }
+
+    fn map_ids<T>(self, query: T, candidate: T) -> (T, T) {
+        (query, candidate)
+    }
+}
+
+impl MapIds for QueryResult {
+    type Mapped<T> = QueryResult<T>;
+
+    fn pair(&self) -> (usize, usize) {
+        QueryResult::pair(self)
+    }
+
+    fn map_ids<T>(self, query: T, candidate: T) -> QueryResult<T> {
+        QueryResult {
+            query,
+            candidate,
+            hamming_distance: self.hamming_distance,
+            collisions: self.collisions,
+            verified: self.verified,
+            frechet_distance: self.frechet_distance,
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::config::IndexConfig;
+use crate::index::SimilarityIndex;
+use crate::lsh::TensoredMultiHash;
+use crate::metric::VerificationMetric;
+use crate::point::Distance;
+use crate::point::QueryDistance;
+use crate::result::MapIds;
+use crate::trajectory::Trajectory;
+use anyhow::Result;
+use rayon::prelude::*;
+use std::path::Path;
+
+/// How far a window extends from its first point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowSize {
+    /// A fixed number of points
+    Points(usize),
+    /// The fewest points whose polyline is at least this long
+    ArcLength(Distance),
+}
+
+/// Splits trajectories into overlapping windows, starting one every `step`
+/// points. The last window of a trajectory runs up to its end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Windows {
+    pub size: WindowSize,
+    pub step: usize,
+}
+
+/// A window of the data trajectory `parent`, covering its points
+/// `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub parent: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Windows {
+    pub fn new(size: WindowSize, step: usize) -> Self {
+        assert!(step > 0, "window step must be positive");
+        Windows { size, step }
+    }
+
+    /// The windows of `trajectory`, the data trajectory with index `parent`
+    pub fn split<'a>(
+        &'a self,
+        parent: usize,
+        trajectory: &'a Trajectory,
+    ) -> impl Iterator<Item = Window> + 'a {
+        let len = trajectory.len();
+        let mut done = len == 0;
+        (0..len).step_by(self.step).map_while(move |start| {
+            if done {
+                return None;
+            }
+            let end = self.window_end(trajectory, start);
+            done = end == len;
+            Some(Window { parent, start, end })
+        })
+    }
+
+    fn window_end(&self, trajectory: &Trajectory, start: usize) -> usize {
+        let len = trajectory.len();
+        match self.size {
+            WindowSize::Points(points) => (start + points.max(2)).min(len),
+            WindowSize::ArcLength(length) => {
+                let mut arc = 0.0;
+                let mut end = start + 1;
+                while end < len && (arc < length || end - start < 2) {
+                    arc += (trajectory[end] - trajectory[end - 1]).norm();
+                    end += 1;
+                }
+                end
+            }
+        }
+    }
+}
+
+impl Window {
+    pub fn slice(&self, dataset: &[Trajectory]) -> Trajectory {
+        dataset[self.parent].as_ref()[self.start..self.end]
+            .iter()
+            .copied()
+            .collect()
+    }
+}
+
+/// A query matching the points `start..end` of a data trajectory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SubtrajectoryMatch<I = usize> {
+    pub query: I,
+    pub trajectory: I,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl MapIds for SubtrajectoryMatch {
+    type Mapped<T> = SubtrajectoryMatch<T>;
+
+    fn pair(&self) -> (usize, usize) {
+        (self.query, self.trajectory)
+    }
+
+    fn map_ids<T>(self, query: T, candidate: T) -> SubtrajectoryMatch<T> {
+        SubtrajectoryMatch {
+            query,
+            trajectory: candidate,
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// Subtrajectory range search on top of a similarity index `I`, which is
+/// built over the windows of the data trajectories. Queries are matched
+/// against whole windows, so the windows should be about as long as the
+/// queries. Only the ranges of the windows are kept, the verifying queries
+/// slice the candidate windows from the dataset.
+pub struct SubtrajectoryIndex<I> {
+    m_index: I,
+    m_windows: Vec<Window>,
+    m_metric: VerificationMetric,
+}
+
+impl<I> SubtrajectoryIndex<I>
+where
+    I: SimilarityIndex,
+{
+    pub fn build(
+        config: &IndexConfig<I::Params>,
+        hasher: Option<TensoredMultiHash<I::Lsh>>,
+        dataset: &[Trajectory],
+        windows: &Windows,
+    ) -> Self {
+        let (m_windows, slices) = Self::split(dataset, windows);
+        let m_index = I::build(config, hasher, &slices);
+        SubtrajectoryIndex {
+            m_index,
+            m_windows,
+            m_metric: I::verification_metric(config),
+        }
+    }
+
+    /// Like `build`, on the threads of the current rayon pool
+    pub fn par_build(
+        config: &IndexConfig<I::Params>,
+        hasher: Option<TensoredMultiHash<I::Lsh>>,
+        dataset: &[Trajectory],
+        windows: &Windows,
+    ) -> Self {
+        let (m_windows, slices) = Self::split(dataset, windows);
+        let m_index = I::par_build(config, hasher, &slices);
+        SubtrajectoryIndex {
+            m_index,
+            m_windows,
+            m_metric: I::verification_metric(config),
+        }
+    }
+
+    /// Loads the window index saved with `save`, which was built over the
+    /// windows of `dataset`
+    pub fn load(
+        config: &IndexConfig<I::Params>,
+        hasher: Option<TensoredMultiHash<I::Lsh>>,
+        dataset: &[Trajectory],
+        windows: &Windows,
+        path: &Path,
+    ) -> Result<Self> {
+        let (m_windows, slices) = Self::split(dataset, windows);
+        let m_index = I::load(config, hasher, &slices, path)?;
+        Ok(SubtrajectoryIndex {
+            m_index,
+            m_windows,
+            m_metric: I::verification_metric(config),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.m_index.save(path)
+    }
+
+    /// The windows of `dataset` and their points, which are only needed to
+    /// build the index
+    fn split(dataset: &[Trajectory], windows: &Windows) -> (Vec<Window>, Vec<Trajectory>) {
+        let m_windows: Vec<_> = dataset
+            .iter()
+            .enumerate()
+            .flat_map(|(parent, trajectory)| windows.split(parent, trajectory))
+            .collect();
+        let slices = m_windows
+            .iter()
+            .map(|window| window.slice(dataset))
+            .collect();
+        (m_windows, slices)
+    }
+
+    pub fn windows(&self) -> &[Window] {
+        &self.m_windows
+    }
+
+    pub fn index(&self) -> &I {
+        &self.m_index
+    }
+
+    /// The windows colliding with each query, without verifying them
+    pub fn query(&self, queryset: &[Trajectory]) -> Vec<SubtrajectoryMatch> {
+        let candidates = self.m_index.query(queryset);
+        candidates
+            .into_iter()
+            .map(|pair| self.to_match(pair))
+            .collect()
+    }
+
+    /// The windows within `distance` of each query, checked with the decider
+    /// of the verification metric of the index on the slice of `dataset`, the
+    /// data the index was built over
+    pub fn query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Vec<SubtrajectoryMatch> {
+        let candidates = self.m_index.query(queryset);
+        candidates
+            .into_iter()
+            .filter(|&pair| self.verify(dataset, queryset, pair, distance))
+            .map(|pair| self.to_match(pair))
+            .collect()
+    }
+
+    /// Like `query_with_verification`, with the queries split across the
+    /// threads of the current rayon pool
+    pub fn par_query_with_verification(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        distance: QueryDistance<'_>,
+    ) -> Vec<SubtrajectoryMatch>
+    where
+        I: Sync,
+    {
+        let candidates = self.m_index.par_query(queryset);
+        candidates
+            .into_par_iter()
+            .filter(|&pair| self.verify(dataset, queryset, pair, distance))
+            .map(|pair| self.to_match(pair))
+            .collect()
+    }
+
+    fn verify(
+        &self,
+        dataset: &[Trajectory],
+        queryset: &[Trajectory],
+        (query, window): (usize, usize),
+        distance: QueryDistance<'_>,
+    ) -> bool {
+        let slice = self.m_windows[window].slice(dataset);
+        self.m_metric.decide(&queryset[query], &slice, distance.of(query))
+    }
+
+    fn to_match(&self, (query, window): (usize, usize)) -> SubtrajectoryMatch {
+        let Window { parent, start, end } = self.m_windows[window];
+        SubtrajectoryMatch {
+            query,
+            trajectory: parent,
+            start,
+            end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::FreshConfig;
+    use crate::fresh::Fresh;
+    use crate::lsh::Linear64;
+
+    #[test]
+    fn windows_cover_the_trajectory() {
+        let trajectory = Trajectory::from((0..10).map(|i| [i as f64, 0.0]));
+        let windows = Windows::new(WindowSize::Points(4), 3);
+        let split: Vec<_> = windows
+            .split(7, &trajectory)
+            .map(|window| (window.start, window.end))
+            .collect();
+        assert_eq!(split, vec![(0, 4), (3, 7), (6, 10)]);
+
+        let windows = Windows::new(WindowSize::ArcLength(2.5), 4);
+        let split: Vec<_> = windows
+            .split(7, &trajectory)
+            .map(|window| (window.start, window.end))
+            .collect();
+        assert_eq!(split, vec![(0, 4), (4, 8), (8, 10)]);
+    }
+
+    #[test]
+    fn query_finds_the_matching_slice() {
+        // a long trajectory along the x axis, queried with a piece of it
+        let dataset = vec![
+            Trajectory::from((0..40).map(|i| [i as f64 * 0.5, 0.0])),
+            Trajectory::from((0..40).map(|i| [i as f64 * 0.5, 30.0])),
+        ];
+        let queryset = vec![Trajectory::from(
+            (0..8).map(|i| [10.0 + i as f64 * 0.5, 0.1]),
+        )];
+        let windows = Windows::new(WindowSize::Points(8), 2);
+        let index = SubtrajectoryIndex::<Fresh<Linear64, true>>::build(
+            &IndexConfig::<FreshConfig>::default().resolution(1.0),
+            None,
+            &dataset,
+            &windows,
+        );
+
+        let exact = SubtrajectoryMatch {
+            query: 0,
+            trajectory: 0,
+            start: 20,
+            end: 28,
+        };
+        let matches = index.query_with_verification(&dataset, &queryset, 0.5.into());
+        assert!(matches.contains(&exact));
+        for found in matches {
+            assert_eq!(found.trajectory, 0);
+            let slice = Window {
+                parent: found.trajectory,
+                start: found.start,
+                end: found.end,
+            }
+            .slice(&dataset);
+            assert!(queryset[0].frechet_decider(&slice, 0.5));
+        }
+
+        // the windows are decided with the metric of the index, under which
+        // the offsets of the 8 points add up to 0.8
+        let index = SubtrajectoryIndex::<Fresh<Linear64, true>>::build(
+            &IndexConfig::<FreshConfig>::default()
+                .resolution(1.0)
+                .metric(VerificationMetric::Dtw),
+            None,
+            &dataset,
+            &windows,
+        );
+        assert!(index.query_with_verification(&dataset, &queryset, 0.5.into()).is_empty());
+        let matches = index.query_with_verification(&dataset, &queryset, 1.0.into());
+        assert!(matches.contains(&exact));
+    }
+}
@@ -0,0 +1,29 @@
+//! Random trajectories shared by the tests
+
+use crate::point::Distance;
+use crate::point::PointMatrix;
+use crate::trajectory::Trajectory;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// A walk of `len` points from a random start in [0, 10)², moving at most
+/// `step` along each axis between consecutive points
+pub fn random_walk(len: usize, step: Distance, rng: &mut StdRng) -> Trajectory {
+    let mut point = PointMatrix::new(rng.gen_range(0.0..10.0), rng.gen_range(0.0..10.0));
+    Trajectory::from_iter((0..len).map(|_| {
+        point += PointMatrix::new(rng.gen_range(-step..step), rng.gen_range(-step..step));
+        point
+    }))
+}
+
+/// `n` random walks of `len` points, spread over [0, 10)² and each within
+/// about a unit of its start
+pub fn random_trajectories(n: usize, len: usize, rng: &mut StdRng) -> Vec<Trajectory> {
+    Vec::from_iter((0..n).map(|_| random_walk(len, 0.1, rng)))
+}
+
+/// A curve of 1 to `max_len` points drawn uniformly from [0, extent)²
+pub fn random_curve(max_len: usize, extent: Distance, rng: &mut StdRng) -> Trajectory {
+    let len = rng.gen_range(1..=max_len);
+    Trajectory::from((0..len).map(|_| [rng.gen_range(0.0..extent), rng.gen_range(0.0..extent)]))
+}
@@ -11,17 +11,31 @@ use serde::Deserializer;
 use serde::Serialize;
 use serde::Serializer;
 use std::ops::Index;
-use geo::algorithm::FrechetDistance;
 
 pub struct TrajectoryDataset<'a> {
     ids: Vec<TrajectoryID<'a>>,
     data: Vec<Trajectory>,
+    radii: Option<Vec<Distance>>, // fréchet distance threshold of each trajectory
 }
 
 impl<'a> TrajectoryDataset<'a> {
     pub fn new(data: IndexMap<TrajectoryID<'a>, Trajectory>) -> Self {
         let (ids, data) = data.into_iter().unzip();
-        Self { ids, data }
+        Self {
+            ids,
+            data,
+            radii: None,
+        }
+    }
+
+    /// Attaches a fréchet distance threshold to every trajectory, used
+    /// instead of the configured distance when the dataset is a queryset
+    pub fn with_radii(self, radii: Vec<Distance>) -> Self {
+        assert_eq!(radii.len(), self.data.len());
+        Self {
+            radii: Some(radii),
+            ..self
+        }
     }
 }
 
@@ -38,11 +52,15 @@ impl<'a> TrajectoryDataset<'a> {
     pub fn trajectories(&self) -> &[Trajectory] {
         self.data.as_ref()
     }
+    pub fn radii(&self) -> Option<&[Distance]> {
+        self.radii.as_deref()
+    }
     pub fn take(self, n: Option<usize>) -> Self {
         match n {
             Some(n) if n < self.len() => Self {
                 ids: self.ids.into_iter().take(n).collect(),
                 data: self.data.into_iter().take(n).collect(),
+                radii: self.radii.map(|radii| radii.into_iter().take(n).collect()),
             },
             _ => self,
         }
@@ -124,9 +142,15 @@ impl Trajectory {
         self.0.push(point);
     }
 
+    /// Whether the continuous fréchet distance to `other` is at most `bound`
     pub fn frechet_decider(&self, other: &Self, bound: Distance) -> bool {
-        // crate::frechet::discrete_frechet_distance_predicate(self, other, bound)
-        self.line_string().frechet_distance(&other.line_string()) <= bound
+        crate::frechet::continuous_frechet_decider(self, other, bound)
+    }
+
+    /// The continuous fréchet distance to `other`, the distance
+    /// `frechet_decider` decides
+    pub fn frechet_distance(&self, other: &Self) -> Distance {
+        crate::frechet::continuous_frechet_distance(self, other)
     }
 }
 
@@ -1,4 +1,6 @@
 use crate::id::TrajectoryID;
+use crate::metric::VerificationMetric;
+use crate::result::MapIds;
 use anyhow::Result;
 use serde::Serialize;
 use std::collections::HashMap;
@@ -105,6 +107,7 @@ pub fn run_plot_curves_cmd<'a>(
 }
 
 /// Maps a list of query and candidate indices to a list of query and candidate trajectory IDs.
+/// Besides bare pairs, `QueryResult` records are mapped with their fields kept.
 ///
 /// ```
 /// use master::util::map_to_trajectory_ids;
@@ -115,14 +118,18 @@ pub fn run_plot_curves_cmd<'a>(
 /// let mapped = map_to_trajectory_ids(results, &dataset, &queryset).collect::<Vec<_>>();
 /// assert_eq!(mapped, vec![[queryset[0], dataset[1]], [queryset[1], dataset[0]]]);
 /// ```
-pub fn map_to_trajectory_ids<'a>(
-    results: impl IntoIterator<Item = (usize, usize)> + 'a,
+pub fn map_to_trajectory_ids<'a, R>(
+    results: impl IntoIterator<Item = R> + 'a,
     dataset: &'a [TrajectoryID<'a>],
     queryset: &'a [TrajectoryID<'a>],
-) -> impl Iterator<Item = (TrajectoryID<'a>, TrajectoryID<'a>)> + 'a {
-    results.into_iter().filter_map(|(query, candidate)| {
+) -> impl Iterator<Item = R::Mapped<TrajectoryID<'a>>> + 'a
+where
+    R: MapIds + 'a,
+{
+    results.into_iter().filter_map(|result| {
+        let (query, candidate) = result.pair();
         match (queryset.get(query), dataset.get(candidate)) {
-            (Some(q), Some(c)) => Some((q.clone(), c.clone())),
+            (Some(q), Some(c)) => Some(result.map_ids(q.clone(), c.clone())),
             _ => None,
         }
     })
@@ -197,10 +204,13 @@ pub struct MasterStats<'a, T, C> {
     config: C,
     dataset_load: Option<Duration>,
     index_build_time: Option<Duration>,
+    index_parallel_build_time: Option<Duration>,
     index_query_time: Option<Duration>,
     index_query_size: Option<usize>,
     index_stats: Option<T>,
+    verification_metric: Option<VerificationMetric>,
     candidates: Option<HashMap<String, Vec<String>>>,
+    result_counts: Option<HashMap<String, usize>>,
 }
 
 #[derive(Debug, Serialize, Default)]
@@ -228,10 +238,13 @@ impl<'a, T, C: Clone + Serialize> MasterStats<'a, T, C> {
             mem: HashMap::new(),
             dataset_load: None,
             index_build_time: None,
+            index_parallel_build_time: None,
             index_query_time: None,
             index_query_size: None,
             index_stats: None,
+            verification_metric: None,
             candidates: None,
+            result_counts: None,
         }
     }
 
@@ -247,6 +260,11 @@ impl<'a, T, C: Clone + Serialize> MasterStats<'a, T, C> {
         self.index_build_time = Some(time);
     }
 
+    /// The build time of an index built on the threads of the rayon pool
+    pub fn index_parallel_build_time(&mut self, time: Duration) {
+        self.index_parallel_build_time = Some(time);
+    }
+
     pub fn index_query_time(&mut self, time: Duration) {
         self.index_query_time = Some(time);
     }
@@ -263,6 +281,11 @@ impl<'a, T, C: Clone + Serialize> MasterStats<'a, T, C> {
         self.index_stats.as_mut().unwrap()
     }
 
+    /// The distance the candidates of the verifying queries are decided with
+    pub fn verification_metric(&mut self, metric: VerificationMetric) {
+        self.verification_metric = Some(metric);
+    }
+
     pub fn candidates<I>(&mut self, candidates: I)
     where
         I: IntoIterator<Item = (TrajectoryID<'a>, TrajectoryID<'a>)>,
@@ -276,4 +299,12 @@ impl<'a, T, C: Clone + Serialize> MasterStats<'a, T, C> {
         });
         self.candidates = Some(results);
     }
+
+    /// Records the number of results of `query`, for results that arrive one
+    /// query at a time and are not held in the stats
+    pub fn query_result_count(&mut self, query: TrajectoryID<'a>, count: usize) {
+        self.result_counts
+            .get_or_insert_with(HashMap::new)
+            .insert(query.value().to_string(), count);
+    }
 }